@@ -0,0 +1,423 @@
+//! Backups of all the data of a chat, used by `/backup` and `/restore`.
+//!
+//! A backup is a JSON document that mirrors the database tables of a chat, including
+//! deleted and settled rows, so that restoring it does not lose any history. Rows refer
+//! to each other by the IDs they had when the backup was made: the database gives new
+//! IDs to everything when a backup is restored.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{BackupProblem, InputError},
+    export::ExportedFile,
+    types::Amount,
+};
+
+/// The version of the backup format, to be increased whenever the format changes in a
+/// way that older versions of the bot cannot read, e.g. when tables are added: an older
+/// bot would otherwise restore the backup and silently drop them.
+///
+/// - 1: the first version
+/// - 2: settlement constraints and households
+/// - 3: groups that contain other groups
+pub const BACKUP_VERSION: u32 = 3;
+
+const BACKUP_FILE_NAME: &str = "backup.json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatBackup {
+    pub version: u32,
+    pub participants: Vec<BackupParticipant>,
+    pub aliases: Vec<BackupAlias>,
+    pub groups: Vec<BackupGroup>,
+    pub group_members: Vec<BackupGroupMember>,
+    pub expenses: Vec<BackupExpense>,
+    pub settings: Vec<BackupSetting>,
+    /// Missing in backups made before settlement constraints existed.
+    #[serde(default)]
+    pub settlement_constraints: Vec<BackupSettlementConstraint>,
+    /// Missing in backups made before groups could contain other groups.
+    #[serde(default)]
+    pub group_subgroups: Vec<BackupGroupSubgroup>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupParticipant {
+    pub id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupAlias {
+    pub name: String,
+    pub participant_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupGroup {
+    pub id: i64,
+    pub name: String,
+    /// Missing in backups made before households existed.
+    #[serde(default)]
+    pub is_household: bool,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupGroupMember {
+    pub group_id: i64,
+    pub participant_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupGroupSubgroup {
+    pub group_id: i64,
+    pub subgroup_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupExpense {
+    pub id: i64,
+    pub amount: Amount,
+    pub message: Option<String>,
+    pub message_ts: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub settled_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub participants: Vec<BackupExpenseParticipant>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupExpenseParticipant {
+    pub participant_id: i64,
+    pub is_creditor: bool,
+    pub amount: Option<Amount>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSettlementConstraint {
+    pub kind: String,
+    pub participant_id: i64,
+    pub other_participant_id: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A chat setting, as saved in the database: unknown settings are kept, but they are
+/// ignored when the settings are read.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSetting {
+    pub key: String,
+    pub value: String,
+}
+
+/// Only the version is read first, so that backups written by newer versions of the bot
+/// get a clear error instead of a generic parsing one.
+#[derive(Deserialize)]
+struct BackupVersion {
+    version: u32,
+}
+
+pub fn write_backup(backup: &ChatBackup) -> anyhow::Result<ExportedFile> {
+    Ok(ExportedFile {
+        file_name: BACKUP_FILE_NAME.to_string(),
+        content: serde_json::to_string_pretty(backup)?,
+    })
+}
+
+/// Read a backup, checking that its rows only refer to participants and groups that
+/// are part of the backup, and that no group contains itself.
+pub fn read_backup(content: &str) -> Result<ChatBackup, InputError> {
+    let BackupVersion { version } = serde_json::from_str(content).map_err(unreadable)?;
+    // Older versions only lack some tables, which are then empty.
+    if !(1..=BACKUP_VERSION).contains(&version) {
+        return Err(InputError::invalid_backup(
+            BackupProblem::UnsupportedVersion(version),
+        ));
+    }
+
+    let backup: ChatBackup = serde_json::from_str(content).map_err(unreadable)?;
+
+    let participant_ids: HashSet<_> = backup.participants.iter().map(|p| p.id).collect();
+    let group_ids: HashSet<_> = backup.groups.iter().map(|g| g.id).collect();
+
+    let referenced_participants = backup
+        .aliases
+        .iter()
+        .map(|a| a.participant_id)
+        .chain(backup.group_members.iter().map(|m| m.participant_id))
+        .chain(
+            backup
+                .expenses
+                .iter()
+                .flat_map(|e| e.participants.iter().map(|p| p.participant_id)),
+        )
+        .chain(
+            backup
+                .settlement_constraints
+                .iter()
+                .flat_map(|c| [Some(c.participant_id), c.other_participant_id])
+                .flatten(),
+        );
+    for id in referenced_participants {
+        if !participant_ids.contains(&id) {
+            return Err(InputError::invalid_backup(
+                BackupProblem::UnknownParticipant(id),
+            ));
+        }
+    }
+
+    let referenced_groups = backup.group_members.iter().map(|m| m.group_id).chain(
+        backup
+            .group_subgroups
+            .iter()
+            .flat_map(|s| [s.group_id, s.subgroup_id]),
+    );
+    for id in referenced_groups {
+        if !group_ids.contains(&id) {
+            return Err(InputError::invalid_backup(BackupProblem::UnknownGroup(id)));
+        }
+    }
+
+    if let Some(id) = find_group_cycle(&backup.group_subgroups) {
+        return Err(InputError::invalid_backup(BackupProblem::GroupCycle(id)));
+    }
+
+    Ok(backup)
+}
+
+/// Find a group that contains itself through the subgroups that are not deleted,
+/// which the database does not allow.
+fn find_group_cycle(group_subgroups: &[BackupGroupSubgroup]) -> Option<i64> {
+    let mut subgroups: HashMap<i64, Vec<i64>> = HashMap::new();
+    for s in group_subgroups.iter().filter(|s| s.deleted_at.is_none()) {
+        subgroups.entry(s.group_id).or_default().push(s.subgroup_id);
+    }
+
+    // A group contains itself if it can be reached from one of its subgroups.
+    subgroups.keys().copied().find(|&group_id| {
+        let mut visited = HashSet::new();
+        let mut to_visit = subgroups[&group_id].clone();
+        while let Some(id) = to_visit.pop() {
+            if id == group_id {
+                return true;
+            }
+            if visited.insert(id) {
+                to_visit.extend(subgroups.get(&id).into_iter().flatten());
+            }
+        }
+        false
+    })
+}
+
+fn unreadable(e: serde_json::Error) -> InputError {
+    InputError::invalid_backup(BackupProblem::Unreadable(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).expect("test").into()
+    }
+
+    fn backup() -> ChatBackup {
+        let created_at = timestamp("2023-05-01T10:00:00Z");
+        ChatBackup {
+            version: BACKUP_VERSION,
+            participants: vec![
+                BackupParticipant {
+                    id: 4,
+                    name: "aa".to_string(),
+                    created_at,
+                    deleted_at: None,
+                },
+                BackupParticipant {
+                    id: 9,
+                    name: "bb".to_string(),
+                    created_at,
+                    deleted_at: Some(timestamp("2023-05-02T10:00:00Z")),
+                },
+            ],
+            aliases: vec![BackupAlias {
+                name: "a".to_string(),
+                participant_id: 4,
+                created_at,
+                deleted_at: None,
+            }],
+            groups: vec![
+                BackupGroup {
+                    id: 2,
+                    name: "all".to_string(),
+                    is_household: true,
+                    created_at,
+                    deleted_at: None,
+                },
+                BackupGroup {
+                    id: 6,
+                    name: "everyone".to_string(),
+                    is_household: false,
+                    created_at,
+                    deleted_at: None,
+                },
+            ],
+            group_members: vec![BackupGroupMember {
+                group_id: 2,
+                participant_id: 9,
+                created_at,
+                deleted_at: None,
+            }],
+            expenses: vec![BackupExpense {
+                id: 1,
+                amount: 1000,
+                message: Some("pizza".to_string()),
+                message_ts: created_at,
+                created_at,
+                settled_at: None,
+                deleted_at: None,
+                participants: vec![
+                    BackupExpenseParticipant {
+                        participant_id: 4,
+                        is_creditor: true,
+                        amount: None,
+                    },
+                    BackupExpenseParticipant {
+                        participant_id: 9,
+                        is_creditor: false,
+                        amount: Some(1000),
+                    },
+                ],
+            }],
+            settings: vec![BackupSetting {
+                key: "language".to_string(),
+                value: "it".to_string(),
+            }],
+            settlement_constraints: vec![BackupSettlementConstraint {
+                kind: "only_pays".to_string(),
+                participant_id: 4,
+                other_participant_id: Some(9),
+                created_at,
+                deleted_at: None,
+            }],
+            group_subgroups: vec![BackupGroupSubgroup {
+                group_id: 6,
+                subgroup_id: 2,
+                created_at,
+                deleted_at: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_backup() -> anyhow::Result<()> {
+        let file = write_backup(&backup())?;
+        assert_eq!("backup.json", file.file_name);
+        assert_eq!(backup(), read_backup(&file.content)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_older_backup() -> anyhow::Result<()> {
+        // Backups made before settlement constraints, households and subgroups existed.
+        let mut old = backup();
+        old.version = 1;
+        old.settlement_constraints.clear();
+        old.group_subgroups.clear();
+        old.groups[0].is_household = false;
+        let mut content: serde_json::Value = serde_json::from_str(&write_backup(&old)?.content)?;
+        let object = content.as_object_mut().expect("test");
+        object.remove("settlement_constraints");
+        object.remove("group_subgroups");
+        object["groups"][0]
+            .as_object_mut()
+            .expect("test")
+            .remove("is_household");
+        assert_eq!(old, read_backup(&content.to_string())?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid_backup() -> anyhow::Result<()> {
+        let expect_problem = |content: &str| match read_backup(content) {
+            Err(InputError::InvalidBackup(problem)) => problem,
+            r => panic!("unexpected result: {r:?}"),
+        };
+
+        assert!(matches!(
+            expect_problem("not json"),
+            BackupProblem::Unreadable(_)
+        ));
+
+        let mut newer = backup();
+        newer.version = BACKUP_VERSION + 1;
+        assert_eq!(
+            BackupProblem::UnsupportedVersion(BACKUP_VERSION + 1),
+            expect_problem(&write_backup(&newer)?.content)
+        );
+
+        let mut no_version = backup();
+        no_version.version = 0;
+        assert_eq!(
+            BackupProblem::UnsupportedVersion(0),
+            expect_problem(&write_backup(&no_version)?.content)
+        );
+
+        let mut unknown_participant = backup();
+        unknown_participant.expenses[0].participants[0].participant_id = 5;
+        assert_eq!(
+            BackupProblem::UnknownParticipant(5),
+            expect_problem(&write_backup(&unknown_participant)?.content)
+        );
+
+        let mut unknown_constraint_participant = backup();
+        unknown_constraint_participant.settlement_constraints[0].other_participant_id = Some(7);
+        assert_eq!(
+            BackupProblem::UnknownParticipant(7),
+            expect_problem(&write_backup(&unknown_constraint_participant)?.content)
+        );
+
+        let mut unknown_group = backup();
+        unknown_group.group_members[0].group_id = 3;
+        assert_eq!(
+            BackupProblem::UnknownGroup(3),
+            expect_problem(&write_backup(&unknown_group)?.content)
+        );
+
+        let mut unknown_subgroup = backup();
+        unknown_subgroup.group_subgroups[0].subgroup_id = 8;
+        assert_eq!(
+            BackupProblem::UnknownGroup(8),
+            expect_problem(&write_backup(&unknown_subgroup)?.content)
+        );
+
+        // everyone contains all, which contains everyone.
+        let mut cycle = backup();
+        let mut reversed = cycle.group_subgroups[0].clone();
+        reversed.group_id = 2;
+        reversed.subgroup_id = 6;
+        cycle.group_subgroups.push(reversed);
+        assert!(matches!(
+            expect_problem(&write_backup(&cycle)?.content),
+            BackupProblem::GroupCycle(2 | 6)
+        ));
+
+        // Deleted subgroups do not count.
+        cycle.group_subgroups[1].deleted_at = Some(timestamp("2023-05-02T10:00:00Z"));
+        assert!(read_backup(&write_backup(&cycle)?.content).is_ok());
+
+        Ok(())
+    }
+}
@@ -4,24 +4,37 @@ use std::sync::Arc;
 
 use anyhow::bail;
 use log::{debug, error};
+use serde::{Deserialize, Serialize};
 use teloxide::{
     dispatching::{
-        dialogue::{self, GetChatId, InMemStorage},
+        dialogue::{self, GetChatId},
         UpdateHandler,
     },
+    net::Download,
     prelude::*,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId, ParseMode},
+    types::{
+        BotCommandScope, Document, InlineKeyboardButton, InlineKeyboardMarkup, InputFile,
+        MessageId, ParseMode,
+    },
     utils::command::BotCommands,
 };
 use tokio::sync::Mutex;
 
 use crate::{
-    database::{sqlite::SqliteDatabase, Database},
+    database::{
+        sqlite::{SqliteDatabase, SqliteDialogueStorage},
+        Database,
+    },
     endpoints,
-    error::{DatabaseError, InputError, TelegramError},
+    error::{BackupProblem, CsvProblem, DatabaseError, InputError, TelegramError},
+    formatter::{format_error_message, format_import_report, format_setting, format_settings},
+    help::{find_command, format_help, format_help_page, menu_commands},
+    i18n::Language,
+    parser::{parse_import_options, replace_expense_name},
+    settings::{ChatSettings, Setting, SettingKey},
 };
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub enum State {
     #[default]
     Normal,
@@ -32,19 +45,31 @@ pub enum State {
     rename_rule = "lowercase",
     description = "This bot keeps track of debts and credits in a group. Supported commands:"
 )]
-enum Command {
-    #[command(description = "shows this message.")]
-    Help,
+pub enum Command {
+    #[command(
+        description = "/help [command] shows this message or the detailed help of a command."
+    )]
+    Help(String),
     #[command(
         description = "adds a new expense; format: participant1 34.4 participant2 participant3"
     )]
     Expense(String),
     #[command(description = "shortcut for the /expense command")]
     E(String),
-    #[command(description = "prints the current balance.")]
-    Balance,
+    #[command(
+        description = "prints the current balance, or the balance --at YYYY-MM-DD or --before an expense ID."
+    )]
+    Balance(String),
     #[command(description = "shortcut for the /balance command.")]
-    B,
+    B(String),
+    #[command(description = "shows how much each participant is owed (+) or owes (-).")]
+    Net,
+    #[command(
+        description = "/between p1 p2 shows how much p1 owes p2, add --list to see the expenses."
+    )]
+    Between(String),
+    #[command(description = "/why p1 shows the active expenses that make up the balance of p1.")]
+    Why(String),
     #[command(description = "marks all expenses as settled.")]
     Reset,
     #[command(description = "/list shows all the expenses added since the latest call to reset.")]
@@ -76,6 +101,20 @@ enum Command {
     RemoveParticipants(String),
     #[command(description = "shortcut for the /removeparticipants command")]
     Rp(String),
+    #[command(
+        description = "/renameparticipant old_name new_name [--keep-alias] renames a participant, \
+                       optionally keeping the old name as an alias."
+    )]
+    RenameParticipant(String),
+    #[command(description = "shortcut for the /renameparticipant command")]
+    Rnp(String),
+    #[command(
+        description = "/mergeparticipants keep drop merges two participants that are the same person \
+                       into the first one."
+    )]
+    MergeParticipants(String),
+    #[command(description = "shortcut for the /mergeparticipants command")]
+    Mp(String),
     #[command(
         description = "returns the list of all registered participants (only registered participants can \
                        appear in expenses)."
@@ -102,7 +141,7 @@ enum Command {
     #[command(description = "shortcut for the /listparticipantaliases command")]
     Lpa(String),
     #[command(
-        description = "/addgroup group_name member1 member2 creates a group with two members."
+        description = "/addgroup group_name [--household] creates a group, which may be a household."
     )]
     AddGroup(String),
     #[command(description = "shortcut for the /addgroup command")]
@@ -111,6 +150,10 @@ enum Command {
     RemoveGroup(String),
     #[command(description = "shortcut for the /removegroup command")]
     Rg(String),
+    #[command(description = "/renamegroup old_name new_name renames a group.")]
+    RenameGroup(String),
+    #[command(description = "shortcut for the /renamegroup command")]
+    Rng(String),
     #[command(
         description = "/addgroupmembers group_name member1 member2 adds two members to a group if not already present."
     )]
@@ -131,6 +174,22 @@ enum Command {
     ListGroupMembers(String),
     #[command(description = "shortcut for the /listgroupmembers command")]
     Lgm(String),
+    #[command(
+        description = "/addconstraint only p1 p2, forbid p1 p2 or noroute p1 adds a constraint on how debts are settled."
+    )]
+    AddConstraint(String),
+    #[command(description = "shortcut for the /addconstraint command")]
+    Ac(String),
+    #[command(
+        description = "/removeconstraint only p1 p2, forbid p1 p2 or noroute p1 removes a constraint if present."
+    )]
+    RemoveConstraint(String),
+    #[command(description = "shortcut for the /removeconstraint command")]
+    Rc(String),
+    #[command(description = "returns the list of all constraints on how debts are settled.")]
+    ListConstraints,
+    #[command(description = "shortcut for the /listconstraints command")]
+    Lc,
     #[command(
         description = "toggle the auto register mode: when active the participants in an expense are \
                        automatically registered as participants if they are not already"
@@ -138,6 +197,38 @@ enum Command {
     ToggleAutoRegister,
     #[command(description = "return whether auto register mode is active")]
     IsAutoRegister,
+    #[command(
+        description = "/language [code] shows the language of the chat or changes it (e.g. /language it)."
+    )]
+    Language(String),
+    #[command(description = "shows the settings of the chat and lets you change them.")]
+    Settings,
+    #[command(
+        description = "/timezone [name] shows the timezone of the chat or changes it (e.g. /timezone Europe/Rome)."
+    )]
+    Timezone(String),
+    #[command(
+        description = "/export [active|all] [csv|ledger|beancount] [currency] sends a file with the active (default) or all expenses."
+    )]
+    Export(String),
+    #[command(
+        description = "/import [csv|splitwise] [dry-run], in reply to a CSV document, imports its expenses."
+    )]
+    Import(String),
+    #[command(
+        description = "/report [active|all] sends an HTML report with expenses, totals and settlement."
+    )]
+    Report(String),
+    #[command(
+        description = "/chart [people|categories|time] [active|all] draws charts of the expenses."
+    )]
+    Chart(String),
+    #[command(description = "/backup sends a file with all the data of the chat.")]
+    Backup,
+    #[command(
+        description = "/restore, in reply to a backup file, restores it in this chat, which must be new."
+    )]
+    Restore,
 }
 
 type HandlerResult = anyhow::Result<()>;
@@ -148,9 +239,17 @@ type HandlerResult = anyhow::Result<()>;
 // handling.
 type DatabaseInUse = Arc<Mutex<SqliteDatabase>>;
 
-const DEFAULT_LIMIT: usize = 15;
+/// Larger files are not downloaded by `/import`.
+const MAX_IMPORT_FILE_KB: u32 = 1024;
+/// Larger files are not downloaded by `/restore`.
+const MAX_BACKUP_FILE_KB: u32 = 10 * 1024;
 const LIST_CALLBACK_PREFIX: &str = "list";
 const LIST_ALL_CALLBACK_PREFIX: &str = "list-all";
+const SETTINGS_CALLBACK_PREFIX: &str = "settings";
+const FIX_CALLBACK_PREFIX: &str = "fix";
+const CONFIRM_CALLBACK_PREFIX: &str = "confirm";
+/// Telegram does not accept longer callback data.
+const MAX_CALLBACK_DATA_BYTES: usize = 64;
 
 pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sync + 'static>> {
     use dptree::case;
@@ -159,10 +258,16 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
         teloxide::filter_command::<Command, _>().branch(case![State::Normal].endpoint(
             |msg: Message, bot: Bot, cmd: Command, database: DatabaseInUse| async move {
                 use Command::*;
+                let is_expense = matches!(cmd, Expense(_) | E(_));
                 let result = match cmd {
-                    Help => handle_help(&bot, &msg).await,
-                    Expense(e) | E(e) => handle_expense(&msg, &database, &e).await,
-                    Balance | B => handle_balance(&bot, &msg, &database).await,
+                    Help(command) => handle_help(&bot, &msg, &database, &command).await,
+                    Expense(e) | E(e) => handle_expense(&bot, &msg, &database, &e).await,
+                    Balance(options) | B(options) => {
+                        handle_balance(&bot, &msg, &database, &options).await
+                    }
+                    Net => handle_net(&bot, &msg, &database).await,
+                    Between(s) => handle_between(&bot, &msg, &database, &s).await,
+                    Why(participant) => handle_why(&bot, &msg, &database, &participant).await,
                     Reset => handle_reset(&msg, &database).await,
                     List | L => handle_list(&bot, &msg, &database, true).await,
                     ListAll | La => handle_list(&bot, &msg, &database, false).await,
@@ -173,6 +278,12 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
                     RemoveParticipants(s) | Rp(s) => {
                         handle_remove_participants(&msg, &database, &s).await
                     }
+                    RenameParticipant(s) | Rnp(s) => {
+                        handle_rename_participant(&msg, &database, &s).await
+                    }
+                    MergeParticipants(s) | Mp(s) => {
+                        handle_merge_participants(&msg, &database, &s).await
+                    }
                     ListParticipants | Lp => handle_list_participants(&bot, &msg, &database).await,
                     AddParticipantAliases(s) | Apa(s) => {
                         handle_add_participant_aliases(&msg, &database, &s).await
@@ -189,6 +300,7 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
                     RemoveGroup(group_name) | Rg(group_name) => {
                         handle_remove_group(&msg, &database, &group_name).await
                     }
+                    RenameGroup(s) | Rng(s) => handle_rename_group(&msg, &database, &s).await,
                     AddGroupMembers(s) | Agm(s) => {
                         handle_add_group_members(&msg, &database, &s).await
                     }
@@ -199,8 +311,26 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
                     ListGroupMembers(group_name) | Lgm(group_name) => {
                         handle_list_group_members(&bot, &msg, &database, &group_name).await
                     }
+                    AddConstraint(s) | Ac(s) => {
+                        handle_add_settlement_constraint(&msg, &database, &s).await
+                    }
+                    RemoveConstraint(s) | Rc(s) => {
+                        handle_remove_settlement_constraint(&msg, &database, &s).await
+                    }
+                    ListConstraints | Lc => {
+                        handle_list_settlement_constraints(&bot, &msg, &database).await
+                    }
                     ToggleAutoRegister => handle_toggle_auto_register(&bot, &msg, &database).await,
                     IsAutoRegister => handle_is_auto_register(&bot, &msg, &database).await,
+                    Language(code) => handle_language(&bot, &msg, &database, &code).await,
+                    Settings => handle_settings(&bot, &msg, &database).await,
+                    Timezone(name) => handle_timezone(&bot, &msg, &database, &name).await,
+                    Export(options) => handle_export(&bot, &msg, &database, &options).await,
+                    Import(options) => handle_import(&bot, &msg, &database, &options).await,
+                    Report(options) => handle_report(&bot, &msg, &database, &options).await,
+                    Chart(options) => handle_chart(&bot, &msg, &database, &options).await,
+                    Backup => handle_backup(&bot, &msg, &database).await,
+                    Restore => handle_restore(&bot, &msg, &database).await,
                 };
 
                 // We are basically bypassing teloxide error handler and managing errors here.
@@ -219,7 +349,22 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
                     } else {
                         error!("Error in chat {}: {:#?}", msg.chat.id.0, e);
                     }
-                    if let Err(e) = bot.send_message(msg.chat.id, format!("{e}")).await {
+                    // If even the language cannot be read, we still want to tell the user something.
+                    let language = database
+                        .lock()
+                        .await
+                        .get_settings(msg.chat.id.0)
+                        .map(|s| s.language)
+                        .unwrap_or_default();
+                    let mut request = bot
+                        .send_message(msg.chat.id, format_error_message(&e, language))
+                        .parse_mode(ParseMode::MarkdownV2);
+                    // Names that are not registered are often typos, which can be fixed with a tap.
+                    if let Some(keyboard) = is_expense.then(|| make_fix_keyboard(&e, &[])).flatten()
+                    {
+                        request = request.reply_to_message_id(msg.id).reply_markup(keyboard);
+                    }
+                    if let Err(e) = request.await {
                         error!(
                             "Cannot send error message in chat {}: {:#?}",
                             msg.chat.id.0, e
@@ -242,10 +387,15 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
 
                 match (chat_id, message, callback_data) {
                     (Some(chat_id), Some(message), Some(callback_data)) => {
-                        let message_id = message.id;
-                        let result =
-                            dispatch_callback(chat_id, message_id, &bot, &database, callback_data)
-                                .await;
+                        let result = dispatch_callback(
+                            chat_id,
+                            &q.id,
+                            &message,
+                            &bot,
+                            &database,
+                            callback_data,
+                        )
+                        .await;
                         if result.is_err() {
                             debug!("Cannot dispatch callback: {:#?}", result);
                         }
@@ -258,24 +408,103 @@ pub fn dialogue_handler() -> UpdateHandler<Box<dyn std::error::Error + Send + Sy
             },
         ));
 
-    dialogue::enter::<Update, InMemStorage<State>, State, _>()
+    dialogue::enter::<Update, SqliteDialogueStorage, State, _>()
         .branch(message_handler)
         .branch(callback_query_handler)
 }
 
-async fn handle_help(bot: &Bot, msg: &Message) -> HandlerResult {
-    bot.send_message(msg.chat.id, Command::descriptions().to_string())
+/// Register the command menu with Telegram.
+///
+/// Group chats and private chats get different menus (see [`menu_commands`]).
+///
+/// The menu is in the default language, except for users whose Telegram app is in
+/// another language supported by the bot.
+pub async fn set_my_commands(bot: &Bot) -> HandlerResult {
+    for language in Language::ALL {
+        let language_code = (language != Language::default()).then(|| language.code());
+
+        let mut request = bot
+            .set_my_commands(menu_commands(false, language))
+            .scope(BotCommandScope::AllGroupChats);
+        request.language_code = language_code.map(|c| c.to_string());
+        request
+            .await
+            .map_err(|e| TelegramError::new("cannot set group chat commands", e))?;
+
+        let mut request = bot
+            .set_my_commands(menu_commands(true, language))
+            .scope(BotCommandScope::AllPrivateChats);
+        request.language_code = language_code.map(|c| c.to_string());
+        request
+            .await
+            .map_err(|e| TelegramError::new("cannot set private chat commands", e))?;
+    }
+    Ok(())
+}
+
+async fn handle_help<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    command: &str,
+) -> HandlerResult {
+    let language = database.lock().await.get_settings(msg.chat.id.0)?.language;
+    let help = if command.trim().is_empty() {
+        format_help(language)
+    } else {
+        let command_help = find_command(command)
+            .ok_or_else(|| InputError::unknown_command(command.trim().to_string()))?;
+        format_help_page(command_help, language)
+    };
+
+    bot.send_message(msg.chat.id, help)
+        .parse_mode(ParseMode::MarkdownV2)
         .await
         .map_err(|e| TelegramError::new("cannot send help", e))?;
     Ok(())
 }
 
 async fn handle_expense<D: Database>(
+    bot: &Bot,
     msg: &Message,
     database: &Arc<Mutex<D>>,
     message: &str,
 ) -> HandlerResult {
     let chat_id = msg.chat.id.0;
+
+    // Expenses written as sentences are saved only after the user confirms them.
+    if let Some(expense) = endpoints::interpret_expense(chat_id, message, database).await? {
+        let catalog = database
+            .lock()
+            .await
+            .get_settings(chat_id)?
+            .language
+            .catalog();
+        let buttons = [
+            InlineKeyboardButton::callback(
+                catalog.save_expense(),
+                format!("{} save", CONFIRM_CALLBACK_PREFIX),
+            ),
+            InlineKeyboardButton::callback(
+                catalog.cancel_expense(),
+                format!("{} cancel", CONFIRM_CALLBACK_PREFIX),
+            ),
+        ];
+        let confirmation = bot
+            .send_message(msg.chat.id, catalog.confirm_expense(&expense))
+            .reply_to_message_id(msg.id)
+            .reply_markup(InlineKeyboardMarkup::new([buttons]))
+            .await
+            .map_err(|e| TelegramError::new("cannot send expense confirmation", e))?;
+        database.lock().await.add_pending_expense(
+            chat_id,
+            confirmation.id.0,
+            &expense,
+            msg.date,
+        )?;
+        return Ok(());
+    }
+
     let message_ts = msg.date;
     endpoints::handle_expense(chat_id, message, database, message_ts).await?;
     Ok(())
@@ -285,9 +514,10 @@ async fn handle_balance<D: Database>(
     bot: &Bot,
     msg: &Message,
     database: &Arc<Mutex<D>>,
+    options: &str,
 ) -> HandlerResult {
     let chat_id = msg.chat.id.0;
-    let formatted_balance = endpoints::handle_balance(chat_id, database).await?;
+    let formatted_balance = endpoints::handle_balance(chat_id, database, options).await?;
     bot.send_message(msg.chat.id, formatted_balance)
         .parse_mode(ParseMode::MarkdownV2)
         .await
@@ -295,6 +525,50 @@ async fn handle_balance<D: Database>(
     Ok(())
 }
 
+async fn handle_net<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let positions = endpoints::handle_net(chat_id, database).await?;
+    bot.send_message(msg.chat.id, positions)
+        .parse_mode(ParseMode::MarkdownV2)
+        .await
+        .map_err(|e| TelegramError::new("cannot send net positions", e))?;
+    Ok(())
+}
+
+async fn handle_between<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let debt = endpoints::handle_between(chat_id, database, payload).await?;
+    bot.send_message(msg.chat.id, debt)
+        .parse_mode(ParseMode::MarkdownV2)
+        .await
+        .map_err(|e| TelegramError::new("cannot send debt between participants", e))?;
+    Ok(())
+}
+
+async fn handle_why<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    participant: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let explanation = endpoints::handle_why(chat_id, database, participant).await?;
+    bot.send_message(msg.chat.id, explanation)
+        .parse_mode(ParseMode::MarkdownV2)
+        .await
+        .map_err(|e| TelegramError::new("cannot send explanation of balance", e))?;
+    Ok(())
+}
+
 async fn handle_reset<D: Database>(msg: &Message, database: &Arc<Mutex<D>>) -> HandlerResult {
     let chat_id = msg.chat.id.0;
     database.lock().await.mark_all_as_settled(chat_id)?;
@@ -308,13 +582,16 @@ async fn handle_list<D: Database>(
     only_active: bool,
 ) -> HandlerResult {
     let chat_id = msg.chat.id.0;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let page_size = settings.page_size;
     let (result, there_are_more) =
-        endpoints::handle_list(chat_id, database, 0, DEFAULT_LIMIT, only_active).await?;
+        endpoints::handle_list(chat_id, database, 0, page_size, only_active).await?;
+    let catalog = settings.language.catalog();
 
     let buttons = if there_are_more {
         vec![InlineKeyboardButton::callback(
-            "Next",
-            make_list_callback_data(DEFAULT_LIMIT, only_active),
+            catalog.next_page(),
+            make_list_callback_data(page_size, only_active),
         )]
     } else {
         vec![]
@@ -329,13 +606,144 @@ async fn handle_list<D: Database>(
     Ok(())
 }
 
+async fn handle_export<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    options: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let file = endpoints::handle_export(chat_id, database, options).await?;
+    let document = InputFile::memory(file.content.into_bytes()).file_name(file.file_name);
+    bot.send_document(msg.chat.id, document)
+        .await
+        .map_err(|e| TelegramError::new("cannot send exported file", e))?;
+    Ok(())
+}
+
+async fn handle_import<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    options: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let options = parse_import_options(options)?;
+
+    let document = msg
+        .reply_to_message()
+        .and_then(|m| m.document())
+        .ok_or_else(InputError::import_without_document)?;
+    if document.file.size > MAX_IMPORT_FILE_KB * 1024 {
+        return Err(InputError::invalid_csv(CsvProblem::TooLarge(MAX_IMPORT_FILE_KB)).into());
+    }
+
+    let content = download_document(bot, document).await?;
+    let content = String::from_utf8(content)
+        .map_err(|_| InputError::invalid_csv(CsvProblem::Unreadable("not UTF-8".to_string())))?;
+
+    let report = endpoints::handle_import(chat_id, database, &content, options).await?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    bot.send_message(msg.chat.id, format_import_report(&report, &settings))
+        .await
+        .map_err(|e| TelegramError::new("cannot send import report", e))?;
+    Ok(())
+}
+
+async fn handle_report<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    options: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let file = endpoints::handle_report(chat_id, database, options).await?;
+    let document = InputFile::memory(file.content.into_bytes()).file_name(file.file_name);
+    bot.send_document(msg.chat.id, document)
+        .await
+        .map_err(|e| TelegramError::new("cannot send report", e))?;
+    Ok(())
+}
+
+async fn handle_chart<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    options: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let charts = endpoints::handle_chart(chat_id, database, options).await?;
+    for chart in charts {
+        let photo = InputFile::memory(chart).file_name("chart.png");
+        bot.send_photo(msg.chat.id, photo)
+            .await
+            .map_err(|e| TelegramError::new("cannot send chart", e))?;
+    }
+    Ok(())
+}
+
+async fn handle_backup<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let file = endpoints::handle_backup(chat_id, database).await?;
+    let document = InputFile::memory(file.content.into_bytes()).file_name(file.file_name);
+    bot.send_document(msg.chat.id, document)
+        .await
+        .map_err(|e| TelegramError::new("cannot send backup file", e))?;
+    Ok(())
+}
+
+async fn handle_restore<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+
+    let document = msg
+        .reply_to_message()
+        .and_then(|m| m.document())
+        .ok_or_else(InputError::restore_without_document)?;
+    if document.file.size > MAX_BACKUP_FILE_KB * 1024 {
+        return Err(InputError::invalid_backup(BackupProblem::TooLarge(MAX_BACKUP_FILE_KB)).into());
+    }
+
+    let content = download_document(bot, document).await?;
+    let content = String::from_utf8(content).map_err(|_| {
+        InputError::invalid_backup(BackupProblem::Unreadable("not UTF-8".to_string()))
+    })?;
+
+    let reply = endpoints::handle_restore(chat_id, database, &content).await?;
+    bot.send_message(msg.chat.id, reply)
+        .await
+        .map_err(|e| TelegramError::new("cannot send restore reply", e))?;
+    Ok(())
+}
+
+async fn download_document(bot: &Bot, document: &Document) -> Result<Vec<u8>, TelegramError> {
+    let file = bot
+        .get_file(&document.file.id)
+        .await
+        .map_err(|e| TelegramError::new("cannot get file", e))?;
+    let mut content = vec![];
+    bot.download_file(&file.path, &mut content)
+        .await
+        .map_err(|e| TelegramError::new("cannot download file", e.into()))?;
+    Ok(content)
+}
+
 async fn dispatch_callback<D: Database>(
     chat_id: ChatId,
-    message_id: MessageId,
+    query_id: &str,
+    message: &Message,
     bot: &Bot,
     database: &Arc<Mutex<D>>,
     callback_data: String,
 ) -> HandlerResult {
+    let message_id = message.id;
     let parsed_callback_data = callback_data.split_once(" ");
     match parsed_callback_data {
         Some((LIST_CALLBACK_PREFIX, start)) => {
@@ -344,6 +752,15 @@ async fn dispatch_callback<D: Database>(
         Some((LIST_ALL_CALLBACK_PREFIX, start)) => {
             handle_list_callback(chat_id, message_id, bot, database, start, false).await
         }
+        Some((SETTINGS_CALLBACK_PREFIX, key)) => {
+            handle_settings_callback(chat_id, message_id, bot, database, key).await
+        }
+        Some((CONFIRM_CALLBACK_PREFIX, choice)) => {
+            handle_confirm_callback(chat_id, query_id, message, bot, database, choice).await
+        }
+        Some((FIX_CALLBACK_PREFIX, replacements)) => {
+            handle_fix_callback(chat_id, message, bot, database, replacements).await
+        }
         Some((prefix, _)) => bail!("Unknown callback data prefix: {}", prefix),
         None => bail!("Invalid callback data: {}", callback_data),
     }
@@ -360,27 +777,32 @@ async fn handle_list_callback<D: Database>(
     let start = start.trim();
     let start = start.parse()?;
 
+    let settings = database.lock().await.get_settings(chat_id.0)?;
+    let page_size = settings.page_size;
     let (result, there_are_more) =
-        endpoints::handle_list(chat_id.0, database, start, DEFAULT_LIMIT, only_active).await?;
+        endpoints::handle_list(chat_id.0, database, start, page_size, only_active).await?;
+    let catalog = settings.language.catalog();
 
     let mut buttons = vec![];
     if start > 0 {
-        if start <= DEFAULT_LIMIT {
-            let button =
-                InlineKeyboardButton::callback("Previous", make_list_callback_data(0, only_active));
+        if start <= page_size {
+            let button = InlineKeyboardButton::callback(
+                catalog.previous_page(),
+                make_list_callback_data(0, only_active),
+            );
             buttons.push(button);
         } else {
             let button = InlineKeyboardButton::callback(
-                "Previous",
-                make_list_callback_data(start - DEFAULT_LIMIT, only_active),
+                catalog.previous_page(),
+                make_list_callback_data(start - page_size, only_active),
             );
             buttons.push(button);
         }
     }
     if there_are_more {
         let button = InlineKeyboardButton::callback(
-            "Next",
-            make_list_callback_data(start + DEFAULT_LIMIT, only_active),
+            catalog.next_page(),
+            make_list_callback_data(start + page_size, only_active),
         );
         buttons.push(button);
     }
@@ -400,6 +822,173 @@ fn make_list_callback_data(start: usize, only_active: bool) -> String {
     }
 }
 
+/// Resubmit the expense that the error message replies to, replacing the names
+/// that were not registered with the ones in the callback data.
+async fn handle_fix_callback<D: Database>(
+    chat_id: ChatId,
+    message: &Message,
+    bot: &Bot,
+    database: &Arc<Mutex<D>>,
+    replacements: &str,
+) -> HandlerResult {
+    let replacements: Vec<_> = replacements.split_whitespace().collect();
+    if replacements.len() % 2 != 0 {
+        bail!("Invalid replacements: {:?}", replacements);
+    }
+    let replacements: Vec<_> = replacements
+        .chunks(2)
+        .map(|r| (r[0].to_string(), r[1].to_string()))
+        .collect();
+
+    let (original, expense) = get_replied_expense(message)?;
+    // Names are replaced in the usual syntax, also when the expense was written as a sentence.
+    let mut expense = endpoints::interpret_expense(chat_id.0, &expense, database)
+        .await?
+        .unwrap_or(expense);
+    for (name, replacement) in &replacements {
+        expense = replace_expense_name(&expense, name, replacement);
+    }
+
+    let result = endpoints::handle_expense(chat_id.0, &expense, database, original.date).await;
+    let language = database.lock().await.get_settings(chat_id.0)?.language;
+    match result {
+        Ok(()) => {
+            // Without a keyboard, the buttons are removed.
+            bot.edit_message_text(
+                chat_id,
+                message.id,
+                language.catalog().expense_corrected(&expense),
+            )
+            .await?;
+        }
+        Err(e) => {
+            let request = bot
+                .edit_message_text(chat_id, message.id, format_error_message(&e, language))
+                .parse_mode(ParseMode::MarkdownV2);
+            match make_fix_keyboard(&e, &replacements) {
+                Some(keyboard) => request.reply_markup(keyboard).await?,
+                None => request.await?,
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Save or discard the expense written as a sentence that waits for confirmation
+/// with the given message.
+///
+/// The expense is taken from the database before it is saved, so that it is saved
+/// only once even if the button is pressed several times.
+async fn handle_confirm_callback<D: Database>(
+    chat_id: ChatId,
+    query_id: &str,
+    message: &Message,
+    bot: &Bot,
+    database: &Arc<Mutex<D>>,
+    choice: &str,
+) -> HandlerResult {
+    let (language, pending) = {
+        let mut database = database.lock().await;
+        let language = database.get_settings(chat_id.0)?.language;
+        (
+            language,
+            database.take_pending_expense(chat_id.0, message.id.0)?,
+        )
+    };
+    let catalog = language.catalog();
+
+    let (expense, message_ts) = match pending {
+        Some(pending) => pending,
+        None => {
+            bot.answer_callback_query(query_id)
+                .text(catalog.expense_already_confirmed())
+                .await?;
+            return Ok(());
+        }
+    };
+    // Without a keyboard, the buttons are removed.
+    bot.edit_message_reply_markup(chat_id, message.id).await?;
+
+    match choice.trim() {
+        "save" => {
+            let result = endpoints::handle_expense(chat_id.0, &expense, database, message_ts).await;
+            match result {
+                Ok(()) => {
+                    bot.edit_message_text(chat_id, message.id, catalog.expense_saved(&expense))
+                        .await?;
+                }
+                Err(e) => {
+                    let request = bot
+                        .edit_message_text(chat_id, message.id, format_error_message(&e, language))
+                        .parse_mode(ParseMode::MarkdownV2);
+                    match make_fix_keyboard(&e, &[]) {
+                        Some(keyboard) => request.reply_markup(keyboard).await?,
+                        None => request.await?,
+                    };
+                }
+            }
+        }
+        "cancel" => {
+            bot.edit_message_text(chat_id, message.id, catalog.expense_not_saved())
+                .await?;
+        }
+        _ => bail!("Unknown choice: {}", choice),
+    }
+    Ok(())
+}
+
+/// The expense, without the command, of the message that the given message replies to.
+fn get_replied_expense(message: &Message) -> anyhow::Result<(&Message, String)> {
+    let original = match message.reply_to_message() {
+        Some(original) => original,
+        None => bail!("The message does not reply to an expense"),
+    };
+    let text = match original.text() {
+        Some(text) => text,
+        None => bail!("The expense has no text"),
+    };
+    // Skip the command, e.g. `/e` or `/expense@bot_name`.
+    match text.split_once(char::is_whitespace) {
+        Some((_, expense)) => Ok((original, expense.trim_start().to_string())),
+        None => bail!("The expense is empty"),
+    }
+}
+
+/// One button for each registered name suggested by an error about a participant
+/// or a group that is not registered. The callback data contains all replacements
+/// to make in the expense, including the ones that were made before.
+fn make_fix_keyboard(
+    error: &anyhow::Error,
+    replacements: &[(String, String)],
+) -> Option<InlineKeyboardMarkup> {
+    let (name, suggestions, prefix) = match error.downcast_ref::<InputError>()? {
+        InputError::UnregisteredParticipant(name, suggestions) => (name, suggestions, ""),
+        InputError::UnregisteredGroup(name, suggestions) => (name, suggestions, "#"),
+        _ => return None,
+    };
+
+    let buttons: Vec<_> = suggestions
+        .iter()
+        .filter_map(|suggestion| {
+            let mut callback_data = FIX_CALLBACK_PREFIX.to_string();
+            for (name, replacement) in replacements {
+                callback_data += &format!(" {name} {replacement}");
+            }
+            callback_data += &format!(" {prefix}{name} {prefix}{suggestion}");
+
+            (callback_data.len() <= MAX_CALLBACK_DATA_BYTES).then(|| {
+                InlineKeyboardButton::callback(format!("✏️ {prefix}{suggestion}"), callback_data)
+            })
+        })
+        .collect();
+
+    if buttons.is_empty() {
+        None
+    } else {
+        Some(InlineKeyboardMarkup::new([buttons]))
+    }
+}
+
 async fn handle_delete<D: Database>(
     msg: &Message,
     database: &Arc<Mutex<D>>,
@@ -430,6 +1019,26 @@ async fn handle_remove_participants<D: Database>(
     Ok(())
 }
 
+async fn handle_rename_participant<D: Database>(
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    endpoints::handle_rename_participant(chat_id, database, payload).await?;
+    Ok(())
+}
+
+async fn handle_merge_participants<D: Database>(
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    endpoints::handle_merge_participants(chat_id, database, payload).await?;
+    Ok(())
+}
+
 async fn handle_list_participants<D: Database>(
     bot: &Bot,
     msg: &Message,
@@ -498,6 +1107,16 @@ async fn handle_remove_group<D: Database>(
     Ok(())
 }
 
+async fn handle_rename_group<D: Database>(
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    endpoints::handle_rename_group(chat_id, database, payload).await?;
+    Ok(())
+}
+
 async fn handle_add_group_members<D: Database>(
     msg: &Message,
     database: &Arc<Mutex<D>>,
@@ -518,6 +1137,39 @@ async fn handle_remove_group_members<D: Database>(
     Ok(())
 }
 
+async fn handle_add_settlement_constraint<D: Database>(
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    endpoints::handle_add_settlement_constraint(chat_id, database, payload).await?;
+    Ok(())
+}
+
+async fn handle_remove_settlement_constraint<D: Database>(
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    endpoints::handle_remove_settlement_constraint(chat_id, database, payload).await?;
+    Ok(())
+}
+
+async fn handle_list_settlement_constraints<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let result = endpoints::handle_list_settlement_constraints(chat_id, database).await?;
+    bot.send_message(msg.chat.id, result)
+        .await
+        .map_err(|e| TelegramError::new("cannot send settlement constraint list", e))?;
+    Ok(())
+}
+
 async fn handle_list_groups<D: Database>(
     bot: &Bot,
     msg: &Message,
@@ -553,21 +1205,22 @@ async fn handle_toggle_auto_register<D: Database>(
     let chat_id = msg.chat.id.0;
     debug!("Toggling auto register");
 
-    let auto_register = database.lock().await.toggle_auto_register(chat_id)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let auto_register = !settings.auto_register;
+    database
+        .lock()
+        .await
+        .set_setting(chat_id, Setting::AutoRegister(auto_register))?;
+    let catalog = settings.language.catalog();
 
-    if auto_register {
-        let message = "The 'auto register' mode is ENABLED: participants used in expenses will \
-                       be automatically registered as participants if they are not already.";
-        bot.send_message(msg.chat.id, message)
-            .await
-            .map_err(|e| TelegramError::new("cannot send toggle auto register message", e))?;
+    let message = if auto_register {
+        catalog.auto_register_enabled()
     } else {
-        let message = "The 'auto register' mode is DISABLED: using unregistered participants in \
-                       expenses will result in an error.";
-        bot.send_message(msg.chat.id, message)
-            .await
-            .map_err(|e| TelegramError::new("cannot send toggle auto register message", e))?;
-    }
+        catalog.auto_register_disabled()
+    };
+    bot.send_message(msg.chat.id, message)
+        .await
+        .map_err(|e| TelegramError::new("cannot send toggle auto register message", e))?;
 
     Ok(())
 }
@@ -580,19 +1233,95 @@ async fn handle_is_auto_register<D: Database>(
     let chat_id = msg.chat.id.0;
     debug!("Checking auto register mode");
 
-    let auto_register = database.lock().await.is_auto_register_active(chat_id)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let catalog = settings.language.catalog();
 
-    if auto_register {
-        let message = "The 'auto register' mode is ENABLED.";
-        bot.send_message(msg.chat.id, message)
-            .await
-            .map_err(|e| TelegramError::new("cannot send is auto register message", e))?;
+    let message = if settings.auto_register {
+        catalog.auto_register_is_enabled()
     } else {
-        let message = "The 'auto register' mode is DISABLED.";
-        bot.send_message(msg.chat.id, message)
-            .await
-            .map_err(|e| TelegramError::new("cannot send is auto register message", e))?;
-    }
+        catalog.auto_register_is_disabled()
+    };
+    bot.send_message(msg.chat.id, message)
+        .await
+        .map_err(|e| TelegramError::new("cannot send is auto register message", e))?;
+
+    Ok(())
+}
+
+async fn handle_language<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let message = endpoints::handle_language(chat_id, database, payload).await?;
+    bot.send_message(msg.chat.id, message)
+        .await
+        .map_err(|e| TelegramError::new("cannot send language message", e))?;
+    Ok(())
+}
 
+async fn handle_timezone<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let message = endpoints::handle_timezone(chat_id, database, payload).await?;
+    bot.send_message(msg.chat.id, message)
+        .await
+        .map_err(|e| TelegramError::new("cannot send timezone message", e))?;
     Ok(())
 }
+
+async fn handle_settings<D: Database>(
+    bot: &Bot,
+    msg: &Message,
+    database: &Arc<Mutex<D>>,
+) -> HandlerResult {
+    let chat_id = msg.chat.id.0;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    bot.send_message(msg.chat.id, format_settings(&settings))
+        .reply_markup(make_settings_keyboard(&settings))
+        .await
+        .map_err(|e| TelegramError::new("cannot send settings", e))?;
+    Ok(())
+}
+
+/// Change the setting that was tapped to its next value and update the message.
+async fn handle_settings_callback<D: Database>(
+    chat_id: ChatId,
+    message_id: MessageId,
+    bot: &Bot,
+    database: &Arc<Mutex<D>>,
+    key: &str,
+) -> HandlerResult {
+    let key = match SettingKey::parse(key.trim()) {
+        Some(key) => key,
+        None => bail!("Unknown setting: {}", key),
+    };
+
+    let mut settings = database.lock().await.get_settings(chat_id.0)?;
+    let setting = settings.next(key);
+    debug!("Changing setting: {:?}", setting);
+    database.lock().await.set_setting(chat_id.0, setting)?;
+    settings.set(setting);
+
+    bot.edit_message_text(chat_id, message_id, format_settings(&settings))
+        .reply_markup(make_settings_keyboard(&settings))
+        .await?;
+    Ok(())
+}
+
+/// One button per setting, each on its own row.
+fn make_settings_keyboard(settings: &ChatSettings) -> InlineKeyboardMarkup {
+    let buttons = SettingKey::ALL.iter().filter(|k| k.has_button()).map(|k| {
+        vec![InlineKeyboardButton::callback(
+            format_setting(settings, *k),
+            format!("{} {}", SETTINGS_CALLBACK_PREFIX, k.as_str()),
+        )]
+    });
+    InlineKeyboardMarkup::new(buttons)
+}
@@ -1,13 +1,16 @@
 //! The core of the bot logic. It contains the algorithm that computes
 //! the money exchanges needed to settle debts.
 
-use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
 use log::Level::Debug;
 use log::{debug, log_enabled, warn};
 
-use crate::types::{MoneyExchange, SavedExpense};
+use crate::types::{
+    Amount, Household, MoneyExchange, NetPosition, SavedExpense, SavedParticipant,
+    SettlementConstraint,
+};
 
 /// Get a list of money exchanges which settle debts computed from the list
 /// of expenses in input. The output is sorted by debtors first and creditors
@@ -15,7 +18,8 @@ use crate::types::{MoneyExchange, SavedExpense};
 ///
 /// The algorithm works as follows:
 /// - process all expenses to get a list of people who owe money (debtors) and
-///   a list of people who must receive money (creditors)
+///   a list of people who must receive money (creditors), where the members of
+///   each household count as a single person (see [`household_party`])
 /// - pick a debtor and a creditor
 /// - compare debtor's debt (*d*) and creditor's credit (*c*):
 ///     * if bigger: let the debtor give *c* to creditor, then pick a new creditor
@@ -32,8 +36,11 @@ use crate::types::{MoneyExchange, SavedExpense};
 /// errors, but in general we tolerate errors up to one cent. In the future it may
 /// be better to use fixed-precision numbers, since the integral part is what
 /// we care about and we don't need a lot of precision in the decimal part.
-pub fn compute_exchanges(expenses: Vec<SavedExpense>) -> Vec<MoneyExchange> {
-    let debts_and_credits = compute_debts_and_credits(expenses);
+pub fn compute_exchanges(
+    expenses: Vec<SavedExpense>,
+    households: &[Household],
+) -> Vec<MoneyExchange> {
+    let debts_and_credits = merge_households(compute_debts_and_credits(expenses), households);
     let mut debtors: Vec<_> = debts_and_credits
         .iter()
         .filter_map(|(p, &a)| if a < 0.0 { Some((p, a)) } else { None })
@@ -50,11 +57,8 @@ pub fn compute_exchanges(expenses: Vec<SavedExpense>) -> Vec<MoneyExchange> {
         .sort_by(|x, y| reverse_ordering(x.0.partial_cmp(y.0).expect("Cannot sort creditors")));
 
     if log_enabled!(Debug) {
-        let sum: i64 = debts_and_credits
-            .iter()
-            .map(|(_, m)| m.round() as i64)
-            .sum();
-        if sum > 1 || sum < -1 {
+        let sum: i64 = debts_and_credits.values().map(|m| m.round() as i64).sum();
+        if !(-1..=1).contains(&sum) {
             debug!(
                 "Total sum should be 0 (or 1 or -1 in some corner cases). In reality it is {sum}"
             );
@@ -110,6 +114,465 @@ pub fn compute_exchanges(expenses: Vec<SavedExpense>) -> Vec<MoneyExchange> {
     result
 }
 
+/// The money exchanges of a balance cannot respect the settlement constraints of the chat.
+#[derive(Debug)]
+pub struct ImpossibleSettlement {
+    /// What each participant would still owe or be owed after all the exchanges that
+    /// the constraints allow.
+    pub unsettled: Vec<NetPosition>,
+}
+
+/// Get a list of money exchanges which settle debts computed from the list of expenses
+/// in input, while respecting the settlement constraints of the chat. The output is
+/// sorted by debtors first and creditors second.
+///
+/// Without constraints, this is the same as [`compute_exchanges`]. Otherwise, the
+/// algorithm works on the net positions of the participants, as follows:
+/// - let debtors pay creditors directly while the constraints allow it, starting from
+///   the debtors and creditors with the fewest allowed counterparts and preferring the
+///   exchanges that settle a debtor and a creditor at once
+/// - route each remaining debt through the shortest chain of participants that are
+///   allowed to pay each other, so that the participants in the middle give away what
+///   they receive
+/// - merge the exchanges between the same participants
+///
+/// Like the simpler algorithm, the solution may require more money exchanges than
+/// needed. If some debts cannot be settled at all, an error says which ones.
+pub fn compute_constrained_exchanges(
+    expenses: Vec<SavedExpense>,
+    constraints: &[SettlementConstraint],
+    households: &[Household],
+) -> Result<Vec<MoneyExchange>, ImpossibleSettlement> {
+    if constraints.is_empty() {
+        return Ok(compute_exchanges(expenses, households));
+    }
+
+    // Constraints on members of a household apply to the whole household.
+    let party_of = |p: &str| {
+        households
+            .iter()
+            .find(|h| h.members.iter().any(|m| m == p))
+            .map(|h| household_party(&h.name))
+            .unwrap_or_else(|| p.to_string())
+    };
+    let constraints: Vec<_> = constraints
+        .iter()
+        .filter_map(|c| {
+            let (participant, other) = c.participants();
+            SettlementConstraint::from_kind(c.kind(), party_of(participant), other.map(party_of))
+        })
+        .filter(|c| !matches!(c.participants(), (p1, Some(p2)) if p1 == p2))
+        .collect();
+
+    let rules = SettlementRules::new(&constraints);
+    let mut balance: BTreeMap<String, Amount> = round_positions(merge_households(
+        compute_debts_and_credits(expenses),
+        households,
+    ))
+    .into_iter()
+    .map(|p| (p.participant, p.amount))
+    .collect();
+    // Participants that appear in a constraint can route money, even if they have no
+    // expenses.
+    for constraint in &constraints {
+        let (participant, other) = constraint.participants();
+        for p in [Some(participant), other].into_iter().flatten() {
+            balance.entry(p.to_string()).or_insert(0);
+        }
+    }
+
+    let mut transfers = BTreeMap::new();
+    pay_directly(&mut balance, &rules, &mut transfers);
+    pay_through_others(&mut balance, &rules, &mut transfers);
+
+    let mut unsettled: Vec<_> = balance
+        .iter()
+        .filter(|(_, &a)| a != 0)
+        .map(|(p, &a)| NetPosition::new(p, a))
+        .collect();
+    if !unsettled.is_empty() {
+        debug!("Cannot settle with constraints {constraints:?}: {unsettled:?}");
+        unsettled.sort_by(|x, y| {
+            y.amount
+                .cmp(&x.amount)
+                .then_with(|| x.participant.cmp(&y.participant))
+        });
+        return Err(ImpossibleSettlement { unsettled });
+    }
+
+    // If two participants pay each other, only the difference is exchanged.
+    Ok(transfers
+        .iter()
+        .filter_map(|((debtor, creditor), &amount)| {
+            let back = transfers
+                .get(&(creditor.clone(), debtor.clone()))
+                .copied()
+                .unwrap_or_default();
+            if amount > back {
+                Some(MoneyExchange::new(debtor, creditor, amount - back))
+            } else {
+                None
+            }
+        })
+        .collect())
+}
+
+/// The settlement constraints of a chat, arranged to check exchanges quickly.
+struct SettlementRules<'a> {
+    only_pays: HashMap<&'a str, HashSet<&'a str>>,
+    forbidden: HashSet<(&'a str, &'a str)>,
+    no_routing: HashSet<&'a str>,
+}
+
+impl<'a> SettlementRules<'a> {
+    fn new(constraints: &'a [SettlementConstraint]) -> Self {
+        let mut rules = SettlementRules {
+            only_pays: HashMap::new(),
+            forbidden: HashSet::new(),
+            no_routing: HashSet::new(),
+        };
+        for constraint in constraints {
+            match constraint {
+                SettlementConstraint::OnlyPays(debtor, creditor) => {
+                    rules
+                        .only_pays
+                        .entry(debtor.as_str())
+                        .or_default()
+                        .insert(creditor.as_str());
+                }
+                SettlementConstraint::Forbidden(p1, p2) => {
+                    rules.forbidden.insert((p1, p2));
+                    rules.forbidden.insert((p2, p1));
+                }
+                SettlementConstraint::NoRouting(p) => {
+                    rules.no_routing.insert(p);
+                }
+            }
+        }
+        rules
+    }
+
+    fn allows(&self, debtor: &str, creditor: &str) -> bool {
+        debtor != creditor
+            && self
+                .only_pays
+                .get(debtor)
+                .is_none_or(|creditors| creditors.contains(creditor))
+            && !self.forbidden.contains(&(debtor, creditor))
+    }
+
+    fn can_route(&self, participant: &str) -> bool {
+        !self.no_routing.contains(participant)
+    }
+}
+
+type Transfers = BTreeMap<(String, String), Amount>;
+
+/// Let debtors pay creditors directly, as long as there is an allowed exchange.
+fn pay_directly(
+    balance: &mut BTreeMap<String, Amount>,
+    rules: &SettlementRules,
+    transfers: &mut Transfers,
+) {
+    loop {
+        let debtors: Vec<_> = balance.iter().filter(|(_, &a)| a < 0).collect();
+        let creditors: Vec<_> = balance.iter().filter(|(_, &a)| a > 0).collect();
+        let num_creditors = |d: &str| creditors.iter().filter(|c| rules.allows(d, c.0)).count();
+        let num_debtors = |c: &str| debtors.iter().filter(|d| rules.allows(d.0, c)).count();
+
+        let best = debtors
+            .iter()
+            .flat_map(|d| creditors.iter().map(move |c| (d, c)))
+            .filter(|(d, c)| rules.allows(d.0, c.0))
+            .min_by_key(|((d, &debt), (c, &credit))| {
+                (
+                    num_creditors(d),
+                    num_debtors(c),
+                    -debt != credit,
+                    Reverse((-debt).min(credit)),
+                )
+            })
+            .map(|((d, &debt), (c, &credit))| ((*d).clone(), (*c).clone(), (-debt).min(credit)));
+
+        let Some((debtor, creditor, amount)) = best else {
+            break;
+        };
+        *balance
+            .get_mut(&debtor)
+            .expect("debtors are in the balance!") += amount;
+        *balance
+            .get_mut(&creditor)
+            .expect("creditors are in the balance!") -= amount;
+        *transfers.entry((debtor, creditor)).or_default() += amount;
+    }
+}
+
+/// Let the debtors that are left pay creditors through other participants.
+fn pay_through_others(
+    balance: &mut BTreeMap<String, Amount>,
+    rules: &SettlementRules,
+    transfers: &mut Transfers,
+) {
+    let debtors: Vec<_> = balance
+        .iter()
+        .filter(|(_, &a)| a < 0)
+        .map(|(p, _)| p.clone())
+        .collect();
+    for debtor in debtors {
+        while balance[&debtor] < 0 {
+            let Some(route) = find_route(&debtor, balance, rules) else {
+                break;
+            };
+            let creditor = route.last().expect("routes are never empty!");
+            let amount = (-balance[&debtor]).min(balance[creditor]);
+            for pair in route.windows(2) {
+                *transfers
+                    .entry((pair[0].clone(), pair[1].clone()))
+                    .or_default() += amount;
+            }
+            *balance
+                .get_mut(&debtor)
+                .expect("debtors are in the balance!") += amount;
+            *balance
+                .get_mut(creditor)
+                .expect("creditors are in the balance!") -= amount;
+        }
+    }
+}
+
+/// The shortest chain of allowed exchanges from a debtor to a creditor, going only
+/// through participants that can route money.
+fn find_route(
+    debtor: &str,
+    balance: &BTreeMap<String, Amount>,
+    rules: &SettlementRules,
+) -> Option<Vec<String>> {
+    let mut previous: HashMap<&str, &str> = HashMap::new();
+    let mut queue = VecDeque::from([debtor]);
+    while let Some(current) = queue.pop_front() {
+        if balance[current] > 0 {
+            let mut route = vec![current.to_string()];
+            let mut p = current;
+            while let Some(&q) = previous.get(p) {
+                route.push(q.to_string());
+                p = q;
+            }
+            route.reverse();
+            return Some(route);
+        }
+        if current != debtor && !rules.can_route(current) {
+            continue;
+        }
+        for next in balance.keys() {
+            if next != debtor
+                && !previous.contains_key(next.as_str())
+                && rules.allows(current, next)
+            {
+                previous.insert(next, current);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+/// Get the net position of each participant, from the one who is owed the most money
+/// to the one who owes the most.
+///
+/// Each position is rounded to the nearest cent, except that the positions with the
+/// biggest rounding errors are moved by one more cent where needed to make the
+/// positions sum to zero, as they must.
+pub fn compute_net_positions(expenses: Vec<SavedExpense>) -> Vec<NetPosition> {
+    round_positions(compute_debts_and_credits(expenses))
+}
+
+/// The name of a household in the balance. It starts with `#`, like groups in expenses,
+/// so that it cannot be confused with a participant.
+pub fn household_party(household_name: &str) -> String {
+    format!("#{household_name}")
+}
+
+/// Replace the members of each household with the household itself, whose balance is
+/// the sum of theirs.
+fn merge_households(
+    debts_and_credits: HashMap<String, f64>,
+    households: &[Household],
+) -> HashMap<String, f64> {
+    let party_of: HashMap<_, _> = households
+        .iter()
+        .flat_map(|h| {
+            h.members
+                .iter()
+                .map(|m| (m.as_str(), household_party(&h.name)))
+        })
+        .collect();
+
+    let mut merged = HashMap::new();
+    for (p, amount) in debts_and_credits {
+        let party = party_of.get(p.as_str()).cloned().unwrap_or(p);
+        *merged.entry(party).or_insert(0.0) += amount;
+    }
+    merged
+}
+
+/// Round each position to the nearest cent, keeping their sum equal to zero (see
+/// [`compute_net_positions`]).
+fn round_positions(debts_and_credits: HashMap<String, f64>) -> Vec<NetPosition> {
+    let sum: f64 = debts_and_credits.values().sum();
+    if sum.abs() >= 1.0 {
+        warn!("Net positions should sum to 0. In reality they sum to {sum}");
+    }
+
+    let mut positions: Vec<_> = debts_and_credits
+        .iter()
+        .map(|(p, &a)| NetPosition::new(p, a.round() as Amount))
+        .collect();
+    // The rounding errors of the positions, sorted from the biggest to the smallest, so that
+    // we know which positions to move by one cent.
+    let mut rounding_errors: Vec<_> = debts_and_credits
+        .values()
+        .zip(positions.iter())
+        .map(|(&a, p)| a - p.amount as f64)
+        .enumerate()
+        .collect();
+    rounding_errors.sort_by(|x, y| {
+        y.1.abs()
+            .partial_cmp(&x.1.abs())
+            .expect("Cannot sort rounding errors")
+            .then_with(|| positions[x.0].participant.cmp(&positions[y.0].participant))
+    });
+
+    let mut excess: Amount = positions.iter().map(|p| p.amount).sum();
+    for (index, error) in rounding_errors {
+        if excess == 0 || error.abs() >= 1.0 {
+            break;
+        }
+        // A position that was rounded up can be moved down, and vice versa.
+        if excess > 0 && error < 0.0 {
+            positions[index].amount -= 1;
+            excess -= 1;
+        } else if excess < 0 && error > 0.0 {
+            positions[index].amount += 1;
+            excess += 1;
+        }
+    }
+
+    positions.sort_by(|x, y| {
+        y.amount
+            .cmp(&x.amount)
+            .then_with(|| x.participant.cmp(&y.participant))
+    });
+    positions
+}
+
+/// How much each participant paid and how much each participant owes for a single
+/// expense, with the same split as [`compute_exchanges`] and before any rounding.
+///
+/// Creditors are also debtors, so a participant can appear in both maps.
+#[derive(Debug)]
+pub struct ExpenseShares {
+    pub paid: HashMap<String, f64>,
+    pub owed: HashMap<String, f64>,
+}
+
+pub fn compute_expense_shares(expense: &SavedExpense) -> ExpenseShares {
+    ExpenseShares {
+        paid: compute_credits(expense),
+        owed: compute_debts(expense),
+    }
+}
+
+/// The effect of an expense on the balance of a participant.
+#[derive(Debug)]
+pub struct ExpenseContribution<'a> {
+    pub expense: &'a SavedExpense,
+    /// The share of the expense of the participant.
+    pub share: f64,
+    pub paid: f64,
+    /// The balance of the participant after this expense and all the ones before it:
+    /// positive if they are owed money, negative if they owe money.
+    pub net: f64,
+}
+
+/// The expenses that affect the balance of `participant`, from the oldest, with the
+/// same split as [`compute_exchanges`] and before any rounding.
+pub fn explain_balance<'a>(
+    participant: &str,
+    expenses: &'a [SavedExpense],
+) -> Vec<ExpenseContribution<'a>> {
+    let mut expenses: Vec<_> = expenses.iter().collect();
+    expenses.sort_by_key(|e| (e.message_ts, e.id));
+
+    let mut net = 0.0;
+    expenses
+        .into_iter()
+        .filter_map(|expense| {
+            let share = compute_debts(expense).get(participant).copied();
+            let paid = compute_credits(expense).get(participant).copied();
+            if share.is_none() && paid.is_none() {
+                return None;
+            }
+
+            let share = share.unwrap_or_default();
+            let paid = paid.unwrap_or_default();
+            net += paid - share;
+            Some(ExpenseContribution {
+                expense,
+                share,
+                paid,
+                net,
+            })
+        })
+        .collect()
+}
+
+/// The effect of an expense on the debt between two participants.
+#[derive(Debug)]
+pub struct PairwiseContribution<'a> {
+    pub expense: &'a SavedExpense,
+    /// How much the debtor owes to the creditor because of this expense, net of what the
+    /// creditor owes to the debtor: it is negative if the creditor owes more.
+    pub amount: f64,
+}
+
+/// The expenses that make `debtor` owe money to `creditor` (or vice versa), from the
+/// oldest, before any simplification of the balance.
+///
+/// The share of an expense of each participant is owed to the creditors of the expense
+/// in proportion to what they paid.
+pub fn compute_pairwise_debt<'a>(
+    debtor: &str,
+    creditor: &str,
+    expenses: &'a [SavedExpense],
+) -> Vec<PairwiseContribution<'a>> {
+    let mut expenses: Vec<_> = expenses.iter().collect();
+    expenses.sort_by_key(|e| (e.message_ts, e.id));
+
+    expenses
+        .into_iter()
+        .filter_map(|expense| {
+            let debts = compute_debts(expense);
+            let credits = compute_credits(expense);
+            let total: f64 = credits.values().sum();
+            if total <= 0.0 {
+                return None;
+            }
+
+            let owed_to = |d: &str, c: &str| {
+                debts.get(d).copied().unwrap_or_default()
+                    * credits.get(c).copied().unwrap_or_default()
+                    / total
+            };
+            let amount = owed_to(debtor, creditor) - owed_to(creditor, debtor);
+            if amount.abs() < f64::EPSILON {
+                None
+            } else {
+                Some(PairwiseContribution { expense, amount })
+            }
+        })
+        .collect()
+}
+
 fn reverse_ordering(o: Ordering) -> Ordering {
     use Ordering::*;
     match o {
@@ -133,14 +596,78 @@ fn compute_debts_and_credits(expenses: Vec<SavedExpense>) -> HashMap<String, f64
     let mut balance = HashMap::new();
 
     for expense in expenses {
-        compute_debts(&expense, &mut balance);
-        compute_credits(&expense, &mut balance);
+        for (p, debt) in compute_debts(&expense) {
+            *balance.entry(p).or_insert(0.0) -= debt;
+        }
+        for (p, credit) in compute_credits(&expense) {
+            *balance.entry(p).or_insert(0.0) += credit;
+        }
     }
 
     balance
 }
 
-fn compute_debts(expense: &SavedExpense, balance: &mut HashMap<String, f64>) {
+/// Get the participants of an expense that replace *keep* and *drop*, when the two are
+/// merged into *keep*.
+///
+/// The merged participant owes and is owed what the two did together, and the others owe
+/// and are owed what they did before. This means that the merged participant often gets
+/// a custom amount, rounded to the cent, since a single share would not be enough.
+pub fn merge_participant_shares(
+    expense: &SavedExpense,
+    keep: &str,
+    drop: &str,
+) -> Vec<SavedParticipant> {
+    let is_merged = |name: &str| name == keep || name == drop;
+    let mut merged = vec![];
+
+    let is_creditor = expense
+        .participants
+        .iter()
+        .any(|p| p.is_creditor() && is_merged(&p.name));
+    if is_creditor {
+        let credits = compute_credits(expense);
+        let credit = credits.get(keep).unwrap_or(&0.0) + credits.get(drop).unwrap_or(&0.0);
+        // Without other creditors sharing the rest, the merged participant gets it all.
+        let others_share = expense
+            .participants
+            .iter()
+            .any(|p| p.is_creditor() && p.amount.is_none() && !is_merged(&p.name));
+        let amount = others_share.then_some(credit.round() as Amount);
+        merged.push(SavedParticipant::new_creditor(keep, amount));
+    }
+
+    let is_debtor = expense
+        .participants
+        .iter()
+        .any(|p| p.is_debtor() && is_merged(&p.name));
+    let fixed_debtors: HashSet<_> = expense
+        .participants
+        .iter()
+        .filter(|p| p.is_debtor() && p.amount.is_some())
+        .map(|p| p.name.as_str())
+        .collect();
+    let others_share = expense
+        .participants
+        .iter()
+        .any(|p| !is_merged(&p.name) && !fixed_debtors.contains(p.name.as_str()));
+    if others_share {
+        let debts = compute_debts(expense);
+        let debt = debts.get(keep).unwrap_or(&0.0) + debts.get(drop).unwrap_or(&0.0);
+        merged.push(SavedParticipant::new_debtor(
+            keep,
+            Some(debt.round() as Amount),
+        ));
+    } else if is_debtor {
+        merged.push(SavedParticipant::new_debtor(keep, None));
+    }
+
+    merged
+}
+
+/// How much each debtor of an expense owes.
+fn compute_debts(expense: &SavedExpense) -> HashMap<String, f64> {
+    let mut debts = HashMap::new();
     let mut total_amount = expense.amount as f64;
 
     let fixed_debtors: Vec<_> = expense
@@ -164,19 +691,23 @@ fn compute_debts(expense: &SavedExpense, balance: &mut HashMap<String, f64>) {
 
     for p in fixed_debtors {
         let amount = p.amount.expect("fixed debtors must have a custom amount!") as f64;
-        let entry = balance.entry(p.name.clone()).or_insert(0.0);
-        *entry -= amount;
+        let entry = debts.entry(p.name.clone()).or_insert(0.0);
+        *entry += amount;
         total_amount -= amount;
     }
 
     let single_quota = total_amount / all_others_len as f64;
     for p in all_others {
-        let entry = balance.entry(p.clone()).or_insert(0.0);
-        *entry -= single_quota;
+        let entry = debts.entry(p.clone()).or_insert(0.0);
+        *entry += single_quota;
     }
+
+    debts
 }
 
-fn compute_credits(expense: &SavedExpense, balance: &mut HashMap<String, f64>) {
+/// How much each creditor of an expense paid.
+fn compute_credits(expense: &SavedExpense) -> HashMap<String, f64> {
+    let mut credits = HashMap::new();
     let mut total_amount = expense.amount as f64;
 
     let fixed_creditors: Vec<_> = expense
@@ -195,15 +726,17 @@ fn compute_credits(expense: &SavedExpense, balance: &mut HashMap<String, f64>) {
         let amount = p
             .amount
             .expect("fixed creditors must have a custom amount!") as f64;
-        let entry = balance.entry(p.name.clone()).or_insert(0.0);
+        let entry = credits.entry(p.name.clone()).or_insert(0.0);
         *entry += amount;
         total_amount -= amount;
     }
     let single_quota = total_amount / other_creditors_len as f64;
     for p in other_creditors {
-        let entry = balance.entry(p.name.clone()).or_insert(0.0);
+        let entry = credits.entry(p.name.clone()).or_insert(0.0);
         *entry += single_quota;
     }
+
+    credits
 }
 
 #[cfg(test)]
@@ -270,10 +803,236 @@ mod tests {
         assert_abs_diff_eq!(*balance.get("p4").expect("test"), 1000.0);
     }
 
+    #[test]
+    fn test_compute_net_positions() {
+        let positions = compute_net_positions(make_expenses());
+
+        let positions: Vec<_> = positions
+            .iter()
+            .map(|p| (p.participant.as_str(), p.amount))
+            .collect();
+        assert_eq!(
+            vec![
+                ("ã2", 2200),
+                ("p2", 1790),
+                ("p4", 1000),
+                ("p1", -550),
+                ("à3", -1300),
+                ("a3", -3140)
+            ],
+            positions
+        );
+
+        // Three people who share 100 cents cannot each have the same position: one cent is
+        // moved so that the positions still sum to zero.
+        let expenses = vec![SavedExpense::new(
+            1,
+            true,
+            vec![
+                SavedParticipant::new_creditor("p1", None),
+                SavedParticipant::new_debtor("p2", None),
+                SavedParticipant::new_debtor("p3", None),
+            ],
+            100,
+            None,
+            DateTime::<Utc>::MIN_UTC,
+        )];
+        let positions = compute_net_positions(expenses);
+        assert_eq!(0, positions.iter().map(|p| p.amount).sum::<Amount>());
+        assert_eq!(66, positions[0].amount);
+        assert_eq!(-33, positions[1].amount);
+        assert_eq!(-33, positions[2].amount);
+
+        assert!(compute_net_positions(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_compute_expense_shares() {
+        let expenses = make_expenses();
+        let shares = compute_expense_shares(&expenses[0]);
+
+        assert_eq!(shares.paid.len(), 1);
+        assert_abs_diff_eq!(*shares.paid.get("p2").expect("test"), 2340.0);
+
+        // The creditor shares the amount that is left after the custom amounts.
+        assert_eq!(shares.owed.len(), 4);
+        assert_abs_diff_eq!(*shares.owed.get("a3").expect("test"), 1040.0);
+        assert_abs_diff_eq!(*shares.owed.get("à3").expect("test"), 200.0);
+        assert_abs_diff_eq!(*shares.owed.get("p1").expect("test"), 550.0);
+        assert_abs_diff_eq!(*shares.owed.get("p2").expect("test"), 550.0);
+    }
+
+    #[test]
+    fn test_explain_balance() {
+        let expenses = make_expenses();
+        let contributions = explain_balance("a3", &expenses);
+
+        let ids: Vec<_> = contributions.iter().map(|c| c.expense.id).collect();
+        assert_eq!(vec![1, 2, 3], ids);
+        assert_abs_diff_eq!(contributions[0].share, 1040.0);
+        assert_abs_diff_eq!(contributions[1].share, 1100.0);
+        assert_abs_diff_eq!(contributions[1].paid, 0.0);
+        assert_abs_diff_eq!(contributions[1].net, -2140.0);
+
+        // The running net ends with the balance of the participant.
+        let balance = compute_debts_and_credits(expenses.clone());
+        for participant in balance.keys() {
+            let contributions = explain_balance(participant, &expenses);
+            let last = contributions.last().expect("test");
+            assert_abs_diff_eq!(last.net, balance[participant], epsilon = 1e-6);
+        }
+
+        let contributions = explain_balance("p2", &expenses);
+        assert_eq!(1, contributions.len());
+        assert_abs_diff_eq!(contributions[0].paid, 2340.0);
+        assert_abs_diff_eq!(contributions[0].share, 550.0);
+
+        assert!(explain_balance("p5", &expenses).is_empty());
+    }
+
+    #[test]
+    fn test_compute_pairwise_debt() {
+        let mut expenses = make_expenses();
+        // p1 pays for a3 too, with p2.
+        expenses.push(SavedExpense::new(
+            4,
+            true,
+            vec![
+                SavedParticipant::new_creditor("p1", Some(300)),
+                SavedParticipant::new_creditor("p2", None),
+                SavedParticipant::new_debtor("a3", None),
+            ],
+            1200,
+            None,
+            DateTime::<Utc>::MIN_UTC,
+        ));
+
+        let contributions = compute_pairwise_debt("a3", "p2", &expenses);
+        let ids: Vec<_> = contributions.iter().map(|c| c.expense.id).collect();
+        assert_eq!(vec![1, 4], ids);
+        assert_abs_diff_eq!(contributions[0].amount, 1040.0);
+        // a3 owes a third of 12 euros, 3/4 of which were paid by p2.
+        assert_abs_diff_eq!(contributions[1].amount, 300.0, epsilon = 1e-6);
+
+        // In the last expense p1 and p2 owe money to each other, and only the difference counts.
+        let contributions = compute_pairwise_debt("p1", "p2", &expenses);
+        assert_eq!(2, contributions.len());
+        assert_abs_diff_eq!(contributions[0].amount, 550.0);
+        assert_abs_diff_eq!(
+            contributions[1].amount,
+            400.0 * 0.75 - 400.0 * 0.25,
+            epsilon = 1e-6
+        );
+
+        let reversed = compute_pairwise_debt("p2", "p1", &expenses);
+        assert_abs_diff_eq!(reversed[0].amount, -550.0);
+
+        assert!(compute_pairwise_debt("p4", "p2", &expenses).is_empty());
+    }
+
+    /// An expense where the debtor owes the whole amount to the creditor.
+    fn make_debt(id: i64, creditor: &str, debtor: &str, amount: Amount) -> SavedExpense {
+        SavedExpense::new(
+            id,
+            true,
+            vec![
+                SavedParticipant::new_creditor(creditor, None),
+                SavedParticipant::new_debtor(debtor, Some(amount)),
+            ],
+            amount,
+            None,
+            DateTime::<Utc>::MIN_UTC,
+        )
+    }
+
+    fn to_tuples(exchanges: &[MoneyExchange]) -> Vec<(&str, &str, Amount)> {
+        exchanges
+            .iter()
+            .map(|e| (e.debtor.as_str(), e.creditor.as_str(), e.amount))
+            .collect()
+    }
+
+    #[test]
+    fn test_compute_constrained_exchanges() {
+        use SettlementConstraint::*;
+
+        // Without constraints, nothing changes.
+        let exchanges = compute_constrained_exchanges(make_expenses(), &[], &[]).expect("test");
+        let expected = compute_exchanges(make_expenses(), &[]);
+        assert_eq!(to_tuples(&expected), to_tuples(&exchanges));
+
+        // p3 must pay p2, but only pays p1: p1 passes the money on.
+        let expenses = vec![
+            make_debt(1, "p2", "p3", 1000),
+            make_debt(2, "p1", "p4", 500),
+        ];
+        let constraints = [OnlyPays("p3".into(), "p1".into())];
+        let exchanges =
+            compute_constrained_exchanges(expenses.clone(), &constraints, &[]).expect("test");
+        assert_eq!(
+            vec![("p1", "p2", 500), ("p3", "p1", 1000), ("p4", "p2", 500)],
+            to_tuples(&exchanges)
+        );
+
+        // If p1 does not route money, p4 is the one who passes it on.
+        let constraints = [
+            OnlyPays("p3".into(), "p1".into()),
+            OnlyPays("p3".into(), "p4".into()),
+            NoRouting("p1".into()),
+        ];
+        let exchanges =
+            compute_constrained_exchanges(expenses.clone(), &constraints, &[]).expect("test");
+        let sum = |p: &str| -> Amount {
+            exchanges
+                .iter()
+                .map(|e| {
+                    if e.creditor == p {
+                        e.amount
+                    } else if e.debtor == p {
+                        -e.amount
+                    } else {
+                        0
+                    }
+                })
+                .sum()
+        };
+        assert_eq!(1000, sum("p2"));
+        assert_eq!(500, sum("p1"));
+        assert_eq!(-1000, sum("p3"));
+        assert_eq!(-500, sum("p4"));
+        assert!(exchanges.iter().all(|e| e.debtor != "p1"));
+
+        // Forbidden pairs choose the other creditor, without routing.
+        let expenses = vec![
+            make_debt(1, "p1", "p3", 1000),
+            make_debt(2, "p2", "p4", 1000),
+        ];
+        let constraints = [Forbidden("p1".into(), "p3".into())];
+        let exchanges = compute_constrained_exchanges(expenses, &constraints, &[]).expect("test");
+        assert_eq!(
+            vec![("p3", "p2", 1000), ("p4", "p1", 1000)],
+            to_tuples(&exchanges)
+        );
+
+        // Nobody can take the money of p3 to p2.
+        let expenses = vec![
+            make_debt(1, "p2", "p3", 1000),
+            make_debt(2, "p1", "p4", 500),
+        ];
+        let constraints = [OnlyPays("p3".into(), "p1".into()), NoRouting("p1".into())];
+        let error = compute_constrained_exchanges(expenses, &constraints, &[]).expect_err("test");
+        let unsettled: Vec<_> = error
+            .unsettled
+            .iter()
+            .map(|p| (p.participant.as_str(), p.amount))
+            .collect();
+        assert_eq!(vec![("p2", 500), ("p3", -500)], unsettled);
+    }
+
     #[test]
     fn test_compute_exchanges() {
         let expenses = make_expenses();
-        let exchanges = compute_exchanges(expenses);
+        let exchanges = compute_exchanges(expenses, &[]);
         assert_eq!(exchanges.len(), 5);
 
         assert_eq!(exchanges[0].debtor, "a3");
@@ -296,4 +1055,101 @@ mod tests {
         assert_eq!(exchanges[4].creditor, "ã2");
         assert_eq!(exchanges[4].amount, 1300);
     }
+
+    #[test]
+    fn test_compute_exchanges_with_households() {
+        let expenses = vec![
+            make_debt(1, "p2", "p1", 1000),
+            make_debt(2, "p1", "p3", 600),
+            make_debt(3, "p4", "p2", 300),
+        ];
+        let households = [Household {
+            name: "couple".to_string(),
+            members: vec!["p1".to_string(), "p2".to_string()],
+        }];
+
+        // What members of the household owe each other disappears.
+        let mut exchanges = compute_exchanges(expenses.clone(), &households);
+        exchanges.sort_by(|e1, e2| e1.creditor.cmp(&e2.creditor));
+        assert_eq!(
+            vec![("p3", "#couple", 300), ("p3", "p4", 300)],
+            to_tuples(&exchanges)
+        );
+
+        // Constraints on a member apply to the whole household.
+        let constraints = [SettlementConstraint::Forbidden("p2".into(), "p3".into())];
+        let mut exchanges =
+            compute_constrained_exchanges(expenses, &constraints, &households).expect("test");
+        exchanges.sort_by(|e1, e2| e1.creditor.cmp(&e2.creditor));
+        assert_eq!(
+            vec![("p4", "#couple", 300), ("p3", "p4", 600)],
+            to_tuples(&exchanges)
+        );
+    }
+
+    #[test]
+    fn test_merge_participant_shares() {
+        let expense = |participants: Vec<SavedParticipant>, amount| {
+            SavedExpense::new(
+                1,
+                true,
+                participants,
+                amount,
+                None,
+                DateTime::<Utc>::MIN_UTC,
+            )
+        };
+        let creditor = SavedParticipant::new_creditor;
+        let debtor = SavedParticipant::new_debtor;
+
+        // Merging must not change what anyone owes or is owed, up to rounding.
+        let check = |expense: SavedExpense| {
+            let merged_participants = merge_participant_shares(&expense, "p1", "p2");
+            let mut merged = expense.clone();
+            merged
+                .participants
+                .retain(|p| p.name != "p1" && p.name != "p2");
+            merged.participants.extend(merged_participants.clone());
+
+            let mut before = compute_debts_and_credits(vec![expense]);
+            let p2 = before.remove("p2").unwrap_or(0.0);
+            *before.entry("p1".to_string()).or_insert(0.0) += p2;
+            let after = compute_debts_and_credits(vec![merged]);
+            for (p, amount) in before {
+                assert_abs_diff_eq!(amount, after[&p], epsilon = 1.0);
+            }
+            merged_participants
+                .iter()
+                .map(|p| (p.is_creditor(), p.amount))
+                .collect::<Vec<_>>()
+        };
+
+        let split = check(expense(
+            vec![creditor("p3", None), debtor("p1", None), debtor("p2", None)],
+            1000,
+        ));
+        assert_eq!(vec![(false, Some(667))], split);
+
+        let both_paid = check(expense(
+            vec![
+                creditor("p1", None),
+                creditor("p2", None),
+                debtor("p3", Some(500)),
+                debtor("p1", None),
+            ],
+            3000,
+        ));
+        assert_eq!(vec![(true, None), (false, None)], both_paid);
+
+        let different_sides = check(expense(
+            vec![
+                creditor("p1", None),
+                creditor("p3", Some(1000)),
+                debtor("p2", None),
+                debtor("p4", None),
+            ],
+            3000,
+        ));
+        assert_eq!(vec![(true, None), (false, Some(1500))], different_sides);
+    }
 }
@@ -0,0 +1,426 @@
+//! Draw the charts that are sent by `/chart`.
+//!
+//! Charts are PNG images drawn with `plotters`, without any system dependency: the font
+//! is embedded in the binary. Like the report, charts follow the language and the
+//! settings of the chat.
+
+use std::{io::Cursor, sync::OnceLock};
+
+use anyhow::anyhow;
+use chrono::Duration;
+use plotters::{
+    coord::{
+        combinators::WithKeyPoints,
+        types::{RangedCoordf64, RangedCoordi32},
+    },
+    prelude::*,
+    style::{register_font, FontStyle},
+};
+
+use crate::{
+    error::InputError,
+    formatter::format_amount,
+    report::{category_totals, person_totals, ReportLabel},
+    settings::ChatSettings,
+    types::{Amount, SavedExpense},
+};
+
+const AMOUNT_TO_FLOAT_DIVISOR: f64 = 100.0;
+
+pub const CHART_WIDTH: u32 = 1000;
+pub const CHART_HEIGHT: u32 = 600;
+
+/// Only the biggest categories are drawn, so that their labels can be read.
+const MAX_CATEGORIES: usize = 10;
+const MAX_LABEL_CHARS: usize = 12;
+/// Bar charts have a slot for each bar or group of bars, with some empty space on each
+/// side.
+const SLOT_WIDTH: i32 = 10;
+const BAR_MARGIN: i32 = 1;
+
+const FONT_FAMILY: &str = "sans-serif";
+const FONT_DATA: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+const PAID_COLOR: RGBColor = RGBColor(33, 113, 181);
+const SPENT_COLOR: RGBColor = RGBColor(253, 141, 60);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChartKind {
+    /// What each person paid and spent.
+    People,
+    /// The total of the expenses with the same description.
+    Categories,
+    /// The total spent up to each day.
+    Time,
+}
+
+impl ChartKind {
+    pub const ALL: [ChartKind; 3] = [ChartKind::People, ChartKind::Categories, ChartKind::Time];
+
+    pub fn parse(s: &str) -> Option<ChartKind> {
+        match s {
+            "people" => Some(ChartKind::People),
+            "categories" => Some(ChartKind::Categories),
+            "time" => Some(ChartKind::Time),
+            _ => None,
+        }
+    }
+}
+
+/// What `/chart` should draw.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChartOptions {
+    /// The charts to draw, in order: all of them if none is asked.
+    pub kinds: Vec<ChartKind>,
+    pub only_active: bool,
+}
+
+impl Default for ChartOptions {
+    fn default() -> Self {
+        ChartOptions {
+            kinds: ChartKind::ALL.to_vec(),
+            only_active: true,
+        }
+    }
+}
+
+/// Draw a chart of the given expenses, as a PNG image of `CHART_WIDTH` by `CHART_HEIGHT`
+/// pixels.
+pub fn draw_chart(
+    kind: ChartKind,
+    expenses: &[SavedExpense],
+    settings: &ChatSettings,
+) -> anyhow::Result<Vec<u8>> {
+    if expenses.is_empty() {
+        return Err(InputError::nothing_to_chart().into());
+    }
+    load_font()?;
+
+    let mut expenses: Vec<_> = expenses.iter().collect();
+    expenses.sort_by_key(|e| (e.message_ts, e.id));
+
+    let mut buffer = vec![0; (CHART_WIDTH * CHART_HEIGHT * 3) as usize];
+    {
+        let root = BitMapBackend::with_buffer(&mut buffer, (CHART_WIDTH, CHART_HEIGHT))
+            .into_drawing_area();
+        root.fill(&WHITE)?;
+        let title = settings.language.catalog().chart_title(kind);
+        let mut builder = ChartBuilder::on(&root);
+        builder
+            .caption(title, (FONT_FAMILY, 28))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(90);
+
+        match kind {
+            ChartKind::People => draw_people(&mut builder, &expenses, settings)?,
+            ChartKind::Categories => draw_categories(&mut builder, &expenses, settings)?,
+            ChartKind::Time => draw_time(&mut builder, &expenses, settings)?,
+        }
+        root.present()?;
+    }
+
+    let image = image::RgbImage::from_raw(CHART_WIDTH, CHART_HEIGHT, buffer)
+        .ok_or_else(|| anyhow!("the chart buffer has the wrong size"))?;
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+type Builder<'a, 'b> = ChartBuilder<'a, 'b, BitMapBackend<'b>>;
+
+/// Two bars for each person: what they paid and what they spent.
+fn draw_people(
+    builder: &mut Builder,
+    expenses: &[&SavedExpense],
+    settings: &ChatSettings,
+) -> anyhow::Result<()> {
+    let catalog = settings.language.catalog();
+    let people: Vec<_> = person_totals(expenses)
+        .into_iter()
+        .map(|(name, totals)| (name, to_float(totals.paid), to_float(totals.spent)))
+        .collect();
+    let names: Vec<_> = people.iter().map(|p| p.0.clone()).collect();
+    let max = people.iter().map(|p| p.1.max(p.2)).fold(0.0, f64::max);
+
+    let mut chart = builder.build_cartesian_2d(slots(people.len()), 0.0..y_limit(max))?;
+    configure_slots(&mut chart, &names, settings)?;
+
+    let bar = |i: usize, left: bool, value: f64, color: RGBColor| {
+        let start = i as i32 * SLOT_WIDTH;
+        let (from, to) = if left {
+            (start + BAR_MARGIN, start + SLOT_WIDTH / 2)
+        } else {
+            (start + SLOT_WIDTH / 2, start + SLOT_WIDTH - BAR_MARGIN)
+        };
+        Rectangle::new([(from, 0.0), (to, value)], color.filled())
+    };
+    chart
+        .draw_series(
+            people
+                .iter()
+                .enumerate()
+                .map(|(i, p)| bar(i, true, p.1, PAID_COLOR)),
+        )?
+        .label(catalog.report_label(ReportLabel::Paid))
+        .legend(|(x, y)| Rectangle::new([(x, y - 6), (x + 12, y + 6)], PAID_COLOR.filled()));
+    chart
+        .draw_series(
+            people
+                .iter()
+                .enumerate()
+                .map(|(i, p)| bar(i, false, p.2, SPENT_COLOR)),
+        )?
+        .label(catalog.report_label(ReportLabel::Spent))
+        .legend(|(x, y)| Rectangle::new([(x, y - 6), (x + 12, y + 6)], SPENT_COLOR.filled()));
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::UpperRight)
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .label_font((FONT_FAMILY, 16))
+        .draw()?;
+    Ok(())
+}
+
+/// One bar for each of the biggest categories.
+fn draw_categories(
+    builder: &mut Builder,
+    expenses: &[&SavedExpense],
+    settings: &ChatSettings,
+) -> anyhow::Result<()> {
+    let catalog = settings.language.catalog();
+    let mut categories =
+        category_totals(expenses, catalog.report_label(ReportLabel::NoDescription));
+    categories.truncate(MAX_CATEGORIES);
+    let names: Vec<_> = categories.iter().map(|c| c.name.clone()).collect();
+    let max = categories
+        .iter()
+        .map(|c| c.amount as f64 / AMOUNT_TO_FLOAT_DIVISOR)
+        .fold(0.0, f64::max);
+
+    let mut chart = builder.build_cartesian_2d(slots(categories.len()), 0.0..y_limit(max))?;
+    configure_slots(&mut chart, &names, settings)?;
+
+    chart.draw_series(categories.iter().enumerate().map(|(i, c)| {
+        let amount = c.amount as f64 / AMOUNT_TO_FLOAT_DIVISOR;
+        let start = i as i32 * SLOT_WIDTH;
+        Rectangle::new(
+            [
+                (start + BAR_MARGIN, 0.0),
+                (start + SLOT_WIDTH - BAR_MARGIN, amount),
+            ],
+            PAID_COLOR.filled(),
+        )
+    }))?;
+    Ok(())
+}
+
+/// A line with the total spent up to each day with expenses.
+fn draw_time(
+    builder: &mut Builder,
+    expenses: &[&SavedExpense],
+    settings: &ChatSettings,
+) -> anyhow::Result<()> {
+    let catalog = settings.language.catalog();
+    let first = settings.local_date(expenses[0].message_ts);
+
+    let mut points: Vec<(i64, f64)> = vec![];
+    let mut total: Amount = 0;
+    for expense in expenses {
+        let day = (settings.local_date(expense.message_ts) - first).num_days();
+        total += expense.amount;
+        let total = total as f64 / AMOUNT_TO_FLOAT_DIVISOR;
+        match points.last_mut() {
+            Some(last) if last.0 == day => last.1 = total,
+            _ => points.push((day, total)),
+        }
+    }
+    let last_day = points.last().map(|p| p.0).unwrap_or(0).max(1);
+    let max = total as f64 / AMOUNT_TO_FLOAT_DIVISOR;
+
+    // The last date is centered on the right end of the axis.
+    builder.margin_right(60);
+    let mut chart = builder.build_cartesian_2d(0..last_day, 0.0..y_limit(max))?;
+    chart
+        .configure_mesh()
+        .x_labels(6)
+        .x_label_formatter(&|day| {
+            (first + Duration::days(*day))
+                .format(catalog.date_format())
+                .to_string()
+        })
+        .y_label_formatter(&|y| format_amount(from_float(*y), settings))
+        .label_style((FONT_FAMILY, 16))
+        .draw()?;
+
+    chart.draw_series(LineSeries::new(points, PAID_COLOR.stroke_width(3)).point_size(4))?;
+    Ok(())
+}
+
+/// The x axis of a bar chart with `n` slots, labelled at their center.
+fn slots(n: usize) -> WithKeyPoints<RangedCoordi32> {
+    let n = n.max(1) as i32;
+    (0..n * SLOT_WIDTH).with_key_points((0..n).map(|i| i * SLOT_WIDTH + SLOT_WIDTH / 2).collect())
+}
+
+/// Draw the mesh of a chart with a slot for each name.
+fn configure_slots(
+    chart: &mut ChartContext<
+        BitMapBackend,
+        Cartesian2d<WithKeyPoints<RangedCoordi32>, RangedCoordf64>,
+    >,
+    names: &[String],
+    settings: &ChatSettings,
+) -> anyhow::Result<()> {
+    chart
+        .configure_mesh()
+        .disable_x_mesh()
+        .x_label_formatter(&|x| {
+            names
+                .get((x / SLOT_WIDTH) as usize)
+                .map(|n| shorten(n))
+                .unwrap_or_default()
+        })
+        .y_label_formatter(&|y| format_amount(from_float(*y), settings))
+        .label_style((FONT_FAMILY, 16))
+        .draw()?;
+    Ok(())
+}
+
+/// Leave some room above the highest value.
+fn y_limit(max: f64) -> f64 {
+    if max > 0.0 {
+        max * 1.1
+    } else {
+        1.0
+    }
+}
+
+fn shorten(name: &str) -> String {
+    if name.chars().count() <= MAX_LABEL_CHARS {
+        name.to_string()
+    } else {
+        let mut short: String = name.chars().take(MAX_LABEL_CHARS - 1).collect();
+        short.push('…');
+        short
+    }
+}
+
+fn to_float(cents: f64) -> f64 {
+    cents / AMOUNT_TO_FLOAT_DIVISOR
+}
+
+fn from_float(amount: f64) -> Amount {
+    (amount * AMOUNT_TO_FLOAT_DIVISOR).round() as Amount
+}
+
+fn load_font() -> anyhow::Result<()> {
+    static IS_LOADED: OnceLock<bool> = OnceLock::new();
+    let is_loaded =
+        IS_LOADED.get_or_init(|| register_font(FONT_FAMILY, FontStyle::Normal, FONT_DATA).is_ok());
+    if *is_loaded {
+        Ok(())
+    } else {
+        Err(anyhow!("cannot load the font of the charts"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use crate::{i18n::Language, types::SavedParticipant};
+
+    use super::*;
+
+    fn make_expenses() -> Vec<SavedExpense> {
+        let day = |d: &str| -> DateTime<Utc> {
+            DateTime::parse_from_rfc3339(&format!("2023-05-{d}T12:00:00Z"))
+                .expect("test")
+                .into()
+        };
+        vec![
+            SavedExpense::new(
+                1,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("aa", None),
+                    SavedParticipant::new_debtor("bb", Some(500)),
+                    SavedParticipant::new_debtor("cc", None),
+                ],
+                1550,
+                Some("Train".to_string()),
+                day("01"),
+            ),
+            SavedExpense::new(
+                2,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("bb", None),
+                    SavedParticipant::new_debtor("aa", None),
+                    SavedParticipant::new_debtor("averyveryverylongname", None),
+                ],
+                3000,
+                Some("A dinner with a very long description".to_string()),
+                day("12"),
+            ),
+            SavedExpense::new(
+                3,
+                false,
+                vec![
+                    SavedParticipant::new_creditor("cc", None),
+                    SavedParticipant::new_debtor("aa", None),
+                ],
+                1000,
+                None,
+                day("12"),
+            ),
+        ]
+    }
+
+    fn image_size(png: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory_with_format(png, image::ImageFormat::Png)
+            .expect("the chart is not a PNG image");
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn test_chart_size() -> anyhow::Result<()> {
+        let settings = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        for kind in ChartKind::ALL {
+            let png = draw_chart(kind, &make_expenses(), &settings)?;
+            assert_eq!((CHART_WIDTH, CHART_HEIGHT), image_size(&png), "{kind:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chart_of_one_expense() -> anyhow::Result<()> {
+        let expenses = &make_expenses()[2..];
+        for kind in ChartKind::ALL {
+            let png = draw_chart(kind, expenses, &ChatSettings::default())?;
+            assert_eq!((CHART_WIDTH, CHART_HEIGHT), image_size(&png), "{kind:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_chart_without_expenses() {
+        let result = draw_chart(ChartKind::People, &[], &ChatSettings::default());
+        assert!(matches!(
+            result.map_err(|e| e.downcast::<InputError>()),
+            Err(Ok(InputError::NothingToChart))
+        ));
+    }
+
+    #[test]
+    fn test_shorten() {
+        assert_eq!("dinner", shorten("dinner"));
+        assert_eq!("a very long…", shorten("a very long description"));
+    }
+}
@@ -5,8 +5,12 @@ use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 
 use crate::{
+    backup::ChatBackup,
     error::DatabaseError,
-    types::{ParsedExpense, SavedExpense},
+    settings::{ChatSettings, Setting},
+    types::{
+        HistoryPoint, Household, ImportedExpense, ParsedExpense, SavedExpense, SettlementConstraint,
+    },
 };
 
 type DatabaseResult<T> = Result<T, DatabaseError>;
@@ -14,7 +18,7 @@ type DatabaseResult<T> = Result<T, DatabaseError>;
 pub mod sqlite;
 
 /// This trait abstracts over the type of database.
-
+///
 /// The implementation could save the data in any suitable database or even in memory.
 pub trait Database {
     /// Save an expense inside the database.
@@ -25,6 +29,17 @@ pub trait Database {
         message_ts: DateTime<Utc>,
     ) -> Result<(), DatabaseError>;
 
+    /// Save expenses read from an imported file, registering the given participants first.
+    ///
+    /// Either all expenses are saved or none is. Expenses that are not active are saved as
+    /// settled.
+    fn save_imported_expenses<T: AsRef<str>>(
+        &mut self,
+        chat_id: i64,
+        expenses: Vec<ImportedExpense>,
+        participants_to_register: &[T],
+    ) -> Result<(), DatabaseError>;
+
     /// Get the list of all expenses. If `only_active` is true, then only active expenses
     /// are returned. Otherwise both active and settled expenses are returned. Deleted
     /// expenses are never returned.
@@ -52,6 +67,19 @@ pub trait Database {
         only_active: bool,
     ) -> Result<Vec<SavedExpense>, DatabaseError>;
 
+    /// Get the list of the expenses that were active at the given point of the history:
+    /// those that were saved before it and that were not settled yet, even if they were
+    /// settled later. Deleted expenses are never returned.
+    ///
+    /// At a time, the expenses are those whose message is older. Before an expense ID, they
+    /// are those with a smaller ID, and they are compared with the time when the expense with
+    /// that ID (or the first one after it, if it does not exist) was saved.
+    fn get_expenses_at(
+        &self,
+        chat_id: i64,
+        point: HistoryPoint,
+    ) -> Result<Vec<SavedExpense>, DatabaseError>;
+
     /// Mark all active expenses as settled.
     ///
     /// An expense is active if it is neither settled nor deleted. The actual implementation
@@ -84,6 +112,31 @@ pub trait Database {
         participants: &[T],
     ) -> Result<(), DatabaseError>;
 
+    /// Rename a participant, who keeps their expenses, aliases and groups. If *keep_alias* is
+    /// true, the old name becomes an alias of the participant.
+    ///
+    /// If the participant does not exist, or if the new name is used by another participant
+    /// or by an alias of another participant, an error is returned.
+    fn rename_participant(
+        &mut self,
+        chat_id: i64,
+        old_name: &str,
+        new_name: &str,
+        keep_alias: bool,
+    ) -> Result<(), DatabaseError>;
+
+    /// Merge two participants that are the same person: the expenses, aliases, groups and
+    /// settlement constraints of *drop* move to *keep*, then *drop* is removed and its name
+    /// becomes an alias of *keep*. What each participant owes and is owed does not change.
+    ///
+    /// If one of the participants does not exist, an error is returned.
+    fn merge_participants(
+        &mut self,
+        chat_id: i64,
+        keep: &str,
+        drop: &str,
+    ) -> Result<(), DatabaseError>;
+
     /// Get the list of all participants in the given chat.
     fn get_participants(&self, chat_id: i64) -> Result<Vec<String>, DatabaseError>;
 
@@ -128,13 +181,15 @@ pub trait Database {
         participant: &str,
     ) -> Result<Vec<String>, DatabaseError>;
 
-    /// Add a group with the given *group_name*.
+    /// Add a group with the given *group_name*. Members of a household settle their debts
+    /// together.
     ///
     /// If the group already exists, it is a no-op.
     fn add_group_if_not_exists(
         &mut self,
         chat_id: i64,
         group_name: &str,
+        is_household: bool,
     ) -> Result<(), DatabaseError>;
 
     /// Remove a group with the given *group_name*.
@@ -146,10 +201,20 @@ pub trait Database {
         group_name: &str,
     ) -> Result<(), DatabaseError>;
 
-    /// Add the given members to a group.
+    /// Rename a group, which keeps its members and stays a member of the groups it is in.
+    ///
+    /// If the group does not exist, or if a group with the new name exists, an error is returned.
+    fn rename_group(
+        &mut self,
+        chat_id: i64,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), DatabaseError>;
+
+    /// Add the given members to a group. Members that start with `#` are groups.
     ///
     /// If some of the members are already present, they are ignored. If the group does not exist,
-    /// an error is returned.
+    /// or if a group would end up containing itself, an error is returned.
     fn add_group_members_if_not_exist<T: AsRef<str>>(
         &mut self,
         chat_id: i64,
@@ -157,7 +222,7 @@ pub trait Database {
         members: &[T],
     ) -> Result<(), DatabaseError>;
 
-    /// Remove the given members from a group.
+    /// Remove the given members from a group. Members that start with `#` are groups.
     ///
     /// If some of the members are not present, they are ignored. If the group does not exist,
     /// an error is returned.
@@ -171,10 +236,13 @@ pub trait Database {
     /// Get the list of all groups.
     fn get_groups(&self, chat_id: i64) -> Result<Vec<String>, DatabaseError>;
 
+    /// Get the list of all households, with their members.
+    fn get_households(&self, chat_id: i64) -> Result<Vec<Household>, DatabaseError>;
+
     /// Check if a group with the given *group_name* exists.
     fn group_exists(&self, chat_id: i64, group_name: &str) -> Result<bool, DatabaseError>;
 
-    /// Get the list of members of a group.
+    /// Get the list of direct members of a group. Groups that are members start with `#`.
     ///
     /// If the group does not exist, an error is returned.
     fn get_group_members(
@@ -183,9 +251,69 @@ pub trait Database {
         group_name: &str,
     ) -> Result<Vec<String>, DatabaseError>;
 
-    /// Check if the auto_register flag is active.
-    fn is_auto_register_active(&self, chat_id: i64) -> Result<bool, DatabaseError>;
+    /// Add a settlement constraint.
+    ///
+    /// If the constraint already exists, it is a no-op. If one of its participants does not
+    /// exist, an error is returned.
+    fn add_settlement_constraint_if_not_exists(
+        &mut self,
+        chat_id: i64,
+        constraint: &SettlementConstraint,
+    ) -> Result<(), DatabaseError>;
+
+    /// Remove a settlement constraint.
+    ///
+    /// If the constraint does not exist, it is a no-op.
+    fn remove_settlement_constraint_if_exists(
+        &mut self,
+        chat_id: i64,
+        constraint: &SettlementConstraint,
+    ) -> Result<(), DatabaseError>;
+
+    /// Get the list of all settlement constraints whose participants still exist.
+    fn get_settlement_constraints(
+        &self,
+        chat_id: i64,
+    ) -> Result<Vec<SettlementConstraint>, DatabaseError>;
+
+    /// Get the settings of the chat.
+    ///
+    /// Settings that were never changed have their default value.
+    fn get_settings(&self, chat_id: i64) -> Result<ChatSettings, DatabaseError>;
 
-    /// Toggle the auto_register flag.
-    fn toggle_auto_register(&mut self, chat_id: i64) -> Result<bool, DatabaseError>;
+    /// Change a setting of the chat.
+    fn set_setting(&mut self, chat_id: i64, setting: Setting) -> Result<(), DatabaseError>;
+
+    /// Save an expense that waits for the user to confirm it with the given message.
+    fn add_pending_expense(
+        &mut self,
+        chat_id: i64,
+        message_id: i32,
+        expense: &str,
+        message_ts: DateTime<Utc>,
+    ) -> Result<(), DatabaseError>;
+
+    /// Remove and return the expense that waits for confirmation with the given message.
+    ///
+    /// If there is none (e.g. it was already saved or discarded), `None` is returned.
+    fn take_pending_expense(
+        &mut self,
+        chat_id: i64,
+        message_id: i32,
+    ) -> Result<Option<(String, DateTime<Utc>)>, DatabaseError>;
+
+    /// Get all the data of the chat, including settled and deleted rows.
+    ///
+    /// Dialogues and expenses waiting for confirmation are not part of the backup.
+    fn get_backup(&self, chat_id: i64) -> Result<ChatBackup, DatabaseError>;
+
+    /// Check if the chat never had participants, aliases, groups or expenses, not even
+    /// deleted ones. Settings are not considered.
+    fn is_chat_empty(&self, chat_id: i64) -> Result<bool, DatabaseError>;
+
+    /// Save all the data of a backup in the chat, which should be empty.
+    ///
+    /// Rows get new IDs and keep their timestamps, so that settled and deleted rows stay
+    /// as they are. Either the whole backup is restored or nothing is.
+    fn restore_backup(&mut self, chat_id: i64, backup: &ChatBackup) -> Result<(), DatabaseError>;
 }
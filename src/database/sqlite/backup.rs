@@ -0,0 +1,373 @@
+//! Read and write all the rows of a chat, for `/backup` and `/restore`.
+
+use std::collections::HashMap;
+
+use rusqlite::{params, Connection, Transaction};
+
+use crate::backup::{
+    BackupAlias, BackupExpense, BackupExpenseParticipant, BackupGroup, BackupGroupMember,
+    BackupGroupSubgroup, BackupParticipant, BackupSetting, BackupSettlementConstraint, ChatBackup,
+    BACKUP_VERSION,
+};
+
+pub fn is_chat_empty(connection: &Connection, chat_id: i64) -> anyhow::Result<bool> {
+    let is_empty = connection.query_row(
+        "SELECT NOT EXISTS (SELECT 1 FROM participant WHERE chat_id = ?1)
+           AND NOT EXISTS (SELECT 1 FROM alias WHERE chat_id = ?1)
+           AND NOT EXISTS (SELECT 1 FROM participant_group WHERE chat_id = ?1)
+           AND NOT EXISTS (SELECT 1 FROM expense WHERE chat_id = ?1)",
+        params![&chat_id],
+        |row| row.get(0),
+    )?;
+    Ok(is_empty)
+}
+
+pub fn read_backup(connection: &Connection, chat_id: i64) -> anyhow::Result<ChatBackup> {
+    let participants = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT id, name, created_at, deleted_at FROM participant
+             WHERE chat_id = ?1 ORDER BY id",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupParticipant {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                created_at: row.get(2)?,
+                deleted_at: row.get(3)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    let aliases = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT name, participant_id, created_at, deleted_at FROM alias
+             WHERE chat_id = ?1 ORDER BY rowid",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupAlias {
+                name: row.get(0)?,
+                participant_id: row.get(1)?,
+                created_at: row.get(2)?,
+                deleted_at: row.get(3)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    let groups = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT id, name, is_household, created_at, deleted_at FROM participant_group
+             WHERE chat_id = ?1 ORDER BY id",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupGroup {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                is_household: row.get(2)?,
+                created_at: row.get(3)?,
+                deleted_at: row.get(4)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    let group_members = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT gm.group_id, gm.participant_id, gm.created_at, gm.deleted_at
+             FROM group_member gm
+             INNER JOIN participant_group g ON gm.group_id = g.id
+             WHERE g.chat_id = ?1 ORDER BY gm.rowid",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupGroupMember {
+                group_id: row.get(0)?,
+                participant_id: row.get(1)?,
+                created_at: row.get(2)?,
+                deleted_at: row.get(3)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    let group_subgroups = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT gs.group_id, gs.subgroup_id, gs.created_at, gs.deleted_at
+             FROM group_subgroup gs
+             INNER JOIN participant_group g ON gs.group_id = g.id
+             WHERE g.chat_id = ?1 ORDER BY gs.rowid",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupGroupSubgroup {
+                group_id: row.get(0)?,
+                subgroup_id: row.get(1)?,
+                created_at: row.get(2)?,
+                deleted_at: row.get(3)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    let mut expenses: Vec<BackupExpense> = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT id, amount, message, message_ts, created_at, settled_at, deleted_at
+             FROM expense WHERE chat_id = ?1 ORDER BY id",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupExpense {
+                id: row.get(0)?,
+                amount: row.get(1)?,
+                message: row.get(2)?,
+                message_ts: row.get(3)?,
+                created_at: row.get(4)?,
+                settled_at: row.get(5)?,
+                deleted_at: row.get(6)?,
+                participants: vec![],
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    {
+        let mut stmt = connection.prepare_cached(
+            "SELECT ep.expense_id, ep.participant_id, ep.is_creditor, ep.amount
+             FROM expense_participant ep
+             INNER JOIN expense e ON ep.expense_id = e.id
+             WHERE e.chat_id = ?1 ORDER BY ep.rowid",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                BackupExpenseParticipant {
+                    participant_id: row.get(1)?,
+                    is_creditor: row.get(2)?,
+                    amount: row.get(3)?,
+                },
+            ))
+        })?;
+
+        let positions: HashMap<_, _> = expenses
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.id, i))
+            .collect();
+        for row in iter {
+            let (expense_id, participant) = row?;
+            if let Some(i) = positions.get(&expense_id) {
+                expenses[*i].participants.push(participant);
+            }
+        }
+    }
+
+    let settings = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT key, value FROM chat_setting WHERE chat_id = ?1 ORDER BY key",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupSetting {
+                key: row.get(0)?,
+                value: row.get(1)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    let settlement_constraints = {
+        let mut stmt = connection.prepare_cached(
+            "SELECT kind, participant_id, other_participant_id, created_at, deleted_at
+             FROM settlement_constraint WHERE chat_id = ?1 ORDER BY rowid",
+        )?;
+        let iter = stmt.query_map(params![&chat_id], |row| {
+            Ok(BackupSettlementConstraint {
+                kind: row.get(0)?,
+                participant_id: row.get(1)?,
+                other_participant_id: row.get(2)?,
+                created_at: row.get(3)?,
+                deleted_at: row.get(4)?,
+            })
+        })?;
+        iter.collect::<Result<_, _>>()?
+    };
+
+    Ok(ChatBackup {
+        version: BACKUP_VERSION,
+        participants,
+        aliases,
+        groups,
+        group_members,
+        expenses,
+        settings,
+        settlement_constraints,
+        group_subgroups,
+    })
+}
+
+/// Insert all the rows of a backup, giving them new IDs.
+///
+/// The backup must have been validated: rows that refer to IDs that are not part of
+/// the backup cause an error.
+pub fn restore_backup(tx: &Transaction, chat_id: i64, backup: &ChatBackup) -> anyhow::Result<()> {
+    let mut participant_ids = HashMap::new();
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO participant (chat_id, name, created_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4) RETURNING id",
+        )?;
+        for p in &backup.participants {
+            let id: i64 = stmt.query_row(
+                params![&chat_id, &p.name, &p.created_at, &p.deleted_at],
+                |row| row.get(0),
+            )?;
+            participant_ids.insert(p.id, id);
+        }
+    }
+    let participant_id = |id: &i64| {
+        participant_ids
+            .get(id)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("unknown participant ID {id}"))
+    };
+
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO alias (chat_id, name, participant_id, created_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for a in &backup.aliases {
+            stmt.execute(params![
+                &chat_id,
+                &a.name,
+                &participant_id(&a.participant_id)?,
+                &a.created_at,
+                &a.deleted_at
+            ])?;
+        }
+    }
+
+    let mut group_ids = HashMap::new();
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO participant_group (chat_id, name, is_household, created_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id",
+        )?;
+        for g in &backup.groups {
+            let id: i64 = stmt.query_row(
+                params![
+                    &chat_id,
+                    &g.name,
+                    &g.is_household,
+                    &g.created_at,
+                    &g.deleted_at
+                ],
+                |row| row.get(0),
+            )?;
+            group_ids.insert(g.id, id);
+        }
+    }
+
+    let group_id = |id: &i64| {
+        group_ids
+            .get(id)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("unknown group ID {id}"))
+    };
+
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO group_member (group_id, participant_id, created_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for m in &backup.group_members {
+            stmt.execute(params![
+                &group_id(&m.group_id)?,
+                &participant_id(&m.participant_id)?,
+                &m.created_at,
+                &m.deleted_at
+            ])?;
+        }
+    }
+
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO group_subgroup (group_id, subgroup_id, created_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for s in &backup.group_subgroups {
+            stmt.execute(params![
+                &group_id(&s.group_id)?,
+                &group_id(&s.subgroup_id)?,
+                &s.created_at,
+                &s.deleted_at
+            ])?;
+        }
+    }
+
+    {
+        let mut insert_expense_stmt = tx.prepare_cached(
+            "INSERT INTO expense
+               (chat_id, amount, message, message_ts, created_at, settled_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7) RETURNING id",
+        )?;
+        let mut insert_participant_stmt = tx.prepare_cached(
+            "INSERT INTO expense_participant (expense_id, participant_id, is_creditor, amount)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for e in &backup.expenses {
+            let expense_id: i64 = insert_expense_stmt.query_row(
+                params![
+                    &chat_id,
+                    &e.amount,
+                    &e.message,
+                    &e.message_ts,
+                    &e.created_at,
+                    &e.settled_at,
+                    &e.deleted_at
+                ],
+                |row| row.get(0),
+            )?;
+            for p in &e.participants {
+                insert_participant_stmt.execute(params![
+                    &expense_id,
+                    &participant_id(&p.participant_id)?,
+                    &p.is_creditor,
+                    &p.amount
+                ])?;
+            }
+        }
+    }
+
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO settlement_constraint
+               (chat_id, kind, participant_id, other_participant_id, created_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        for c in &backup.settlement_constraints {
+            stmt.execute(params![
+                &chat_id,
+                &c.kind,
+                &participant_id(&c.participant_id)?,
+                &c.other_participant_id
+                    .as_ref()
+                    .map(participant_id)
+                    .transpose()?,
+                &c.created_at,
+                &c.deleted_at
+            ])?;
+        }
+    }
+
+    {
+        // Settings that were changed before restoring are overwritten.
+        let mut delete_stmt =
+            tx.prepare_cached("DELETE FROM chat_setting WHERE chat_id = ?1 AND key = ?2")?;
+        let mut insert_stmt = tx
+            .prepare_cached("INSERT INTO chat_setting (chat_id, key, value) VALUES (?1, ?2, ?3)")?;
+        for s in &backup.settings {
+            delete_stmt.execute(params![&chat_id, &s.key])?;
+            insert_stmt.execute(params![&chat_id, &s.key, &s.value])?;
+        }
+    }
+
+    Ok(())
+}
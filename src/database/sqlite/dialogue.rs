@@ -0,0 +1,282 @@
+//! A persistent storage for Telegram dialogues.
+//!
+//! Dialogues are saved in the same Sqlite database that holds expenses, so that
+//! an interaction that spans several messages survives a restart of the bot.
+
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use log::warn;
+use rusqlite::{params, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+use teloxide::{dispatching::dialogue::Storage, types::ChatId};
+use tokio::{sync::Mutex, task::block_in_place};
+
+use crate::{database::DatabaseResult, error::DatabaseError};
+
+use super::{map_error, SqliteDatabase};
+
+/// Dialogues that have not been updated for this many hours are considered stale:
+/// they are ignored and eventually deleted, so the chat goes back to the default state.
+const DIALOGUE_TTL_HOURS: i64 = 24;
+
+impl SqliteDatabase {
+    /// Get the serialized state of the dialogue of the given chat.
+    ///
+    /// Expired dialogues are never returned.
+    pub fn get_dialogue(&self, chat_id: i64) -> DatabaseResult<Option<String>> {
+        let fn_impl = || {
+            let state = self
+                .connection
+                .query_row(
+                    "SELECT state FROM dialogue
+                     WHERE chat_id = ?1 AND updated_at > datetime('now', ?2)",
+                    params![&chat_id, &ttl_modifier()],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            Ok(state)
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot get dialogue", e)))
+    }
+
+    /// Save the serialized state of the dialogue of the given chat, replacing
+    /// the previous one if present.
+    pub fn update_dialogue(&mut self, chat_id: i64, state: &str) -> DatabaseResult<()> {
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
+
+            // Sqlite does not support UPSERT, so we first try to update and if we fail we insert.
+
+            let num_rows_updated = tx.execute(
+                "UPDATE dialogue SET state = ?1, updated_at = CURRENT_TIMESTAMP WHERE chat_id = ?2",
+                params![&state, &chat_id],
+            )?;
+
+            if num_rows_updated < 1 {
+                tx.execute(
+                    "INSERT INTO dialogue (chat_id, state) VALUES (?1, ?2)",
+                    params![&chat_id, &state],
+                )?;
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot update dialogue", e)))
+    }
+
+    /// Remove the dialogue of the given chat.
+    ///
+    /// If the dialogue does not exist, an error is returned.
+    pub fn remove_dialogue(&mut self, chat_id: i64) -> DatabaseResult<()> {
+        let fn_impl = || {
+            let num_rows_deleted = self
+                .connection
+                .execute("DELETE FROM dialogue WHERE chat_id = ?1", params![&chat_id])?;
+
+            if num_rows_deleted < 1 {
+                return Err(DatabaseError::concurrency("the dialogue was not found").into());
+            }
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot remove dialogue", e)))
+    }
+
+    /// Delete all dialogues that have not been updated for longer than the time-to-live.
+    pub fn remove_expired_dialogues(&mut self) -> DatabaseResult<()> {
+        let fn_impl = || {
+            self.connection.execute(
+                "DELETE FROM dialogue WHERE updated_at <= datetime('now', ?1)",
+                params![&ttl_modifier()],
+            )?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot remove expired dialogues", e)))
+    }
+}
+
+fn ttl_modifier() -> String {
+    format!("-{DIALOGUE_TTL_HOURS} hours")
+}
+
+/// A teloxide [`Storage`] that keeps dialogues in a [`SqliteDatabase`].
+///
+/// Dialogue states are serialized as JSON.
+pub struct SqliteDialogueStorage {
+    database: Arc<Mutex<SqliteDatabase>>,
+}
+
+impl SqliteDialogueStorage {
+    pub fn new(database: Arc<Mutex<SqliteDatabase>>) -> Arc<Self> {
+        Arc::new(SqliteDialogueStorage { database })
+    }
+}
+
+impl<D> Storage<D> for SqliteDialogueStorage
+where
+    D: Serialize + DeserializeOwned + Send + 'static,
+{
+    type Error = DatabaseError;
+
+    fn remove_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move { self.database.lock().await.remove_dialogue(chat_id.0) })
+    }
+
+    fn update_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+        dialogue: D,
+    ) -> BoxFuture<'static, Result<(), Self::Error>> {
+        Box::pin(async move {
+            let state = serde_json::to_string(&dialogue)
+                .map_err(|e| DatabaseError::new("cannot serialize dialogue", e.into()))?;
+            self.database
+                .lock()
+                .await
+                .update_dialogue(chat_id.0, &state)
+        })
+    }
+
+    fn get_dialogue(
+        self: Arc<Self>,
+        chat_id: ChatId,
+    ) -> BoxFuture<'static, Result<Option<D>, Self::Error>> {
+        Box::pin(async move {
+            let state = self.database.lock().await.get_dialogue(chat_id.0)?;
+
+            // A state that cannot be read back (e.g. because it was saved by an older
+            // version of the bot) is not fatal: the chat simply restarts from the default state.
+            let dialogue = state.and_then(|s| match serde_json::from_str(&s) {
+                Ok(d) => Some(d),
+                Err(e) => {
+                    warn!(
+                        "Cannot deserialize dialogue in chat {}, dropping it: {:#?}",
+                        chat_id.0, e
+                    );
+                    None
+                }
+            });
+
+            Ok(dialogue)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    enum TestState {
+        Start,
+        WaitingForAmount { creditor: String },
+    }
+
+    fn open_storage(
+        tmp_dir: &TempDir,
+    ) -> anyhow::Result<(Arc<Mutex<SqliteDatabase>>, Arc<SqliteDialogueStorage>)> {
+        let file_path = tmp_dir.path().join("treasurer.db");
+        let database = Arc::new(Mutex::new(SqliteDatabase::new(file_path)?));
+        let storage = SqliteDialogueStorage::new(database.clone());
+        Ok((database, storage))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn test_resume_dialogue_after_reopening() -> anyhow::Result<()> {
+        let tmp_dir = TempDir::new("treasurer")?;
+        let chat_id = ChatId(1);
+        let state = TestState::WaitingForAmount {
+            creditor: "aa".to_string(),
+        };
+
+        {
+            let (_, storage) = open_storage(&tmp_dir)?;
+            let none: Option<TestState> = storage.clone().get_dialogue(chat_id).await?;
+            assert!(none.is_none());
+
+            storage
+                .clone()
+                .update_dialogue(chat_id, TestState::Start)
+                .await?;
+            storage
+                .clone()
+                .update_dialogue(chat_id, state.clone())
+                .await?;
+        }
+
+        let (_, storage) = open_storage(&tmp_dir)?;
+        let resumed: Option<TestState> = storage.clone().get_dialogue(chat_id).await?;
+        assert_eq!(Some(state), resumed);
+
+        // Other chats are not affected.
+        let other: Option<TestState> = storage.clone().get_dialogue(ChatId(2)).await?;
+        assert!(other.is_none());
+
+        Storage::<TestState>::remove_dialogue(storage.clone(), chat_id).await?;
+        let removed: Option<TestState> = storage.clone().get_dialogue(chat_id).await?;
+        assert!(removed.is_none());
+        assert!(Storage::<TestState>::remove_dialogue(storage, chat_id)
+            .await
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn test_stale_dialogues_expire() -> anyhow::Result<()> {
+        let tmp_dir = TempDir::new("treasurer")?;
+        let chat_id = ChatId(1);
+
+        {
+            let (database, storage) = open_storage(&tmp_dir)?;
+            storage
+                .clone()
+                .update_dialogue(chat_id, TestState::Start)
+                .await?;
+            storage
+                .clone()
+                .update_dialogue(ChatId(2), TestState::Start)
+                .await?;
+
+            // Pretend that the first dialogue was last updated two days ago.
+            database.lock().await.connection.execute(
+                "UPDATE dialogue SET updated_at = datetime('now', '-48 hours') WHERE chat_id = ?1",
+                params![&chat_id.0],
+            )?;
+
+            let expired: Option<TestState> = storage.clone().get_dialogue(chat_id).await?;
+            assert!(expired.is_none());
+        }
+
+        // Reopening the database purges stale dialogues.
+        let (database, storage) = open_storage(&tmp_dir)?;
+        let num_dialogues: i64 = database.lock().await.connection.query_row(
+            "SELECT COUNT(*) FROM dialogue",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(1, num_dialogues);
+
+        let fresh: Option<TestState> = storage.get_dialogue(ChatId(2)).await?;
+        assert_eq!(Some(TestState::Start), fresh);
+
+        Ok(())
+    }
+}
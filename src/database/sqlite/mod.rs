@@ -2,19 +2,29 @@
 
 use chrono::{DateTime, Utc};
 use log::debug;
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{named_params, params, Connection, OptionalExtension, Row, Transaction};
 use std::{collections::HashMap, path::Path};
 use tokio::task::block_in_place;
 
 use crate::{
+    backup::ChatBackup,
+    bot_logic::merge_participant_shares,
     error::DatabaseError,
-    types::{ParsedExpense, SavedExpense, SavedParticipant},
+    settings::{ChatSettings, Setting, SettingKey},
+    types::{
+        Amount, HistoryPoint, Household, ImportedExpense, ParsedExpense, SavedExpense,
+        SavedParticipant, SettlementConstraint,
+    },
 };
 
 use super::{Database, DatabaseResult};
 
+mod backup;
+mod dialogue;
 mod schema;
 
+pub use dialogue::SqliteDialogueStorage;
+
 pub struct SqliteDatabase {
     connection: Connection,
 }
@@ -26,7 +36,9 @@ impl SqliteDatabase {
                 .map_err(|e| DatabaseError::new("cannot open database", e.into()))?;
             schema::create_all_tables(&connection)
                 .map_err(|e| DatabaseError::new("cannot create tables", e))?;
-            Ok(SqliteDatabase { connection })
+            let mut database = SqliteDatabase { connection };
+            database.remove_expired_dialogues()?;
+            Ok(database)
         })
     }
 }
@@ -40,40 +52,45 @@ impl Database for SqliteDatabase {
     ) -> DatabaseResult<()> {
         let fn_impl = || {
             let tx = self.connection.transaction()?;
+            insert_expense(&tx, chat_id, expense, message_ts)?;
+            tx.commit()?;
 
-            let expense_id: i64 = {
-                let mut insert_expense_stmt = tx.prepare_cached(
-                    "INSERT INTO expense (chat_id, amount, message, message_ts) VALUES (?1, ?2, ?3, ?4) RETURNING id"
-                )?;
+            Ok(())
+        };
 
-                insert_expense_stmt.query_row(
-                    params![&chat_id, &expense.amount, &expense.message, &message_ts],
-                    |row| row.get(0),
-                )?
-            };
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot save expense with message", e)))
+    }
 
-            debug!("expense_id is {expense_id}");
+    fn save_imported_expenses<T: AsRef<str>>(
+        &mut self,
+        chat_id: i64,
+        expenses: Vec<ImportedExpense>,
+        participants_to_register: &[T],
+    ) -> DatabaseResult<()> {
+        let fn_impl = || {
+            let tx = self.connection.transaction()?;
 
             {
                 let mut insert_participant_stmt = tx.prepare_cached(
-                    "INSERT INTO expense_participant (expense_id, participant_id, is_creditor, amount)
-                SELECT ?1, id, ?2, ?3 FROM participant
-                WHERE chat_id = ?4 AND name = ?5 AND deleted_at IS NULL"
+                    "INSERT INTO participant (chat_id, name) SELECT ?1, ?2
+                     WHERE NOT EXISTS (
+                         SELECT 1 FROM participant WHERE chat_id = ?1 AND name = ?2 AND deleted_at IS NULL
+                     )",
                 )?;
+                for participant in participants_to_register {
+                    insert_participant_stmt.execute(params![&chat_id, &participant.as_ref()])?;
+                }
+            }
 
-                for participant in expense.participants {
-                    let num_inserted_rows = insert_participant_stmt.execute(params![
-                        &expense_id,
-                        &participant.is_creditor(),
-                        &participant.amount,
-                        &chat_id,
-                        &participant.name,
-                    ])?;
-                    if num_inserted_rows == 0 {
-                        return Err(
-                            DatabaseError::concurrency("the participant was not found").into()
-                        );
-                    }
+            for imported in expenses {
+                let expense_id =
+                    insert_expense(&tx, chat_id, imported.expense, imported.message_ts)?;
+                if !imported.is_active {
+                    // The original settlement date is not known.
+                    tx.execute(
+                        "UPDATE expense SET settled_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                        params![&expense_id],
+                    )?;
                 }
             }
 
@@ -82,7 +99,7 @@ impl Database for SqliteDatabase {
             Ok(())
         };
 
-        block_in_place(|| fn_impl().map_err(|e| map_error("cannot save expense with message", e)))
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot save imported expenses", e)))
     }
 
     fn get_expenses(
@@ -91,32 +108,15 @@ impl Database for SqliteDatabase {
         only_active: bool,
     ) -> Result<Vec<SavedExpense>, DatabaseError> {
         let fn_impl = || {
-            let base_query = "SELECT
-                     e.id, e.settled_at is null is_active, e.amount, e.message, e.message_ts,
-                     p.name, ep.is_creditor, ep.amount
-                 FROM expense e
-                 INNER JOIN expense_participant ep ON e.id = ep.expense_id
-                 INNER JOIN participant p ON ep.participant_id = p.id
-                 WHERE e.chat_id = :chat_id AND e.deleted_at IS NULL";
             let query = if only_active {
-                format!("{} AND e.settled_at IS NULL", base_query)
+                format!("{} AND e.settled_at IS NULL", GET_EXPENSES_QUERY)
             } else {
-                base_query.to_string()
+                GET_EXPENSES_QUERY.to_string()
             };
             let mut stmt = self.connection.prepare_cached(&query)?;
 
-            let expense_iter = stmt.query_map(&[(":chat_id", &chat_id)], |row| {
-                Ok(GetExpenseQuery {
-                    id: row.get(0)?,
-                    is_active: row.get(1)?,
-                    e_amount: row.get(2)?,
-                    e_message: row.get(3)?,
-                    e_message_ts: row.get(4)?,
-                    p_name: row.get(5)?,
-                    p_is_creditor: row.get(6)?,
-                    p_amount: row.get(7)?,
-                })
-            })?;
+            let expense_iter =
+                stmt.query_map(&[(":chat_id", &chat_id)], GetExpenseQuery::from_row)?;
 
             let expenses: Result<Vec<_>, _> = expense_iter.collect();
             Ok(parse_expenses_query(expenses?))
@@ -148,6 +148,52 @@ impl Database for SqliteDatabase {
         }
     }
 
+    fn get_expenses_at(
+        &self,
+        chat_id: i64,
+        point: HistoryPoint,
+    ) -> DatabaseResult<Vec<SavedExpense>> {
+        let fn_impl = || {
+            // Timestamps are compared with julianday, since those set by Sqlite and those
+            // set by the bot are written in different formats.
+            let expenses: Result<Vec<_>, _> = match point {
+                HistoryPoint::At(ts) => {
+                    let query = format!(
+                        "{GET_EXPENSES_QUERY}
+                         AND julianday(e.message_ts) < julianday(:ts)
+                         AND (e.settled_at IS NULL OR julianday(e.settled_at) >= julianday(:ts))"
+                    );
+                    let mut stmt = self.connection.prepare_cached(&query)?;
+                    let expense_iter = stmt.query_map(
+                        named_params! {":chat_id": &chat_id, ":ts": &ts},
+                        GetExpenseQuery::from_row,
+                    )?;
+                    expense_iter.collect()
+                }
+                HistoryPoint::BeforeExpense(expense_id) => {
+                    let query = format!(
+                        "{GET_EXPENSES_QUERY}
+                         AND e.id < :expense_id
+                         AND (e.settled_at IS NULL OR julianday(e.settled_at) > julianday(COALESCE(
+                             (SELECT created_at FROM expense
+                              WHERE chat_id = :chat_id AND id >= :expense_id
+                              ORDER BY id LIMIT 1),
+                             CURRENT_TIMESTAMP)))"
+                    );
+                    let mut stmt = self.connection.prepare_cached(&query)?;
+                    let expense_iter = stmt.query_map(
+                        named_params! {":chat_id": &chat_id, ":expense_id": &expense_id},
+                        GetExpenseQuery::from_row,
+                    )?;
+                    expense_iter.collect()
+                }
+            };
+            Ok(parse_expenses_query(expenses?))
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot get past expenses", e)))
+    }
+
     fn mark_all_as_settled(&mut self, chat_id: i64) -> DatabaseResult<()> {
         debug!("Marking all as settled using current timestamp. Chat ID: {chat_id}");
         let fn_impl = || {
@@ -239,6 +285,157 @@ impl Database for SqliteDatabase {
         block_in_place(|| fn_impl().map_err(|e| map_error("cannot remove participants", e)))
     }
 
+    fn rename_participant(
+        &mut self,
+        chat_id: i64,
+        old_name: &str,
+        new_name: &str,
+        keep_alias: bool,
+    ) -> DatabaseResult<()> {
+        debug!(
+            "Renaming participant. Chat ID: {chat_id}. Old name: {old_name}. New name: {new_name}"
+        );
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
+
+            let get_participant_id = |name: &str| {
+                tx.query_row(
+                    "SELECT id FROM participant
+                     WHERE chat_id = :chat_id AND name = :name AND deleted_at IS NULL",
+                    params![&chat_id, &name],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()
+            };
+
+            let participant_id = match get_participant_id(old_name)? {
+                Some(participant_id) => participant_id,
+                None => {
+                    return Err(DatabaseError::concurrency("the participant was not found").into())
+                }
+            };
+            if get_participant_id(new_name)?.is_some() {
+                return Err(DatabaseError::concurrency("the new name is already used").into());
+            }
+
+            // The new name may be an alias, but only of the participant being renamed: in that
+            // case the alias is not needed anymore.
+            let alias_participant_id: Option<i64> = tx
+                .query_row(
+                    "SELECT participant_id FROM alias
+                     WHERE chat_id = :chat_id AND name = :name AND deleted_at IS NULL",
+                    params![&chat_id, &new_name],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match alias_participant_id {
+                Some(id) if id != participant_id => {
+                    return Err(DatabaseError::concurrency("the new name is already used").into());
+                }
+                Some(_) => {
+                    tx.execute(
+                        "UPDATE alias SET deleted_at = CURRENT_TIMESTAMP
+                         WHERE chat_id = ?1 AND name = ?2 AND deleted_at IS NULL",
+                        params![&chat_id, &new_name],
+                    )?;
+                }
+                None => {}
+            }
+
+            tx.execute(
+                "UPDATE participant SET name = ?1 WHERE id = ?2",
+                params![&new_name, &participant_id],
+            )?;
+            if keep_alias {
+                tx.execute(
+                    "INSERT INTO alias (chat_id, name, participant_id) VALUES (?1, ?2, ?3)",
+                    params![&chat_id, &old_name, &participant_id],
+                )?;
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot rename participant", e)))
+    }
+
+    fn merge_participants(&mut self, chat_id: i64, keep: &str, drop: &str) -> DatabaseResult<()> {
+        debug!("Merging participants. Chat ID: {chat_id}. Keep: {keep}. Drop: {drop}");
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
+
+            let get_participant_id = |name: &str| {
+                tx.query_row(
+                    "SELECT id FROM participant
+                     WHERE chat_id = :chat_id AND name = :name AND deleted_at IS NULL",
+                    params![&chat_id, &name],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()
+            };
+            let (keep_id, drop_id) = match (get_participant_id(keep)?, get_participant_id(drop)?) {
+                (Some(keep_id), Some(drop_id)) if keep_id != drop_id => (keep_id, drop_id),
+                _ => return Err(DatabaseError::concurrency("the participant was not found").into()),
+            };
+
+            // Expenses with both participants would violate UNIQUE(expense_id, participant_id,
+            // is_creditor), and their shares must be recomputed anyway.
+            merge_shared_expenses(&tx, keep_id, drop_id)?;
+            tx.execute(
+                "UPDATE expense_participant SET participant_id = ?1 WHERE participant_id = ?2",
+                params![&keep_id, &drop_id],
+            )?;
+
+            tx.execute(
+                "UPDATE alias SET participant_id = ?1 WHERE participant_id = ?2",
+                params![&keep_id, &drop_id],
+            )?;
+            tx.execute(
+                "INSERT INTO alias (chat_id, name, participant_id) VALUES (?1, ?2, ?3)",
+                params![&chat_id, &drop, &keep_id],
+            )?;
+
+            tx.execute(
+                "UPDATE group_member SET participant_id = ?1
+                 WHERE participant_id = ?2 AND deleted_at IS NULL AND group_id NOT IN (
+                     SELECT group_id FROM group_member WHERE participant_id = ?1 AND deleted_at IS NULL
+                 )",
+                params![&keep_id, &drop_id],
+            )?;
+
+            tx.execute(
+                "UPDATE settlement_constraint SET participant_id = ?1
+                 WHERE participant_id = ?2 AND deleted_at IS NULL",
+                params![&keep_id, &drop_id],
+            )?;
+            tx.execute(
+                "UPDATE settlement_constraint SET other_participant_id = ?1
+                 WHERE other_participant_id = ?2 AND deleted_at IS NULL",
+                params![&keep_id, &drop_id],
+            )?;
+            // A constraint between the two participants does not mean anything anymore.
+            tx.execute(
+                "UPDATE settlement_constraint SET deleted_at = CURRENT_TIMESTAMP
+                 WHERE participant_id = ?1 AND other_participant_id = ?1 AND deleted_at IS NULL",
+                params![&keep_id],
+            )?;
+
+            // Like any removed participant, the dropped one is not shown in groups anymore.
+            tx.execute(
+                "UPDATE participant SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?1",
+                params![&drop_id],
+            )?;
+
+            tx.commit()?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot merge participants", e)))
+    }
+
     fn get_participants(&self, chat_id: i64) -> DatabaseResult<Vec<String>> {
         let fn_impl = || {
             let mut stmt = self.connection.prepare_cached(
@@ -427,16 +624,21 @@ impl Database for SqliteDatabase {
         block_in_place(|| fn_impl().map_err(|e| map_error("cannot get participant aliases", e)))
     }
 
-    fn add_group_if_not_exists(&mut self, chat_id: i64, group_name: &str) -> DatabaseResult<()> {
+    fn add_group_if_not_exists(
+        &mut self,
+        chat_id: i64,
+        group_name: &str,
+        is_household: bool,
+    ) -> DatabaseResult<()> {
         let fn_impl = || {
             // We cannot use INSERT OR IGNORE because our UNIQUE constraint includes a nullable column,
             // and NULL values are considered distinct (https://www.sqlite.org/nulls.html).
             self.connection.execute(
-                "INSERT INTO participant_group (chat_id, name) SELECT ?1, ?2
+                "INSERT INTO participant_group (chat_id, name, is_household) SELECT ?1, ?2, ?3
                  WHERE NOT EXISTS (
                      SELECT 1 FROM participant_group WHERE chat_id = ?1 AND name = ?2 AND deleted_at IS NULL
                  )",
-                params![&chat_id, &group_name],
+                params![&chat_id, &group_name, &is_household],
             )?;
 
             Ok(())
@@ -461,6 +663,42 @@ impl Database for SqliteDatabase {
         block_in_place(|| fn_impl().map_err(|e| map_error("cannot remove group", e)))
     }
 
+    fn rename_group(&mut self, chat_id: i64, old_name: &str, new_name: &str) -> DatabaseResult<()> {
+        debug!("Renaming group. Chat ID: {chat_id}. Old name: {old_name}. New name: {new_name}");
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
+
+            let get_group_id = |name: &str| {
+                tx.query_row(
+                    "SELECT id FROM participant_group
+                     WHERE chat_id = :chat_id AND name = :name AND deleted_at IS NULL",
+                    params![&chat_id, &name],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()
+            };
+
+            let group_id = match get_group_id(old_name)? {
+                Some(group_id) => group_id,
+                None => return Err(DatabaseError::concurrency("the group was not found").into()),
+            };
+            if get_group_id(new_name)?.is_some() {
+                return Err(DatabaseError::concurrency("the new name is already used").into());
+            }
+
+            tx.execute(
+                "UPDATE participant_group SET name = ?1 WHERE id = ?2",
+                params![&new_name, &group_id],
+            )?;
+
+            tx.commit()?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot rename group", e)))
+    }
+
     fn add_group_members_if_not_exist<T: AsRef<str>>(
         &mut self,
         chat_id: i64,
@@ -493,9 +731,54 @@ impl Database for SqliteDatabase {
                          SELECT 1 FROM group_member WHERE group_id = ?1 AND participant_id = ?2 AND deleted_at IS NULL
                      )",
                 )?;
+                let mut get_subgroup_id_stmt = tx.prepare_cached(
+                    "SELECT id FROM participant_group
+                     WHERE chat_id = :chat_id AND name = :member AND deleted_at IS NULL",
+                )?;
+                // The group must not be a descendant of the new subgroup, or the
+                // group would contain itself.
+                let mut creates_cycle_stmt = tx.prepare_cached(
+                    "WITH RECURSIVE descendant(id) AS (
+                         SELECT ?1
+                         UNION
+                         SELECT gs.subgroup_id FROM group_subgroup gs
+                         INNER JOIN descendant d ON gs.group_id = d.id
+                         WHERE gs.deleted_at IS NULL
+                     )
+                     SELECT EXISTS (SELECT 1 FROM descendant WHERE id = ?2)",
+                )?;
+                let mut insert_subgroup_stmt = tx.prepare_cached(
+                    "INSERT INTO group_subgroup (group_id, subgroup_id) SELECT ?1, ?2
+                     WHERE NOT EXISTS (
+                         SELECT 1 FROM group_subgroup WHERE group_id = ?1 AND subgroup_id = ?2 AND deleted_at IS NULL
+                     )",
+                )?;
                 // It's unclear how to use an IN clause, so we use a loop
                 // https://github.com/rusqlite/rusqlite/issues/345
                 for member in members {
+                    if let Some(subgroup) = member.as_ref().strip_prefix('#') {
+                        let subgroup_id: Option<i64> = get_subgroup_id_stmt
+                            .query_row(params![&chat_id, &subgroup], |row| row.get(0))
+                            .optional()?;
+
+                        if let Some(subgroup_id) = subgroup_id {
+                            let creates_cycle: bool = creates_cycle_stmt
+                                .query_row(params![&subgroup_id, &group_id], |row| row.get(0))?;
+                            if creates_cycle {
+                                return Err(DatabaseError::concurrency(
+                                    "the group would contain itself",
+                                )
+                                .into());
+                            }
+                            insert_subgroup_stmt.execute(params![&group_id, &subgroup_id])?;
+                        } else {
+                            return Err(
+                                DatabaseError::concurrency("the subgroup was not found").into()
+                            );
+                        }
+                        continue;
+                    }
+
                     // TODO: here we run two queries per member, it would be nice to optimize it,
                     // but can we do it considering the restrictions on UPSERT and IN-clause?
 
@@ -549,10 +832,26 @@ impl Database for SqliteDatabase {
                      AND deleted_at IS NULL",
                 )?;
 
+                let mut remove_subgroup_stmt = tx.prepare_cached(
+                    "UPDATE group_subgroup SET deleted_at = CURRENT_TIMESTAMP
+                     WHERE group_id = ?1 AND subgroup_id =
+                         (SELECT id FROM participant_group WHERE chat_id = ?2 AND name = ?3 AND deleted_at IS NULL)
+                     AND deleted_at IS NULL",
+                )?;
+
                 // It's unclear how to use an IN clause, so we use a loop
                 // https://github.com/rusqlite/rusqlite/issues/345
                 for member in members {
-                    remove_member_stmt.execute(params![&group_id, &chat_id, &member.as_ref()])?;
+                    match member.as_ref().strip_prefix('#') {
+                        Some(subgroup) => {
+                            remove_subgroup_stmt.execute(params![&group_id, &chat_id, &subgroup])?
+                        }
+                        None => remove_member_stmt.execute(params![
+                            &group_id,
+                            &chat_id,
+                            &member.as_ref()
+                        ])?,
+                    };
                 }
             } else {
                 return Err(DatabaseError::concurrency("the group was not found").into());
@@ -582,6 +881,43 @@ impl Database for SqliteDatabase {
         block_in_place(|| fn_impl().map_err(|e| map_error("cannot get groups", e)))
     }
 
+    fn get_households(&self, chat_id: i64) -> DatabaseResult<Vec<Household>> {
+        let fn_impl = || {
+            let mut stmt = self.connection.prepare_cached(
+                "SELECT pg.name, p.name FROM participant_group pg
+                 LEFT JOIN group_member gm ON pg.id = gm.group_id AND gm.deleted_at IS NULL
+                 LEFT JOIN participant p ON gm.participant_id = p.id AND p.deleted_at IS NULL
+                 WHERE pg.chat_id = :chat_id AND pg.is_household AND pg.deleted_at IS NULL
+                 ORDER BY pg.name, p.name",
+            )?;
+
+            let member_iter = stmt.query_map(params![&chat_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+            })?;
+
+            let mut households: Vec<Household> = vec![];
+            for member in member_iter {
+                let (household_name, member) = member?;
+                if households.last().map(|h| &h.name) != Some(&household_name) {
+                    households.push(Household {
+                        name: household_name,
+                        members: vec![],
+                    });
+                }
+                if let Some(member) = member {
+                    households
+                        .last_mut()
+                        .expect("just pushed a household!")
+                        .members
+                        .push(member);
+                }
+            }
+            Ok(households)
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot get households", e)))
+    }
+
     fn group_exists(&self, chat_id: i64, group_name: &str) -> DatabaseResult<bool> {
         let fn_impl = || {
             let group_id: Option<i64> = self
@@ -612,7 +948,14 @@ impl Database for SqliteDatabase {
                          INNER JOIN participant p ON gm.participant_id = p.id
                          WHERE pg.chat_id = :chat_id
                          AND pg.name = :group_name
-                         AND pg.deleted_at IS NULL AND gm.deleted_at IS NULL AND p.deleted_at IS NULL",
+                         AND pg.deleted_at IS NULL AND gm.deleted_at IS NULL AND p.deleted_at IS NULL
+                 UNION ALL
+                 SELECT '#' || sg.name FROM participant_group pg
+                         INNER JOIN group_subgroup gs ON pg.id = gs.group_id
+                         INNER JOIN participant_group sg ON gs.subgroup_id = sg.id
+                         WHERE pg.chat_id = :chat_id
+                         AND pg.name = :group_name
+                         AND pg.deleted_at IS NULL AND gs.deleted_at IS NULL AND sg.deleted_at IS NULL",
             )?;
 
             let group_member_iter =
@@ -625,120 +968,485 @@ impl Database for SqliteDatabase {
         block_in_place(|| fn_impl().map_err(|e| map_error("cannot get group members", e)))
     }
 
-    fn is_auto_register_active(&self, chat_id: i64) -> Result<bool, DatabaseError> {
-        let fn_impl = || {
-            let mut stmt = self
-                .connection
-                .prepare_cached("SELECT auto_register FROM chat_flag WHERE chat_id = :chat_id")?;
+    fn add_settlement_constraint_if_not_exists(
+        &mut self,
+        chat_id: i64,
+        constraint: &SettlementConstraint,
+    ) -> Result<(), DatabaseError> {
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
 
-            let auto_flag: Option<bool> = stmt
-                .query_row(params![&chat_id], |row| row.get(0))
-                .optional()?;
+            let (participant_id, other_participant_id) =
+                get_constraint_participant_ids(&tx, chat_id, constraint)?;
 
-            if let Some(auto_flag) = auto_flag {
-                Ok(auto_flag)
-            } else {
-                Ok(false)
-            }
+            // We cannot use INSERT OR IGNORE because there is no UNIQUE constraint (see
+            // `add_participants_if_not_exist`). Forbidden pairs are the same in both orders.
+            tx.execute(
+                "INSERT INTO settlement_constraint (chat_id, kind, participant_id, other_participant_id)
+                 SELECT ?1, ?2, ?3, ?4
+                 WHERE NOT EXISTS (
+                     SELECT 1 FROM settlement_constraint
+                     WHERE chat_id = ?1 AND kind = ?2 AND deleted_at IS NULL
+                     AND ((participant_id = ?3 AND other_participant_id IS ?4)
+                          OR (kind = 'forbidden' AND participant_id = ?4 AND other_participant_id = ?3))
+                 )",
+                params![
+                    &chat_id,
+                    &constraint.kind(),
+                    &participant_id,
+                    &other_participant_id
+                ],
+            )?;
+
+            tx.commit()?;
+
+            Ok(())
         };
 
-        block_in_place(|| fn_impl().map_err(|e| map_error("cannot get auto register flag", e)))
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot add settlement constraint", e)))
     }
 
-    fn toggle_auto_register(&mut self, chat_id: i64) -> Result<bool, DatabaseError> {
-        let auto_register = self.is_auto_register_active(chat_id)?;
-        let target_auto_register = !auto_register;
-
+    fn remove_settlement_constraint_if_exists(
+        &mut self,
+        chat_id: i64,
+        constraint: &SettlementConstraint,
+    ) -> Result<(), DatabaseError> {
         let mut fn_impl = || {
             let tx = self.connection.transaction()?;
 
-            // Sqlite does not support UPSERT, so we first try to update and if we fail we insert.
-
-            let num_rows_updated = tx.execute(
-                "UPDATE chat_flag SET auto_register = ?1 WHERE chat_id = ?2",
-                params![&target_auto_register, &chat_id],
+            let (participant_id, other_participant_id) =
+                get_constraint_participant_ids(&tx, chat_id, constraint)?;
+
+            tx.execute(
+                "UPDATE settlement_constraint SET deleted_at = CURRENT_TIMESTAMP
+                 WHERE chat_id = ?1 AND kind = ?2 AND deleted_at IS NULL
+                 AND ((participant_id = ?3 AND other_participant_id IS ?4)
+                      OR (kind = 'forbidden' AND participant_id = ?4 AND other_participant_id = ?3))",
+                params![
+                    &chat_id,
+                    &constraint.kind(),
+                    &participant_id,
+                    &other_participant_id
+                ],
             )?;
 
-            if num_rows_updated < 1 {
-                // The table did not have an entry for this chat: let's create it now.
-                tx.execute(
-                    "INSERT INTO chat_flag (chat_id, auto_register) VALUES (?1, ?2)",
-                    params![&chat_id, &target_auto_register],
-                )?;
-            }
-
             tx.commit()?;
 
-            Ok(target_auto_register)
+            Ok(())
         };
 
-        block_in_place(|| fn_impl().map_err(|e| map_error("cannot toggle auto register flag", e)))
+        block_in_place(|| {
+            fn_impl().map_err(|e| map_error("cannot remove settlement constraint", e))
+        })
     }
-}
 
-fn parse_expenses_query(expenses: Vec<GetExpenseQuery>) -> Vec<SavedExpense> {
-    let mut result = HashMap::new();
-    for expense in expenses {
-        let entry = result.entry(expense.id).or_insert_with(|| {
-            SavedExpense::new(
-                expense.id,
-                expense.is_active,
-                vec![],
-                expense.e_amount,
-                expense.e_message,
-                expense.e_message_ts,
-            )
-        });
+    fn get_settlement_constraints(
+        &self,
+        chat_id: i64,
+    ) -> Result<Vec<SettlementConstraint>, DatabaseError> {
+        let fn_impl = || {
+            let mut stmt = self.connection.prepare_cached(
+                "SELECT sc.kind, p.name, o.name FROM settlement_constraint sc
+                 INNER JOIN participant p ON sc.participant_id = p.id
+                 LEFT JOIN participant o ON sc.other_participant_id = o.id
+                 WHERE sc.chat_id = :chat_id AND sc.deleted_at IS NULL AND p.deleted_at IS NULL
+                 AND (sc.other_participant_id IS NULL OR (o.id IS NOT NULL AND o.deleted_at IS NULL))
+                 ORDER BY sc.rowid",
+            )?;
 
-        let name = &expense.p_name;
-        let amount = expense.p_amount;
-        let participant = if expense.p_is_creditor {
-            SavedParticipant::new_creditor(name, amount)
-        } else {
-            SavedParticipant::new_debtor(name, amount)
+            let constraint_iter = stmt.query_map(params![&chat_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?;
+
+            let mut constraints = vec![];
+            for constraint in constraint_iter {
+                let (kind, participant, other) = constraint?;
+                match SettlementConstraint::from_kind(&kind, participant, other) {
+                    Some(constraint) => constraints.push(constraint),
+                    None => debug!("Ignoring settlement constraint of kind {kind}"),
+                }
+            }
+
+            Ok(constraints)
         };
-        entry.participants.push(participant);
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot get settlement constraints", e)))
     }
 
-    result.into_iter().map(|(_, e)| e).collect()
-}
+    fn get_settings(&self, chat_id: i64) -> Result<ChatSettings, DatabaseError> {
+        let fn_impl = || {
+            let mut stmt = self
+                .connection
+                .prepare_cached("SELECT key, value FROM chat_setting WHERE chat_id = :chat_id")?;
 
-struct GetExpenseQuery {
-    id: i64,
-    is_active: bool,
-    e_amount: i64,
-    e_message: Option<String>,
-    e_message_ts: DateTime<Utc>,
-    p_name: String,
-    p_is_creditor: bool,
-    p_amount: Option<i64>,
-}
+            let setting_iter = stmt.query_map(params![&chat_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
 
-struct AliasQuery {
-    alias_name: String,
-    participant_name: String,
-}
+            let mut settings = ChatSettings::default();
+            for setting in setting_iter {
+                let (key, value) = setting?;
+                // Unknown keys and invalid values are ignored, so that the setting keeps
+                // its default value.
+                match SettingKey::parse(&key).and_then(|k| Setting::parse(k, &value)) {
+                    Some(setting) => settings.set(setting),
+                    None => debug!("Ignoring setting {key} with value {value}"),
+                }
+            }
 
-fn map_error<T: AsRef<str>>(message: T, e: anyhow::Error) -> DatabaseError {
-    match e.downcast::<DatabaseError>() {
-        Ok(e) => e,
-        Err(e) => DatabaseError::new(message, e),
+            Ok(settings)
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot get settings", e)))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashSet;
+    fn set_setting(&mut self, chat_id: i64, setting: Setting) -> Result<(), DatabaseError> {
+        let key = setting.key().as_str();
 
-    use tempdir::TempDir;
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
 
-    use crate::types::ParsedParticipant;
+            match setting.value() {
+                Some(value) => {
+                    // Sqlite does not support UPSERT, so we first try to update and if we fail we insert.
 
-    use super::*;
+                    let num_rows_updated = tx.execute(
+                        "UPDATE chat_setting SET value = ?1 WHERE chat_id = ?2 AND key = ?3",
+                        params![&value, &chat_id, &key],
+                    )?;
 
-    fn temp_database() -> anyhow::Result<(SqliteDatabase, TempDir)> {
-        let tmp_dir = TempDir::new("treasurer")?;
-        println!("Temporary directory is: {:#?}", tmp_dir);
+                    if num_rows_updated < 1 {
+                        tx.execute(
+                            "INSERT INTO chat_setting (chat_id, key, value) VALUES (?1, ?2, ?3)",
+                            params![&chat_id, &key, &value],
+                        )?;
+                    }
+                }
+                None => {
+                    // Default values are not saved.
+                    tx.execute(
+                        "DELETE FROM chat_setting WHERE chat_id = ?1 AND key = ?2",
+                        params![&chat_id, &key],
+                    )?;
+                }
+            }
+
+            tx.commit()?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot set setting", e)))
+    }
+
+    fn add_pending_expense(
+        &mut self,
+        chat_id: i64,
+        message_id: i32,
+        expense: &str,
+        message_ts: DateTime<Utc>,
+    ) -> Result<(), DatabaseError> {
+        let fn_impl = || {
+            self.connection.execute(
+                "INSERT INTO pending_expense (chat_id, message_id, expense, message_ts)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![&chat_id, &message_id, &expense, &message_ts],
+            )?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot add pending expense", e)))
+    }
+
+    fn take_pending_expense(
+        &mut self,
+        chat_id: i64,
+        message_id: i32,
+    ) -> Result<Option<(String, DateTime<Utc>)>, DatabaseError> {
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
+
+            let pending = tx
+                .query_row(
+                    "SELECT expense, message_ts FROM pending_expense
+                     WHERE chat_id = ?1 AND message_id = ?2",
+                    params![&chat_id, &message_id],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+
+            if pending.is_some() {
+                tx.execute(
+                    "DELETE FROM pending_expense WHERE chat_id = ?1 AND message_id = ?2",
+                    params![&chat_id, &message_id],
+                )?;
+            }
+
+            tx.commit()?;
+
+            Ok(pending)
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot take pending expense", e)))
+    }
+
+    fn get_backup(&self, chat_id: i64) -> Result<ChatBackup, DatabaseError> {
+        block_in_place(|| {
+            backup::read_backup(&self.connection, chat_id)
+                .map_err(|e| map_error("cannot get backup", e))
+        })
+    }
+
+    fn is_chat_empty(&self, chat_id: i64) -> Result<bool, DatabaseError> {
+        block_in_place(|| {
+            backup::is_chat_empty(&self.connection, chat_id)
+                .map_err(|e| map_error("cannot check if chat is empty", e))
+        })
+    }
+
+    fn restore_backup(&mut self, chat_id: i64, backup: &ChatBackup) -> Result<(), DatabaseError> {
+        let mut fn_impl = || {
+            let tx = self.connection.transaction()?;
+            backup::restore_backup(&tx, chat_id, backup)?;
+            tx.commit()?;
+
+            Ok(())
+        };
+
+        block_in_place(|| fn_impl().map_err(|e| map_error("cannot restore backup", e)))
+    }
+}
+
+/// Insert an expense and its participants, returning the ID of the expense.
+fn insert_expense(
+    tx: &Transaction,
+    chat_id: i64,
+    expense: ParsedExpense,
+    message_ts: DateTime<Utc>,
+) -> anyhow::Result<i64> {
+    let expense_id: i64 = {
+        let mut insert_expense_stmt = tx.prepare_cached(
+            "INSERT INTO expense (chat_id, amount, message, message_ts) VALUES (?1, ?2, ?3, ?4) RETURNING id"
+        )?;
+
+        insert_expense_stmt.query_row(
+            params![&chat_id, &expense.amount, &expense.message, &message_ts],
+            |row| row.get(0),
+        )?
+    };
+
+    debug!("expense_id is {expense_id}");
+
+    let mut insert_participant_stmt = tx.prepare_cached(
+        "INSERT INTO expense_participant (expense_id, participant_id, is_creditor, amount)
+        SELECT ?1, id, ?2, ?3 FROM participant
+        WHERE chat_id = ?4 AND name = ?5 AND deleted_at IS NULL",
+    )?;
+
+    for participant in expense.participants {
+        let num_inserted_rows = insert_participant_stmt.execute(params![
+            &expense_id,
+            &participant.is_creditor(),
+            &participant.amount,
+            &chat_id,
+            &participant.name,
+        ])?;
+        if num_inserted_rows == 0 {
+            return Err(DatabaseError::concurrency("the participant was not found").into());
+        }
+    }
+
+    Ok(expense_id)
+}
+
+fn parse_expenses_query(expenses: Vec<GetExpenseQuery>) -> Vec<SavedExpense> {
+    let mut result = HashMap::new();
+    for expense in expenses {
+        let entry = result.entry(expense.id).or_insert_with(|| {
+            SavedExpense::new(
+                expense.id,
+                expense.is_active,
+                vec![],
+                expense.e_amount,
+                expense.e_message,
+                expense.e_message_ts,
+            )
+        });
+
+        let name = &expense.p_name;
+        let amount = expense.p_amount;
+        let participant = if expense.p_is_creditor {
+            SavedParticipant::new_creditor(name, amount)
+        } else {
+            SavedParticipant::new_debtor(name, amount)
+        };
+        entry.participants.push(participant);
+    }
+
+    result.into_values().collect()
+}
+
+/// The query of [`GetExpenseQuery`], to be completed with more conditions.
+const GET_EXPENSES_QUERY: &str = "SELECT
+        e.id, e.settled_at is null is_active, e.amount, e.message, e.message_ts,
+        p.name, ep.is_creditor, ep.amount
+    FROM expense e
+    INNER JOIN expense_participant ep ON e.id = ep.expense_id
+    INNER JOIN participant p ON ep.participant_id = p.id
+    WHERE e.chat_id = :chat_id AND e.deleted_at IS NULL";
+
+struct GetExpenseQuery {
+    id: i64,
+    is_active: bool,
+    e_amount: i64,
+    e_message: Option<String>,
+    e_message_ts: DateTime<Utc>,
+    p_name: String,
+    p_is_creditor: bool,
+    p_amount: Option<i64>,
+}
+
+impl GetExpenseQuery {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(GetExpenseQuery {
+            id: row.get(0)?,
+            is_active: row.get(1)?,
+            e_amount: row.get(2)?,
+            e_message: row.get(3)?,
+            e_message_ts: row.get(4)?,
+            p_name: row.get(5)?,
+            p_is_creditor: row.get(6)?,
+            p_amount: row.get(7)?,
+        })
+    }
+}
+
+struct AliasQuery {
+    alias_name: String,
+    participant_name: String,
+}
+
+/// The IDs of the participants of a settlement constraint.
+fn get_constraint_participant_ids(
+    tx: &Transaction,
+    chat_id: i64,
+    constraint: &SettlementConstraint,
+) -> anyhow::Result<(i64, Option<i64>)> {
+    let mut stmt = tx.prepare_cached(
+        "SELECT id FROM participant
+         WHERE chat_id = :chat_id AND name = :name AND deleted_at IS NULL",
+    )?;
+    let mut get_id = |name: &str| -> anyhow::Result<i64> {
+        stmt.query_row(params![&chat_id, &name], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| DatabaseError::concurrency("the participant was not found").into())
+    };
+
+    let (participant, other) = constraint.participants();
+    let participant_id = get_id(participant)?;
+    let other_participant_id = other.map(&mut get_id).transpose()?;
+    Ok((participant_id, other_participant_id))
+}
+
+/// Replace the rows of *keep* and *drop* in the expenses where both appear with the rows
+/// of the merged participant (see [`merge_participant_shares`]).
+fn merge_shared_expenses(tx: &Transaction, keep_id: i64, drop_id: i64) -> anyhow::Result<()> {
+    let mut get_expenses_stmt = tx.prepare_cached(
+        "SELECT e.id, e.amount, ep.participant_id, ep.is_creditor, ep.amount FROM expense e
+         INNER JOIN expense_participant ep ON e.id = ep.expense_id
+         WHERE e.id IN (
+             SELECT expense_id FROM expense_participant WHERE participant_id = ?1
+             INTERSECT
+             SELECT expense_id FROM expense_participant WHERE participant_id = ?2
+         )
+         ORDER BY e.id",
+    )?;
+    let rows = get_expenses_stmt.query_map(params![&keep_id, &drop_id], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, Amount>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, bool>(3)?,
+            row.get::<_, Option<Amount>>(4)?,
+        ))
+    })?;
+
+    // Participants are identified by their ID, since a removed participant may have the
+    // same name as an active one.
+    let mut expenses: Vec<SavedExpense> = vec![];
+    for row in rows {
+        let (expense_id, expense_amount, participant_id, is_creditor, amount) = row?;
+        if expenses.last().map(|e| e.id) != Some(expense_id) {
+            expenses.push(SavedExpense::new(
+                expense_id,
+                true,
+                vec![],
+                expense_amount,
+                None,
+                DateTime::<Utc>::MIN_UTC,
+            ));
+        }
+        let participant_id = participant_id.to_string();
+        let participant = if is_creditor {
+            SavedParticipant::new_creditor(&participant_id, amount)
+        } else {
+            SavedParticipant::new_debtor(&participant_id, amount)
+        };
+        expenses
+            .last_mut()
+            .expect("just pushed an expense!")
+            .participants
+            .push(participant);
+    }
+
+    let mut delete_stmt = tx.prepare_cached(
+        "DELETE FROM expense_participant WHERE expense_id = ?1 AND participant_id IN (?2, ?3)",
+    )?;
+    let mut insert_stmt = tx.prepare_cached(
+        "INSERT INTO expense_participant (expense_id, participant_id, is_creditor, amount)
+         VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for expense in expenses {
+        let merged = merge_participant_shares(&expense, &keep_id.to_string(), &drop_id.to_string());
+        delete_stmt.execute(params![&expense.id, &keep_id, &drop_id])?;
+        for participant in merged {
+            insert_stmt.execute(params![
+                &expense.id,
+                &keep_id,
+                &participant.is_creditor(),
+                &participant.amount
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+fn map_error<T: AsRef<str>>(message: T, e: anyhow::Error) -> DatabaseError {
+    match e.downcast::<DatabaseError>() {
+        Ok(e) => e,
+        Err(e) => DatabaseError::new(message, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use tempdir::TempDir;
+
+    use crate::{i18n::Language, types::ParsedParticipant};
+
+    use super::*;
+
+    fn temp_database() -> anyhow::Result<(SqliteDatabase, TempDir)> {
+        let tmp_dir = TempDir::new("treasurer")?;
+        println!("Temporary directory is: {:#?}", tmp_dir);
 
         let file_path = tmp_dir.path().join("treasurer.db");
         let database = SqliteDatabase::new(file_path)?;
@@ -804,7 +1512,7 @@ mod tests {
         // Asking only active expenses returns one element.
         let expenses = database.get_expenses_with_limit(chat_id, 0, 2, true)?;
         assert_eq!(1, expenses.len());
-        assert_eq!(4, expenses.get(0).unwrap().amount);
+        assert_eq!(4, expenses.first().unwrap().amount);
 
         // Asking all expenses return them all.
         let expenses = database.get_expenses_with_limit(chat_id, 0, 10, false)?;
@@ -813,12 +1521,307 @@ mod tests {
         // Asking all expenses with low limit returns only some (newest first).
         let expenses = database.get_expenses_with_limit(chat_id, 0, 2, false)?;
         assert_eq!(2, expenses.len());
-        assert_eq!(4, expenses.get(0).unwrap().amount);
+        assert_eq!(4, expenses.first().unwrap().amount);
         assert_eq!(3, expenses.get(1).unwrap().amount);
 
         Ok(())
     }
 
+    #[test]
+    #[ignore]
+    fn test_get_expenses_at() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        database.add_participants_if_not_exist(chat_id, &["aa", "bb"])?;
+
+        let ts =
+            |s: &str| -> DateTime<Utc> { DateTime::parse_from_rfc3339(s).expect("test").into() };
+        let save = |database: &mut SqliteDatabase,
+                    amount,
+                    message_ts: &str,
+                    created_at: &str|
+         -> anyhow::Result<i64> {
+            let expense = ParsedExpense::new(
+                vec![
+                    ParsedParticipant::new_creditor("aa", None),
+                    ParsedParticipant::new_debtor("bb", None),
+                ],
+                amount,
+                None,
+            );
+            database.save_expense_with_message(chat_id, expense, ts(message_ts))?;
+            let id = database
+                .connection
+                .query_row("SELECT MAX(id) FROM expense", [], |row| row.get(0))?;
+            database.connection.execute(
+                "UPDATE expense SET created_at = ?1 WHERE id = ?2",
+                params![&ts(created_at), &id],
+            )?;
+            Ok(id)
+        };
+        let first = save(
+            &mut database,
+            1,
+            "2026-09-29T10:00:00Z",
+            "2026-09-29T10:00:00Z",
+        )?;
+        let second = save(
+            &mut database,
+            2,
+            "2026-09-30T10:00:00Z",
+            "2026-09-30T10:00:00Z",
+        )?;
+        // Settled between the second and the third expense, with the format of Sqlite.
+        database.connection.execute(
+            "UPDATE expense SET settled_at = '2026-10-01 08:00:00' WHERE chat_id = ?1",
+            params![&chat_id],
+        )?;
+        let third = save(
+            &mut database,
+            4,
+            "2026-10-01T10:00:00Z",
+            "2026-10-01T10:00:00Z",
+        )?;
+        let fourth = save(
+            &mut database,
+            8,
+            "2026-10-02T10:00:00Z",
+            "2026-10-02T10:00:00Z",
+        )?;
+        database.delete_expense(chat_id, fourth)?;
+
+        let amounts = |point| -> anyhow::Result<HashSet<i64>> {
+            Ok(database
+                .get_expenses_at(chat_id, point)?
+                .into_iter()
+                .map(|e| e.amount)
+                .collect())
+        };
+
+        assert_eq!(
+            HashSet::from([1]),
+            amounts(HistoryPoint::At(ts("2026-09-30T00:00:00Z")))?
+        );
+        assert_eq!(
+            HashSet::from([1, 2]),
+            amounts(HistoryPoint::At(ts("2026-10-01T00:00:00Z")))?
+        );
+        assert_eq!(
+            HashSet::from([4]),
+            amounts(HistoryPoint::At(ts("2026-10-03T00:00:00Z")))?
+        );
+
+        assert_eq!(HashSet::new(), amounts(HistoryPoint::BeforeExpense(first))?);
+        assert_eq!(
+            HashSet::from([1]),
+            amounts(HistoryPoint::BeforeExpense(second))?
+        );
+        assert_eq!(HashSet::new(), amounts(HistoryPoint::BeforeExpense(third))?);
+        // The deleted expense still marks its point of the history.
+        assert_eq!(
+            HashSet::from([4]),
+            amounts(HistoryPoint::BeforeExpense(fourth))?
+        );
+        assert_eq!(
+            HashSet::from([4]),
+            amounts(HistoryPoint::BeforeExpense(fourth + 10))?
+        );
+
+        // Expenses of other chats are never returned.
+        assert!(database
+            .get_expenses_at(chat_id + 1, HistoryPoint::BeforeExpense(fourth))?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_save_imported_expenses() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+
+        database.add_participants_if_not_exist(chat_id, &["aa"])?;
+
+        let imported = |creditor: &str, amount, is_active| ImportedExpense {
+            expense: ParsedExpense::new(
+                vec![
+                    ParsedParticipant::new_creditor(creditor, None),
+                    ParsedParticipant::new_debtor("aa", None),
+                ],
+                amount,
+                None,
+            ),
+            message_ts: DateTime::<Utc>::MIN_UTC,
+            is_active,
+        };
+        let expenses = vec![imported("bb", 1, true), imported("cc", 2, false)];
+        database.save_imported_expenses(chat_id, expenses, &["aa", "bb", "cc"])?;
+
+        assert_eq!(
+            to_hash_set(vec!["aa", "bb", "cc"]),
+            to_hash_set(database.get_participants(chat_id)?)
+        );
+
+        let expenses = database.get_expenses_with_limit(chat_id, 0, 10, false)?;
+        assert_eq!(2, expenses.len());
+        let active = database.get_expenses_with_limit(chat_id, 0, 10, true)?;
+        assert_eq!(1, active.len());
+        assert_eq!(1, active[0].amount);
+
+        Ok(())
+    }
+
+    /// Fill a chat with data of every kind, including deleted and settled rows.
+    fn fill_chat(database: &mut SqliteDatabase, chat_id: i64) -> anyhow::Result<()> {
+        database.add_participants_if_not_exist(chat_id, &["aa", "bb", "cc"])?;
+        database.add_aliases_if_not_exist(chat_id, "aa", &["a", "x"])?;
+        database.remove_aliases_if_exist(chat_id, "aa", &["x"])?;
+        database.add_group_if_not_exists(chat_id, "all", false)?;
+        database.add_group_if_not_exists(chat_id, "home", true)?;
+        database.add_group_members_if_not_exist(chat_id, "all", &["aa", "bb", "cc"])?;
+        database.remove_group_members_if_exist(chat_id, "all", &["bb"])?;
+        database.add_group_if_not_exists(chat_id, "everyone", false)?;
+        database.add_group_members_if_not_exist(chat_id, "everyone", &["#all", "#home"])?;
+        database.remove_group_members_if_exist(chat_id, "everyone", &["#home"])?;
+        let constraint = SettlementConstraint::OnlyPays("cc".to_string(), "aa".to_string());
+        database.add_settlement_constraint_if_not_exists(chat_id, &constraint)?;
+        database.remove_settlement_constraint_if_exists(chat_id, &constraint)?;
+        database.add_settlement_constraint_if_not_exists(
+            chat_id,
+            &SettlementConstraint::NoRouting("bb".to_string()),
+        )?;
+
+        let expense = |creditor: &str, debtor: &str, amount| {
+            ParsedExpense::new(
+                vec![
+                    ParsedParticipant::new_creditor(creditor, None),
+                    ParsedParticipant::new_debtor(debtor, Some(amount)),
+                ],
+                amount,
+                Some(format!("{creditor} pays")),
+            )
+        };
+        database.save_expense_with_message(chat_id, expense("aa", "cc", 1), Utc::now())?;
+        database.mark_all_as_settled(chat_id)?;
+        database.save_expense_with_message(chat_id, expense("bb", "cc", 2), Utc::now())?;
+        database.save_expense_with_message(chat_id, expense("cc", "aa", 3), Utc::now())?;
+        let expenses = database.get_expenses(chat_id, true)?;
+        let id = expenses.iter().find(|e| e.amount == 2).expect("test").id;
+        database.delete_expense(chat_id, id)?;
+
+        // A participant that is removed and then added again.
+        database.remove_participants_if_exist(chat_id, &["cc"])?;
+        database.add_participants_if_not_exist(chat_id, &["cc"])?;
+
+        database.set_setting(chat_id, Setting::Language(Language::Italian))?;
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_backup_round_trip() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+        let (mut other_database, _other_tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        fill_chat(&mut database, chat_id)?;
+
+        let backup = database.get_backup(chat_id)?;
+        assert_eq!(4, backup.participants.len());
+        assert_eq!(2, backup.aliases.len());
+        assert_eq!(3, backup.group_members.len());
+        assert_eq!(2, backup.group_subgroups.len());
+        assert_eq!(3, backup.expenses.len());
+        assert_eq!(2, backup.settlement_constraints.len());
+        assert!(backup.expenses[0].settled_at.is_some());
+        assert!(backup.expenses[1].deleted_at.is_some());
+
+        // In a new database IDs start from the beginning, so they are the same.
+        assert!(other_database.is_chat_empty(chat_id)?);
+        other_database.restore_backup(chat_id, &backup)?;
+        assert!(!other_database.is_chat_empty(chat_id)?);
+        assert_eq!(backup, other_database.get_backup(chat_id)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_restore_backup_with_new_ids() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        let new_chat_id = 2;
+        fill_chat(&mut database, chat_id)?;
+
+        let backup = database.get_backup(chat_id)?;
+        database.restore_backup(new_chat_id, &backup)?;
+        let restored = database.get_backup(new_chat_id)?;
+
+        // All rows got new IDs.
+        let ids: HashSet<_> = backup.participants.iter().map(|p| p.id).collect();
+        assert!(restored.participants.iter().all(|p| !ids.contains(&p.id)));
+        let ids: HashSet<_> = backup.expenses.iter().map(|e| e.id).collect();
+        assert!(restored.expenses.iter().all(|e| !ids.contains(&e.id)));
+
+        // Everything else is the same.
+        assert_eq!(
+            to_hash_set(database.get_participants(chat_id)?),
+            to_hash_set(database.get_participants(new_chat_id)?)
+        );
+        assert_eq!(
+            database.get_aliases(chat_id)?,
+            database.get_aliases(new_chat_id)?
+        );
+        assert_eq!(
+            to_hash_set(database.get_group_members(chat_id, "all")?),
+            to_hash_set(database.get_group_members(new_chat_id, "all")?)
+        );
+        assert_eq!(
+            database.get_group_members(chat_id, "everyone")?,
+            database.get_group_members(new_chat_id, "everyone")?
+        );
+        assert_eq!(
+            database.get_settings(chat_id)?,
+            database.get_settings(new_chat_id)?
+        );
+
+        let describe = |expenses: Vec<SavedExpense>| {
+            let mut expenses: Vec<_> = expenses
+                .into_iter()
+                .map(|e| {
+                    let mut participants: Vec<_> = e
+                        .participants
+                        .iter()
+                        .map(|p| (p.name.clone(), p.is_creditor(), p.amount))
+                        .collect();
+                    participants.sort();
+                    (e.amount, e.is_active, e.message, e.message_ts, participants)
+                })
+                .collect();
+            expenses.sort();
+            expenses
+        };
+        assert_eq!(
+            describe(database.get_expenses(chat_id, false)?),
+            describe(database.get_expenses(new_chat_id, false)?)
+        );
+        assert_eq!(1, database.get_expenses(new_chat_id, true)?.len());
+
+        // The history of removed participants is kept.
+        let removed = restored
+            .participants
+            .iter()
+            .filter(|p| p.name == "cc" && p.deleted_at.is_some())
+            .count();
+        assert_eq!(1, removed);
+
+        Ok(())
+    }
+
     #[test]
     #[ignore]
     fn test_add_and_remove_participants() -> anyhow::Result<()> {
@@ -876,11 +1879,11 @@ mod tests {
         database.add_participants_if_not_exist(chat_id, participants)?;
 
         let group1 = "all";
-        database.add_group_if_not_exists(chat_id, group1)?;
+        database.add_group_if_not_exists(chat_id, group1, false)?;
         database.add_group_members_if_not_exist(chat_id, group1, &["aa", "bb"])?;
 
         let group2 = "g2";
-        database.add_group_if_not_exists(chat_id, group2)?;
+        database.add_group_if_not_exists(chat_id, group2, true)?;
 
         assert_eq!(
             to_hash_set(vec!["all", "g2"]),
@@ -901,6 +1904,379 @@ mod tests {
         assert_eq!(2, g2_members.len());
         assert_eq!(to_hash_set(vec!["ee", "bb"]), to_hash_set(g2_members));
 
+        assert_eq!(
+            vec![Household {
+                name: group2.to_string(),
+                members: vec!["bb".to_string(), "ee".to_string()],
+            }],
+            database.get_households(chat_id)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_rename() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        database.add_participants_if_not_exist(chat_id, &["aa", "bb", "cc"])?;
+        database.add_aliases_if_not_exist(chat_id, "aa", &["a", "x"])?;
+        database.add_aliases_if_not_exist(chat_id, "bb", &["b"])?;
+        database.add_group_if_not_exists(chat_id, "g1", false)?;
+        database.add_group_if_not_exists(chat_id, "g2", false)?;
+        database.add_group_members_if_not_exist(chat_id, "g1", &["aa", "bb"])?;
+        database.add_group_members_if_not_exist(chat_id, "g2", &["#g1"])?;
+        let expense = ParsedExpense::new(
+            vec![
+                ParsedParticipant::new_creditor("aa", None),
+                ParsedParticipant::new_debtor("bb", None),
+            ],
+            1000,
+            None,
+        );
+        database.save_expense_with_message(chat_id, expense, Utc::now())?;
+
+        // The new name cannot be used by another participant or by their aliases.
+        let result = database.rename_participant(chat_id, "aa", "cc", false);
+        assert!(matches!(result, Err(e) if e.is_concurrency_error()));
+        let result = database.rename_participant(chat_id, "aa", "b", false);
+        assert!(matches!(result, Err(e) if e.is_concurrency_error()));
+
+        // An alias of the participant can become their name.
+        database.rename_participant(chat_id, "aa", "x", false)?;
+        database.rename_participant(chat_id, "bb", "dd", true)?;
+        assert_eq!(
+            to_hash_set(vec!["x", "dd", "cc"]),
+            to_hash_set(database.get_participants(chat_id)?)
+        );
+        assert_eq!(
+            to_hash_set(vec!["a"]),
+            to_hash_set(database.get_participant_aliases(chat_id, "x")?)
+        );
+        assert_eq!(
+            to_hash_set(vec!["b", "bb"]),
+            to_hash_set(database.get_participant_aliases(chat_id, "dd")?)
+        );
+        assert_eq!(
+            to_hash_set(vec!["x", "dd"]),
+            to_hash_set(database.get_group_members(chat_id, "g1")?)
+        );
+        let expenses = database.get_expenses(chat_id, true)?;
+        let names: Vec<_> = expenses[0]
+            .participants
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect();
+        assert_eq!(to_hash_set(vec!["x", "dd"]), to_hash_set(names));
+
+        let result = database.rename_group(chat_id, "g1", "g2");
+        assert!(matches!(result, Err(e) if e.is_concurrency_error()));
+        database.rename_group(chat_id, "g1", "g3")?;
+        assert_eq!(
+            to_hash_set(vec!["g2", "g3"]),
+            to_hash_set(database.get_groups(chat_id)?)
+        );
+        assert_eq!(vec!["#g3"], database.get_group_members(chat_id, "g2")?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_merge_participants() -> anyhow::Result<()> {
+        use crate::bot_logic::compute_net_positions;
+
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        database.add_participants_if_not_exist(chat_id, &["aa", "bb", "cc"])?;
+        database.add_aliases_if_not_exist(chat_id, "bb", &["b"])?;
+        database.add_group_if_not_exists(chat_id, "g1", false)?;
+        database.add_group_if_not_exists(chat_id, "g2", false)?;
+        database.add_group_members_if_not_exist(chat_id, "g1", &["bb"])?;
+        database.add_group_members_if_not_exist(chat_id, "g2", &["aa", "bb"])?;
+        database.add_settlement_constraint_if_not_exists(
+            chat_id,
+            &SettlementConstraint::Forbidden("aa".to_string(), "bb".to_string()),
+        )?;
+        database.add_settlement_constraint_if_not_exists(
+            chat_id,
+            &SettlementConstraint::NoRouting("bb".to_string()),
+        )?;
+
+        let expense = |creditor: &str, debtors: &[&str], amount| {
+            let mut participants = vec![ParsedParticipant::new_creditor(creditor, None)];
+            for debtor in debtors {
+                participants.push(ParsedParticipant::new_debtor(debtor, None));
+            }
+            ParsedExpense::new(participants, amount, None)
+        };
+        for (creditor, debtors, amount) in [
+            ("aa", &["bb", "cc"][..], 3000),
+            ("cc", &["aa", "bb"], 1000),
+            ("bb", &["cc"], 500),
+        ] {
+            database.save_expense_with_message(
+                chat_id,
+                expense(creditor, debtors, amount),
+                Utc::now(),
+            )?;
+        }
+        let positions = |database: &SqliteDatabase| -> anyhow::Result<_> {
+            Ok(compute_net_positions(database.get_expenses(chat_id, true)?)
+                .into_iter()
+                .map(|p| (p.participant, p.amount))
+                .collect::<HashMap<_, _>>())
+        };
+        let before = positions(&database)?;
+
+        database.merge_participants(chat_id, "aa", "bb")?;
+
+        assert_eq!(
+            to_hash_set(vec!["aa", "cc"]),
+            to_hash_set(database.get_participants(chat_id)?)
+        );
+        assert_eq!(
+            to_hash_set(vec!["b", "bb"]),
+            to_hash_set(database.get_participant_aliases(chat_id, "aa")?)
+        );
+        assert_eq!(vec!["aa"], database.get_group_members(chat_id, "g1")?);
+        assert_eq!(vec!["aa"], database.get_group_members(chat_id, "g2")?);
+        assert_eq!(
+            vec![SettlementConstraint::NoRouting("aa".to_string())],
+            database.get_settlement_constraints(chat_id)?
+        );
+
+        let after = positions(&database)?;
+        assert_eq!(2, after.len());
+        assert_eq!(before["aa"] + before["bb"], after["aa"]);
+        assert_eq!(before["cc"], after["cc"]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_subgroups() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        database.add_participants_if_not_exist(chat_id, &["aa", "bb"])?;
+        for group in ["g1", "g2", "g3"] {
+            database.add_group_if_not_exists(chat_id, group, false)?;
+        }
+        database.add_group_members_if_not_exist(chat_id, "g1", &["aa", "#g2"])?;
+        database.add_group_members_if_not_exist(chat_id, "g2", &["#g3", "#g3"])?;
+        database.add_group_members_if_not_exist(chat_id, "g3", &["bb"])?;
+
+        assert_eq!(
+            to_hash_set(vec!["aa", "#g2"]),
+            to_hash_set(database.get_group_members(chat_id, "g1")?)
+        );
+        assert_eq!(vec!["#g3"], database.get_group_members(chat_id, "g2")?);
+
+        // A group cannot contain itself, directly or not.
+        let result = database.add_group_members_if_not_exist(chat_id, "g3", &["#g1"]);
+        assert!(matches!(result, Err(e) if e.is_concurrency_error()));
+        let result = database.add_group_members_if_not_exist(chat_id, "g1", &["#g1"]);
+        assert!(matches!(result, Err(e) if e.is_concurrency_error()));
+        assert_eq!(vec!["bb"], database.get_group_members(chat_id, "g3")?);
+
+        // Once the cycle is broken, the group can be added.
+        database.remove_group_members_if_exist(chat_id, "g2", &["#g3"])?;
+        database.add_group_members_if_not_exist(chat_id, "g3", &["#g1"])?;
+        assert_eq!(
+            to_hash_set(vec!["bb", "#g1"]),
+            to_hash_set(database.get_group_members(chat_id, "g3")?)
+        );
+
+        // Removed groups are not members anymore.
+        database.remove_group_if_exists(chat_id, "g1")?;
+        assert_eq!(vec!["bb"], database.get_group_members(chat_id, "g3")?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_settlement_constraints() -> anyhow::Result<()> {
+        use SettlementConstraint::*;
+
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        database.add_participants_if_not_exist(chat_id, &["aa", "bb", "cc"])?;
+
+        let only_pays = OnlyPays("aa".to_string(), "bb".to_string());
+        let forbidden = Forbidden("bb".to_string(), "cc".to_string());
+        let no_routing = NoRouting("cc".to_string());
+        for constraint in [&only_pays, &forbidden, &no_routing, &only_pays] {
+            database.add_settlement_constraint_if_not_exists(chat_id, constraint)?;
+        }
+        // Forbidden pairs are the same in both orders.
+        let reversed = Forbidden("cc".to_string(), "bb".to_string());
+        database.add_settlement_constraint_if_not_exists(chat_id, &reversed)?;
+        assert_eq!(
+            vec![only_pays.clone(), forbidden, no_routing.clone()],
+            database.get_settlement_constraints(chat_id)?
+        );
+        assert!(database.get_settlement_constraints(2)?.is_empty());
+
+        database.remove_settlement_constraint_if_exists(chat_id, &reversed)?;
+        assert_eq!(
+            vec![only_pays, no_routing.clone()],
+            database.get_settlement_constraints(chat_id)?
+        );
+
+        // Constraints of removed participants are ignored.
+        database.remove_participants_if_exist(chat_id, &["bb"])?;
+        assert_eq!(
+            vec![no_routing],
+            database.get_settlement_constraints(chat_id)?
+        );
+
+        let unknown = NoRouting("dd".to_string());
+        assert!(database
+            .add_settlement_constraint_if_not_exists(chat_id, &unknown)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_settings() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+
+        assert_eq!(ChatSettings::default(), database.get_settings(chat_id)?);
+
+        database.set_setting(chat_id, Setting::Language(Language::Italian))?;
+        database.set_setting(chat_id, Setting::PageSize(5))?;
+        database.set_setting(chat_id, Setting::PageSize(25))?;
+        database.set_setting(chat_id, Setting::DecimalSeparator(Some('.')))?;
+
+        let settings = database.get_settings(chat_id)?;
+        assert_eq!(Language::Italian, settings.language);
+        assert_eq!(25, settings.page_size);
+        assert_eq!('.', settings.decimal_separator());
+        assert!(!settings.auto_register);
+
+        // Going back to the default value removes the setting.
+        database.set_setting(chat_id, Setting::DecimalSeparator(None))?;
+        assert_eq!(',', database.get_settings(chat_id)?.decimal_separator());
+
+        // Invalid values are ignored.
+        database.connection.execute(
+            "UPDATE chat_setting SET value = 'x' WHERE chat_id = ?1 AND key = 'page_size'",
+            params![&chat_id],
+        )?;
+        assert_eq!(
+            ChatSettings::default().page_size,
+            database.get_settings(chat_id)?.page_size
+        );
+
+        // Other chats are not affected.
+        assert_eq!(ChatSettings::default(), database.get_settings(2)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_take_pending_expense() -> anyhow::Result<()> {
+        let (mut database, _tmp_dir) = temp_database()?;
+
+        let chat_id = 1;
+        let message_ts: DateTime<Utc> =
+            DateTime::parse_from_rfc3339("2023-05-01T22:30:00Z")?.into();
+        database.add_pending_expense(chat_id, 10, "aa 5 - pizza", message_ts)?;
+
+        assert_eq!(None, database.take_pending_expense(chat_id, 11)?);
+        assert_eq!(None, database.take_pending_expense(2, 10)?);
+        assert_eq!(
+            Some(("aa 5 - pizza".to_string(), message_ts)),
+            database.take_pending_expense(chat_id, 10)?
+        );
+        // The expense can be taken only once.
+        assert_eq!(None, database.take_pending_expense(chat_id, 10)?);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_migrate_chat_flags() -> anyhow::Result<()> {
+        let tmp_dir = TempDir::new("treasurer")?;
+        let file_path = tmp_dir.path().join("treasurer.db");
+
+        // The table used by older versions of the bot to save the auto register flag.
+        {
+            let connection = Connection::open(&file_path)?;
+            connection.execute(
+                "CREATE TABLE chat_flag (
+                   chat_id INTEGER NOT NULL,
+                   auto_register BOOL NOT NULL DEFAULT FALSE,
+                   UNIQUE(chat_id)
+                 )",
+                (),
+            )?;
+            connection.execute(
+                "INSERT INTO chat_flag (chat_id, auto_register) VALUES (1, TRUE), (2, FALSE)",
+                (),
+            )?;
+        }
+
+        let database = SqliteDatabase::new(&file_path)?;
+        assert!(database.get_settings(1)?.auto_register);
+        assert!(!database.get_settings(2)?.auto_register);
+
+        // Opening the database again does not migrate twice.
+        drop(database);
+        let database = SqliteDatabase::new(&file_path)?;
+        assert!(database.get_settings(1)?.auto_register);
+
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn test_migrate_chat_flags_with_language() -> anyhow::Result<()> {
+        let tmp_dir = TempDir::new("treasurer")?;
+        let file_path = tmp_dir.path().join("treasurer.db");
+
+        // Later versions of the bot also saved the language in the same table.
+        {
+            let connection = Connection::open(&file_path)?;
+            connection.execute(
+                "CREATE TABLE chat_flag (
+                   chat_id INTEGER NOT NULL,
+                   auto_register BOOL NOT NULL DEFAULT FALSE,
+                   language TEXT,
+                   UNIQUE(chat_id)
+                 )",
+                (),
+            )?;
+            connection.execute(
+                "INSERT INTO chat_flag (chat_id, auto_register, language) \
+                 VALUES (1, TRUE, 'it'), (2, FALSE, NULL)",
+                (),
+            )?;
+        }
+
+        let database = SqliteDatabase::new(&file_path)?;
+
+        let settings = database.get_settings(1)?;
+        assert!(settings.auto_register);
+        assert_eq!(Language::Italian, settings.language);
+
+        let settings = database.get_settings(2)?;
+        assert!(!settings.auto_register);
+        assert_eq!(Language::default(), settings.language);
+
         Ok(())
     }
 
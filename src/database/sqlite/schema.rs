@@ -39,6 +39,7 @@ const CREATE_GROUP_TABLE: &str = "CREATE TABLE IF NOT EXISTS participant_group (
   id INTEGER PRIMARY KEY AUTOINCREMENT,
   chat_id INTEGER NOT NULL,
   name TEXT NOT NULL,
+  is_household BOOL NOT NULL DEFAULT FALSE,
   created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
   deleted_at DATETIME,
   UNIQUE(chat_id, name, deleted_at)
@@ -52,12 +53,52 @@ const CREATE_GROUP_MEMBER_TABLE: &str = "CREATE TABLE IF NOT EXISTS group_member
   UNIQUE(group_id, participant_id, deleted_at)
 )";
 
-const CREATE_AUTO_REGISTER_FLAG_TABLE: &str = "CREATE TABLE IF NOT EXISTS chat_flag (
+/// Groups that are members of other groups. The database never contains cycles.
+const CREATE_GROUP_SUBGROUP_TABLE: &str = "CREATE TABLE IF NOT EXISTS group_subgroup (
+  group_id INTEGER NOT NULL,
+  subgroup_id INTEGER NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  deleted_at DATETIME,
+  UNIQUE(group_id, subgroup_id, deleted_at)
+)";
+
+/// Constraints refer to participants by ID, so they follow them if they are renamed. The
+/// other participant is NULL for kinds of constraint that are about a single participant.
+const CREATE_SETTLEMENT_CONSTRAINT_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS settlement_constraint (
+  chat_id INTEGER NOT NULL,
+  kind TEXT NOT NULL,
+  participant_id INTEGER NOT NULL,
+  other_participant_id INTEGER,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  deleted_at DATETIME
+)";
+
+const CREATE_CHAT_SETTING_TABLE: &str = "CREATE TABLE IF NOT EXISTS chat_setting (
+  chat_id INTEGER NOT NULL,
+  key TEXT NOT NULL,
+  value TEXT NOT NULL,
+  UNIQUE(chat_id, key)
+)";
+
+const CREATE_DIALOGUE_TABLE: &str = "CREATE TABLE IF NOT EXISTS dialogue (
   chat_id INTEGER NOT NULL,
-  auto_register BOOL NOT NULL DEFAULT FALSE,
+  state TEXT NOT NULL,
+  updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
   UNIQUE(chat_id)
 )";
 
+/// Expenses written as sentences, waiting for the user to confirm them with a button of
+/// the given message.
+const CREATE_PENDING_EXPENSE_TABLE: &str = "CREATE TABLE IF NOT EXISTS pending_expense (
+  chat_id INTEGER NOT NULL,
+  message_id INTEGER NOT NULL,
+  expense TEXT NOT NULL,
+  message_ts DATETIME NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE(chat_id, message_id)
+)";
+
 pub fn create_all_tables(connection: &rusqlite::Connection) -> anyhow::Result<()> {
     connection.execute(CREATE_PARTICIPANT_TABLE, ())?;
     connection.execute(CREATE_ALIAS_TABLE, ())?;
@@ -65,6 +106,66 @@ pub fn create_all_tables(connection: &rusqlite::Connection) -> anyhow::Result<()
     connection.execute(CREATE_EXPENSE_PARTICIPANT_TABLE, ())?;
     connection.execute(CREATE_GROUP_TABLE, ())?;
     connection.execute(CREATE_GROUP_MEMBER_TABLE, ())?;
-    connection.execute(CREATE_AUTO_REGISTER_FLAG_TABLE, ())?;
+    connection.execute(CREATE_GROUP_SUBGROUP_TABLE, ())?;
+    connection.execute(CREATE_SETTLEMENT_CONSTRAINT_TABLE, ())?;
+    connection.execute(CREATE_CHAT_SETTING_TABLE, ())?;
+    connection.execute(CREATE_DIALOGUE_TABLE, ())?;
+    connection.execute(CREATE_PENDING_EXPENSE_TABLE, ())?;
+    migrate_chat_flags(connection)?;
+    migrate_household_groups(connection)?;
+    Ok(())
+}
+
+/// Older versions of the bot saved the auto register flag and the language in a dedicated
+/// `chat_flag` table: move them to the settings table.
+fn migrate_chat_flags(connection: &rusqlite::Connection) -> anyhow::Result<()> {
+    let exists: bool = connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'chat_flag')",
+        (),
+        |row| row.get(0),
+    )?;
+
+    if exists {
+        // The language column was added later, so it may be missing.
+        let copy_language = if column_exists(connection, "chat_flag", "language")? {
+            "INSERT OR IGNORE INTO chat_setting (chat_id, key, value)
+               SELECT chat_id, 'language', language
+               FROM chat_flag WHERE language IS NOT NULL;"
+        } else {
+            ""
+        };
+        connection.execute_batch(&format!(
+            "BEGIN;
+             INSERT OR IGNORE INTO chat_setting (chat_id, key, value)
+               SELECT chat_id, 'auto_register', CASE WHEN auto_register THEN 'true' ELSE 'false' END
+               FROM chat_flag;
+             {copy_language}
+             DROP TABLE chat_flag;
+             COMMIT;"
+        ))?;
+    }
     Ok(())
 }
+
+/// Older versions of the bot had no households: add the flag to their groups.
+fn migrate_household_groups(connection: &rusqlite::Connection) -> anyhow::Result<()> {
+    if !column_exists(connection, "participant_group", "is_household")? {
+        connection.execute(
+            "ALTER TABLE participant_group ADD COLUMN is_household BOOL NOT NULL DEFAULT FALSE",
+            (),
+        )?;
+    }
+    Ok(())
+}
+
+fn column_exists(
+    connection: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+) -> anyhow::Result<bool> {
+    Ok(connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        (table, column),
+        |row| row.get(0),
+    )?)
+}
@@ -8,45 +8,80 @@ use chrono::{DateTime, Utc};
 use log::debug;
 use std::{
     cmp::Ordering,
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     sync::Arc,
 };
 use tokio::sync::Mutex;
 
 use crate::{
-    bot_logic::compute_exchanges,
+    backup::{read_backup, write_backup},
+    bot_logic::{
+        compute_constrained_exchanges, compute_net_positions, compute_pairwise_debt,
+        explain_balance,
+    },
+    chart::draw_chart,
     database::Database,
     error::{DatabaseError, InputError},
-    formatter::{format_balance, format_list_expenses, format_simple_list},
+    export::{export_expenses, ExportedFile},
+    formatter::{
+        format_balance, format_between, format_group_tree, format_impossible_settlement,
+        format_list_expenses, format_net_positions, format_parsed_expense, format_past_balance,
+        format_settlement_constraints, format_simple_list, format_why,
+    },
+    i18n::Language,
+    import::{read_expenses, ImportOptions, ImportReport},
     parser::{
-        parse_expense, parse_group_and_members, parse_participant_and_aliases, parse_participants,
+        parse_add_group, parse_balance_options, parse_between, parse_chart_options, parse_expense,
+        parse_export_options, parse_group_and_members, parse_merge_participants,
+        parse_natural_expense, parse_participant_and_aliases, parse_participants,
+        parse_rename_group, parse_rename_participant, parse_report_options,
+        parse_settlement_constraint,
+    },
+    report::make_report,
+    settings::{parse_timezone, Setting},
+    types::{
+        BalanceOptions, GroupTree, HistoryPoint, ImportedExpense, ParsedExpense, ParsedParticipant,
+        SettlementConstraint,
     },
-    types::{ParsedExpense, ParsedParticipant},
     validator::{
         validate_alias_names, validate_aliases_do_not_exist, validate_aliases_exist,
         validate_expense, validate_group_exists, validate_group_name, validate_groups,
-        validate_participant_exists, validate_participant_name, validate_participant_names,
-        validate_participants_exist,
+        validate_household_members, validate_no_group_cycles, validate_participant_exists,
+        validate_participant_name, validate_participant_names, validate_participants_exist,
+        validate_same_household,
     },
 };
 
+/// The argument of `/timezone` that selects the timezone of the server.
+const SERVER_TIMEZONE: &str = "server";
+
+/// If the expense is written as a sentence, return it in the syntax of `/expense`,
+/// so that the user can confirm it before it is saved.
+pub async fn interpret_expense<D: Database>(
+    chat_id: i64,
+    message: &str,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<Option<String>> {
+    match parse_natural_expense(message) {
+        Some(expense) => {
+            let settings = database.lock().await.get_settings(chat_id)?;
+            Ok(Some(format_parsed_expense(&expense, &settings)))
+        }
+        None => Ok(None),
+    }
+}
+
 pub async fn handle_expense<D: Database>(
     chat_id: i64,
     message: &str,
     database: &Arc<Mutex<D>>,
     message_ts: DateTime<Utc>,
 ) -> anyhow::Result<()> {
-    let expense = parse_expense(message).map_err(InputError::invalid_expense_syntax)?;
-    let expense = expense.1;
-    validate_groups(&expense, chat_id, database).await?;
-    let expense = resolve_groups(expense, chat_id, database).await?;
-    let expense = resolve_aliases(expense, chat_id, database).await?;
-
-    validate_expense(&expense)?;
-    let expense = normalize_participants(expense);
+    let expense = parse_expense(message)?;
+    let expense = prepare_expense(expense, chat_id, database).await?;
 
     let participants: Vec<_> = expense.participants.iter().map(|p| &p.name).collect();
-    if database.lock().await.is_auto_register_active(chat_id)? {
+    if database.lock().await.get_settings(chat_id)?.auto_register {
         database
             .lock()
             .await
@@ -63,22 +98,110 @@ pub async fn handle_expense<D: Database>(
     Ok(())
 }
 
-/// Replace groups with their participants.
+/// Validate an expense and replace groups and aliases, so that it is ready to be saved.
+///
+/// Participants are not checked, since they may be registered automatically.
+async fn prepare_expense<D: Database>(
+    expense: ParsedExpense,
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<ParsedExpense> {
+    validate_groups(&expense, chat_id, database).await?;
+    let expense = resolve_groups(expense, chat_id, database).await?;
+    let expense = resolve_aliases(expense, chat_id, database).await?;
+
+    validate_expense(&expense)?;
+    Ok(normalize_participants(expense))
+}
+
+/// Import the expenses of a file.
+///
+/// Each expense goes through the same checks as `/expense`. If any expense is not
+/// valid, or if this is a dry run, nothing is saved.
+pub async fn handle_import<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    content: &str,
+    options: ImportOptions,
+) -> anyhow::Result<ImportReport> {
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let read_expenses = read_expenses(content, options.format, &settings)?;
+    debug!("Importing {} expenses: {:?}", read_expenses.len(), options);
+
+    let register_participants = settings.auto_register || options.format.registers_participants();
+
+    let mut expenses = Vec::with_capacity(read_expenses.len());
+    let mut participants_to_register = HashSet::new();
+    let mut errors = vec![];
+    for (line, imported) in read_expenses {
+        let result =
+            check_imported_expense(imported, chat_id, database, register_participants).await;
+        match result {
+            Ok(imported) => {
+                if register_participants {
+                    for participant in &imported.expense.participants {
+                        participants_to_register.insert(participant.name.clone());
+                    }
+                }
+                expenses.push(imported);
+            }
+            Err(e) => errors.push((line, e)),
+        }
+    }
+
+    let num_expenses = expenses.len();
+    if errors.is_empty() && !options.dry_run {
+        let participants_to_register: Vec<_> = participants_to_register.into_iter().collect();
+        database.lock().await.save_imported_expenses(
+            chat_id,
+            expenses,
+            &participants_to_register,
+        )?;
+    }
+
+    Ok(ImportReport {
+        dry_run: options.dry_run,
+        num_expenses,
+        errors,
+    })
+}
+
+async fn check_imported_expense<D: Database>(
+    imported: Result<ImportedExpense, InputError>,
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    register_participants: bool,
+) -> anyhow::Result<ImportedExpense> {
+    let mut imported = imported?;
+    imported.expense = prepare_expense(imported.expense, chat_id, database).await?;
+    if !register_participants {
+        let participants: Vec<_> = imported
+            .expense
+            .participants
+            .iter()
+            .map(|p| &p.name)
+            .collect();
+        validate_participants_exist(&participants, chat_id, database).await?;
+    }
+    Ok(imported)
+}
+
+/// Replace groups with their participants, including those of their subgroups.
 async fn resolve_groups<D: Database>(
     mut expense: ParsedExpense,
     chat_id: i64,
     database: &Arc<Mutex<D>>,
 ) -> Result<ParsedExpense, DatabaseError> {
+    if !expense.participants.iter().any(|p| p.is_group()) {
+        return Ok(expense);
+    }
+
+    let all_members = get_all_group_members(chat_id, database).await?;
     let mut participants = Vec::with_capacity(expense.participants.len());
 
     for participant in expense.participants {
         if participant.is_group() {
-            let members = database
-                .lock()
-                .await
-                .get_group_members(chat_id, &participant.name)?;
-
-            for member in members {
+            for member in expand_group(&participant.name, &all_members) {
                 let p = if participant.is_creditor() {
                     ParsedParticipant::new_creditor(&member, None)
                 } else {
@@ -95,6 +218,75 @@ async fn resolve_groups<D: Database>(
     Ok(expense)
 }
 
+/// Get the direct members of every group, where groups that are members start with `#`.
+async fn get_all_group_members<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> Result<HashMap<String, Vec<String>>, DatabaseError> {
+    let groups = database.lock().await.get_groups(chat_id)?;
+    let mut all_members = HashMap::with_capacity(groups.len());
+    for group in groups {
+        let members = database.lock().await.get_group_members(chat_id, &group)?;
+        all_members.insert(group, members);
+    }
+    Ok(all_members)
+}
+
+/// Get the participants of a group and of all its subgroups, at any depth. A participant
+/// may be returned more than once, if it is in more than one subgroup.
+fn expand_group(group_name: &str, all_members: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let mut participants = vec![];
+    let mut visited = HashSet::new();
+    let mut to_visit = vec![group_name];
+    while let Some(group) = to_visit.pop() {
+        if !visited.insert(group) {
+            continue;
+        }
+        for member in all_members.get(group).into_iter().flatten() {
+            match member.strip_prefix('#') {
+                Some(subgroup) => to_visit.push(subgroup),
+                None => participants.push(member.clone()),
+            }
+        }
+    }
+    participants
+}
+
+/// Build the tree of the members of a group, sorted by name.
+fn make_group_tree(group_name: &str, all_members: &HashMap<String, Vec<String>>) -> GroupTree {
+    do_make_group_tree(group_name, all_members, &mut HashSet::new())
+}
+
+/// The database does not allow cycles, but a group that contains one of its ancestors
+/// is still shown without its members, so that the recursion always ends.
+fn do_make_group_tree<'a>(
+    group_name: &'a str,
+    all_members: &'a HashMap<String, Vec<String>>,
+    ancestors: &mut HashSet<&'a str>,
+) -> GroupTree {
+    let mut participants = vec![];
+    let mut subgroups = vec![];
+    if ancestors.insert(group_name) {
+        for member in all_members.get(group_name).into_iter().flatten() {
+            match member.strip_prefix('#') {
+                Some(subgroup) => {
+                    subgroups.push(do_make_group_tree(subgroup, all_members, ancestors))
+                }
+                None => participants.push(member.clone()),
+            }
+        }
+        ancestors.remove(group_name);
+    }
+    participants.sort();
+    subgroups.sort_by(|g1, g2| g1.name.cmp(&g2.name));
+
+    GroupTree {
+        name: group_name.to_string(),
+        participants,
+        subgroups,
+    }
+}
+
 /// Replace aliases with the corresponding participant.
 async fn resolve_aliases<D: Database>(
     mut expense: ParsedExpense,
@@ -139,26 +331,132 @@ fn normalize_participants(expense: ParsedExpense) -> ParsedExpense {
     }
 
     ParsedExpense::new(
-        participants.into_iter().map(|(_, p)| p).collect(),
+        participants.into_values().collect(),
         expense.amount,
         expense.message,
     )
 }
 
+/// Show the current balance or, if asked, the balance at a point of the past.
 pub async fn handle_balance<D: Database>(
     chat_id: i64,
     database: &Arc<Mutex<D>>,
+    payload: &str,
 ) -> anyhow::Result<String> {
-    let active_expenses = database.lock().await.get_expenses(chat_id, true)?;
-    let mut exchanges = compute_exchanges(active_expenses);
-    exchanges.sort_by(|e1, e2| match e1.debtor.cmp(&e2.debtor) {
-        Ordering::Equal => e1.creditor.cmp(&e2.creditor),
-        o => o,
-    });
-    let formatted_balance = format_balance(&exchanges);
+    let options = parse_balance_options(payload)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let catalog = settings.language.catalog();
+
+    let (expenses, title) = match options {
+        BalanceOptions::Current => (database.lock().await.get_expenses(chat_id, true)?, None),
+        BalanceOptions::AtDate(date) => {
+            // The balance at the end of the day is the one when the next day starts.
+            let end = date
+                .succ_opt()
+                .map(|next_day| settings.start_of_day(next_day))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+            let expenses = database
+                .lock()
+                .await
+                .get_expenses_at(chat_id, HistoryPoint::At(end))?;
+            let date = date.format(catalog.date_format()).to_string();
+            (expenses, Some(catalog.balance_at(&date)))
+        }
+        BalanceOptions::BeforeExpense(expense_id) => {
+            let expenses = database
+                .lock()
+                .await
+                .get_expenses_at(chat_id, HistoryPoint::BeforeExpense(expense_id))?;
+            (expenses, Some(catalog.balance_before(expense_id)))
+        }
+    };
+
+    let constraints = database.lock().await.get_settlement_constraints(chat_id)?;
+    let households = database.lock().await.get_households(chat_id)?;
+    let formatted_balance = match compute_constrained_exchanges(expenses, &constraints, &households)
+    {
+        Ok(mut exchanges) => {
+            exchanges.sort_by(|e1, e2| match e1.debtor.cmp(&e2.debtor) {
+                Ordering::Equal => e1.creditor.cmp(&e2.creditor),
+                o => o,
+            });
+            format_balance(&exchanges, &settings)
+        }
+        Err(impossible) => format_impossible_settlement(&impossible, &settings),
+    };
+    let formatted_balance = match title {
+        Some(title) => format_past_balance(&title, &formatted_balance),
+        None => formatted_balance,
+    };
     Ok(formatted_balance)
 }
 
+/// Show how much a participant owes to another one, before the balance is simplified.
+pub async fn handle_between<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<String> {
+    let (debtor, creditor, list) = parse_between(payload)?;
+    let debtor = resolve_participant(chat_id, database, &debtor).await?;
+    let creditor = resolve_participant(chat_id, database, &creditor).await?;
+    if debtor == creditor {
+        return Err(InputError::invalid_between_participants().into());
+    }
+    debug!("Computing the debt between participants: {debtor}, {creditor}");
+
+    let expenses = database.lock().await.get_expenses(chat_id, true)?;
+    let contributions = compute_pairwise_debt(&debtor, &creditor, &expenses);
+    let settings = database.lock().await.get_settings(chat_id)?;
+    Ok(format_between(
+        &debtor,
+        &creditor,
+        &contributions,
+        list,
+        &settings,
+    ))
+}
+
+/// The registered participant with the given name or alias.
+async fn resolve_participant<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    name: &str,
+) -> anyhow::Result<String> {
+    let name = name.trim().trim_start_matches('@').to_lowercase();
+    validate_participant_name(&name)?;
+    let aliases = database.lock().await.get_aliases(chat_id)?;
+    let participant = aliases.get(&name).cloned().unwrap_or(name);
+    validate_participant_exists(&participant, chat_id, database).await?;
+    Ok(participant)
+}
+
+/// Show how much each participant is owed or owes in the current balance.
+pub async fn handle_net<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<String> {
+    let expenses = database.lock().await.get_expenses(chat_id, true)?;
+    let positions = compute_net_positions(expenses);
+    let settings = database.lock().await.get_settings(chat_id)?;
+    Ok(format_net_positions(&positions, &settings))
+}
+
+/// Show how the active expenses that involve a participant make up their balance.
+pub async fn handle_why<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    participant: &str,
+) -> anyhow::Result<String> {
+    let participant = resolve_participant(chat_id, database, participant).await?;
+    debug!("Explaining the balance of participant: {participant}");
+
+    let expenses = database.lock().await.get_expenses(chat_id, true)?;
+    let contributions = explain_balance(&participant, &expenses);
+    let settings = database.lock().await.get_settings(chat_id)?;
+    Ok(format_why(&participant, &contributions, &settings))
+}
+
 /// This method returns the formatted string and a boolean: if the
 /// boolean is true then there are more results available.
 pub async fn handle_list<D: Database>(
@@ -178,12 +476,13 @@ pub async fn handle_list<D: Database>(
             .lock()
             .await
             .get_expenses_with_limit(chat_id, start, limit + 1, only_active)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
 
     if expenses.len() <= limit {
-        let result = format_list_expenses(&expenses);
+        let result = format_list_expenses(&expenses, &settings);
         Ok((result, false))
     } else {
-        let result = format_list_expenses(&expenses[0..limit]);
+        let result = format_list_expenses(&expenses[0..limit], &settings);
         Ok((result, true))
     }
 }
@@ -241,7 +540,8 @@ pub async fn handle_list_participants<D: Database>(
 ) -> anyhow::Result<String> {
     let mut participants = database.lock().await.get_participants(chat_id)?;
     participants.sort();
-    let result = format_simple_list(&participants);
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let result = format_simple_list(&participants, &settings);
     Ok(result)
 }
 
@@ -308,23 +608,67 @@ pub async fn handle_list_participant_aliases<D: Database>(
         .get_participant_aliases(chat_id, participant)?;
     aliases.sort();
 
-    let result = format_simple_list(&aliases);
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let result = format_simple_list(&aliases, &settings);
     Ok(result)
 }
 
+/// Rename a participant, who keeps their expenses, aliases and groups.
+pub async fn handle_rename_participant<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<()> {
+    let (old_name, new_name, keep_alias) = parse_rename_participant(payload)?;
+    validate_participant_name(&old_name)?;
+    validate_participant_name(&new_name)?;
+    debug!("Renaming participant {old_name} to {new_name}. Keep alias: {keep_alias}");
+
+    validate_participant_exists(&old_name, chat_id, database).await?;
+    // The new name can only be an alias of the participant that is renamed.
+    validate_aliases_do_not_exist(&old_name, &[&new_name], chat_id, database).await?;
+
+    database
+        .lock()
+        .await
+        .rename_participant(chat_id, &old_name, &new_name, keep_alias)?;
+    Ok(())
+}
+
+/// Merge two participants that are the same person into the first one.
+pub async fn handle_merge_participants<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<()> {
+    let (keep, drop) = parse_merge_participants(payload)?;
+    validate_participant_name(&keep)?;
+    validate_participant_name(&drop)?;
+    debug!("Merging participant {drop} into {keep}");
+
+    validate_participants_exist(&[&keep, &drop], chat_id, database).await?;
+    validate_same_household(&keep, &drop, chat_id, database).await?;
+
+    database
+        .lock()
+        .await
+        .merge_participants(chat_id, &keep, &drop)?;
+    Ok(())
+}
+
 pub async fn handle_add_group<D: Database>(
     chat_id: i64,
     database: &Arc<Mutex<D>>,
-    group_name: &str,
+    payload: &str,
 ) -> anyhow::Result<()> {
-    let group_name = group_name.trim();
-    validate_group_name(group_name)?;
-    debug!("Creating group named {group_name}");
+    let (group_name, is_household) = parse_add_group(payload)?;
+    validate_group_name(&group_name)?;
+    debug!("Creating group named {group_name}. Household: {is_household}");
 
     database
         .lock()
         .await
-        .add_group_if_not_exists(chat_id, group_name)?;
+        .add_group_if_not_exists(chat_id, &group_name, is_household)?;
     Ok(())
 }
 
@@ -346,6 +690,29 @@ pub async fn handle_remove_group<D: Database>(
     Ok(())
 }
 
+/// Rename a group, which keeps its members and stays a member of the groups it is in.
+pub async fn handle_rename_group<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<()> {
+    let (old_name, new_name) = parse_rename_group(payload)?;
+    validate_group_name(&old_name)?;
+    validate_group_name(&new_name)?;
+    debug!("Renaming group {old_name} to {new_name}");
+
+    validate_group_exists(&old_name, chat_id, database).await?;
+    if database.lock().await.group_exists(chat_id, &new_name)? {
+        return Err(InputError::group_already_registered(new_name).into());
+    }
+
+    database
+        .lock()
+        .await
+        .rename_group(chat_id, &old_name, &new_name)?;
+    Ok(())
+}
+
 pub async fn handle_add_group_members<D: Database>(
     chat_id: i64,
     database: &Arc<Mutex<D>>,
@@ -353,14 +720,23 @@ pub async fn handle_add_group_members<D: Database>(
 ) -> anyhow::Result<()> {
     let (group_name, members) = parse_group_and_members(payload)?;
     validate_group_name(&group_name)?;
-    validate_participant_names(&members)?;
+    let (subgroups, participants) = split_subgroups(&members);
+    validate_participant_names(&participants)?;
+    for subgroup in &subgroups {
+        validate_group_name(subgroup)?;
+    }
     debug!(
         "Adding group members to group named {group_name}. Members: {:#?}",
         members
     );
 
     validate_group_exists(&group_name, chat_id, database).await?;
-    validate_participants_exist(&members, chat_id, database).await?;
+    for subgroup in &subgroups {
+        validate_group_exists(subgroup, chat_id, database).await?;
+    }
+    validate_participants_exist(&participants, chat_id, database).await?;
+    validate_household_members(&group_name, &members, chat_id, database).await?;
+    validate_no_group_cycles(&group_name, &subgroups, chat_id, database).await?;
 
     database
         .lock()
@@ -376,14 +752,21 @@ pub async fn handle_remove_group_members<D: Database>(
 ) -> anyhow::Result<()> {
     let (group_name, members) = parse_group_and_members(payload)?;
     validate_group_name(&group_name)?;
-    validate_participant_names(&members)?;
+    let (subgroups, participants) = split_subgroups(&members);
+    validate_participant_names(&participants)?;
+    for subgroup in &subgroups {
+        validate_group_name(subgroup)?;
+    }
     debug!(
         "Removing group members from group named {group_name}. Members: {:#?}",
         members
     );
 
     validate_group_exists(&group_name, chat_id, database).await?;
-    validate_participants_exist(&members, chat_id, database).await?;
+    for subgroup in &subgroups {
+        validate_group_exists(subgroup, chat_id, database).await?;
+    }
+    validate_participants_exist(&participants, chat_id, database).await?;
 
     database
         .lock()
@@ -392,13 +775,94 @@ pub async fn handle_remove_group_members<D: Database>(
     Ok(())
 }
 
+/// Split the members of a group into subgroups, without the leading `#`, and participants.
+fn split_subgroups(members: &[String]) -> (Vec<String>, Vec<String>) {
+    let (subgroups, participants): (Vec<_>, Vec<_>) =
+        members.iter().cloned().partition(|m| m.starts_with('#'));
+    let subgroups = subgroups.iter().map(|s| s[1..].to_string()).collect();
+    (subgroups, participants)
+}
+
+/// Add a settlement constraint, whose participants must exist.
+pub async fn handle_add_settlement_constraint<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<()> {
+    let constraint = resolve_constraint(chat_id, database, payload).await?;
+    debug!("Adding settlement constraint: {:?}", constraint);
+
+    database
+        .lock()
+        .await
+        .add_settlement_constraint_if_not_exists(chat_id, &constraint)?;
+    Ok(())
+}
+
+pub async fn handle_remove_settlement_constraint<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<()> {
+    let constraint = resolve_constraint(chat_id, database, payload).await?;
+    debug!("Removing settlement constraint: {:?}", constraint);
+
+    database
+        .lock()
+        .await
+        .remove_settlement_constraint_if_exists(chat_id, &constraint)?;
+    Ok(())
+}
+
+pub async fn handle_list_settlement_constraints<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<String> {
+    let constraints = database.lock().await.get_settlement_constraints(chat_id)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    Ok(format_settlement_constraints(&constraints, &settings))
+}
+
+/// Parse a settlement constraint, replacing aliases with the participants they refer to.
+async fn resolve_constraint<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<SettlementConstraint> {
+    let constraint = parse_settlement_constraint(payload)?;
+    let (participant, other) = constraint.participants();
+    let participant = resolve_participant(chat_id, database, participant).await?;
+    let other = match other {
+        Some(other) => Some(resolve_participant(chat_id, database, other).await?),
+        None => None,
+    };
+    SettlementConstraint::from_kind(constraint.kind(), participant, other)
+        .filter(|c| !matches!(c.participants(), (p1, Some(p2)) if p1 == p2))
+        .ok_or_else(|| InputError::invalid_settlement_constraint(payload.trim().to_string()).into())
+}
+
 pub async fn handle_list_groups<D: Database>(
     chat_id: i64,
     database: &Arc<Mutex<D>>,
 ) -> anyhow::Result<String> {
-    let mut groups = database.lock().await.get_groups(chat_id)?;
+    let households = database.lock().await.get_households(chat_id)?;
+    let mut groups: Vec<_> = database
+        .lock()
+        .await
+        .get_groups(chat_id)?
+        .into_iter()
+        .map(|g| {
+            // Households are marked with a house.
+            if households.iter().any(|h| h.name == g) {
+                format!("{g} 🏠")
+            } else {
+                g
+            }
+        })
+        .collect();
     groups.sort();
-    let result = format_simple_list(&groups);
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let result = format_simple_list(&groups, &settings);
     Ok(result)
 }
 
@@ -413,12 +877,175 @@ pub async fn handle_list_group_members<D: Database>(
 
     validate_group_exists(group_name, chat_id, database).await?;
 
-    let mut members = database
+    let all_members = get_all_group_members(chat_id, database).await?;
+    let tree = make_group_tree(group_name, &all_members);
+
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let result = format_group_tree(&tree, &settings);
+    Ok(result)
+}
+
+/// Build the file requested with `/export`.
+pub async fn handle_export<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<ExportedFile> {
+    let options = parse_export_options(payload)?;
+    debug!("Exporting expenses: {:?}", options);
+
+    let expenses = database
         .lock()
         .await
-        .get_group_members(chat_id, group_name)?;
-    members.sort();
+        .get_expenses(chat_id, options.only_active)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    export_expenses(&expenses, options, &settings)
+}
 
-    let result = format_simple_list(&members);
-    Ok(result)
+/// Make the report of the active expenses or, if asked, of all expenses.
+pub async fn handle_report<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<ExportedFile> {
+    let only_active = parse_report_options(payload)?;
+
+    let expenses = database.lock().await.get_expenses(chat_id, only_active)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let constraints = database.lock().await.get_settlement_constraints(chat_id)?;
+    let households = database.lock().await.get_households(chat_id)?;
+    Ok(make_report(&expenses, &constraints, &households, &settings))
+}
+
+/// Draw the charts asked in the options, as PNG images.
+pub async fn handle_chart<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<Vec<Vec<u8>>> {
+    let options = parse_chart_options(payload)?;
+    debug!("Drawing charts: {:?}", options);
+
+    let expenses = database
+        .lock()
+        .await
+        .get_expenses(chat_id, options.only_active)?;
+    let settings = database.lock().await.get_settings(chat_id)?;
+    options
+        .kinds
+        .iter()
+        .map(|kind| draw_chart(*kind, &expenses, &settings))
+        .collect()
+}
+
+pub async fn handle_backup<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<ExportedFile> {
+    let backup = database.lock().await.get_backup(chat_id)?;
+    debug!(
+        "Backing up {} participants and {} expenses",
+        backup.participants.len(),
+        backup.expenses.len()
+    );
+    write_backup(&backup)
+}
+
+/// Restore a backup, only if the chat is empty.
+pub async fn handle_restore<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    content: &str,
+) -> anyhow::Result<String> {
+    let backup = read_backup(content)?;
+
+    // The lock is kept between the check and the restore.
+    let mut database = database.lock().await;
+    if !database.is_chat_empty(chat_id)? {
+        return Err(InputError::chat_not_empty().into());
+    }
+    database.restore_backup(chat_id, &backup)?;
+
+    let language = database.get_settings(chat_id)?.language;
+    Ok(language.catalog().backup_restored().to_string())
+}
+
+/// Show the language of the chat or, if a language is given, change it.
+///
+/// The reply is in the (new) language of the chat.
+pub async fn handle_language<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<String> {
+    let payload = payload.trim();
+    if payload.is_empty() {
+        let language = database.lock().await.get_settings(chat_id)?.language;
+        return Ok(language.catalog().current_language(language));
+    }
+
+    let language = Language::parse(payload)
+        .ok_or_else(|| InputError::unknown_language(payload.to_string()))?;
+    debug!("Setting language: {}", language.code());
+    database
+        .lock()
+        .await
+        .set_setting(chat_id, Setting::Language(language))?;
+    Ok(language.catalog().language_changed().to_string())
+}
+
+/// Show the timezone of the chat or, if a timezone is given, change it.
+///
+/// The special name `server` goes back to the timezone of the server.
+pub async fn handle_timezone<D: Database>(
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+    payload: &str,
+) -> anyhow::Result<String> {
+    let payload = payload.trim();
+    let settings = database.lock().await.get_settings(chat_id)?;
+    let catalog = settings.language.catalog();
+    if payload.is_empty() {
+        return Ok(catalog.current_timezone(settings.timezone));
+    }
+
+    let timezone = if payload.eq_ignore_ascii_case(SERVER_TIMEZONE) {
+        None
+    } else {
+        let timezone = parse_timezone(payload)
+            .ok_or_else(|| InputError::unknown_timezone(payload.to_string()))?;
+        Some(timezone)
+    };
+    debug!("Setting timezone: {:?}", timezone);
+    database
+        .lock()
+        .await
+        .set_setting(chat_id, Setting::Timezone(timezone))?;
+    Ok(catalog.timezone_changed(timezone))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make_group_tree() {
+        let all_members: HashMap<_, _> = [
+            ("everyone", vec!["#flat1", "#flat2"]),
+            ("flat1", vec!["bb", "aa", "#everyone"]),
+            ("flat2", vec!["cc"]),
+        ]
+        .into_iter()
+        .map(|(g, m)| (g.to_string(), m.into_iter().map(String::from).collect()))
+        .collect();
+
+        // The cycle is cut where everyone appears again.
+        let tree = make_group_tree("everyone", &all_members);
+        let flat1 = &tree.subgroups[0];
+        assert_eq!(vec!["aa", "bb"], flat1.participants);
+        assert_eq!("everyone", flat1.subgroups[0].name);
+        assert!(flat1.subgroups[0].participants.is_empty());
+        assert!(flat1.subgroups[0].subgroups.is_empty());
+        assert_eq!(vec!["cc"], tree.subgroups[1].participants);
+    }
 }
@@ -2,11 +2,11 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum InputError {
-    #[error("invalid syntax for an expense; example of valid syntax: p1 p2/2.2 12 p1/1 p3 #group")]
-    InvalidExpenseSyntax(String),
+    #[error("invalid syntax for an expense: {0}")]
+    InvalidExpenseSyntax(ExpenseSyntaxError),
 
     #[error("invalid expense: {0}")]
-    InvalidExpense(String, String),
+    InvalidExpense(ExpenseProblem, String),
 
     #[error(
         "invalid participant name `{0}`: participant names must start with a letter \
@@ -27,8 +27,8 @@ pub enum InputError {
     )]
     InvalidGroupName(String),
 
-    #[error("`{0}` is not a registered participant")]
-    UnregisteredParticipant(String),
+    #[error("`{0}` is not a registered participant{}", did_you_mean(.1))]
+    UnregisteredParticipant(String, Vec<String>),
 
     #[error("`{0}` is already used as the name of a participant")]
     AliasRegisteredAsParticipant(String),
@@ -39,8 +39,20 @@ pub enum InputError {
     #[error("`{0}` is not an alias for participant `{1}`")]
     AliasNotRegisteredAsAlias(String, String),
 
-    #[error("`{0}` is not a registered group")]
-    UnregisteredGroup(String),
+    #[error("`{0}` is not a registered group{}", did_you_mean(.1))]
+    UnregisteredGroup(String, Vec<String>),
+
+    #[error("`{0}` is already a registered group")]
+    GroupAlreadyRegistered(String),
+
+    #[error("`{0}` is already a member of the household `{1}`")]
+    ParticipantInOtherHousehold(String, String),
+
+    #[error("the household `{0}` can only contain participants")]
+    SubgroupInHousehold(String),
+
+    #[error("`#{1}` cannot be a member of `#{0}`, because `#{0}` would contain itself")]
+    GroupCycle(String, String),
 
     #[error(
         "there must be at least one participant. Format must be \
@@ -59,16 +71,231 @@ pub enum InputError {
 
     #[error("invalid value `{0}` for expense ID: expected an integer")]
     InvalidExpenseId(String),
+
+    #[error("`{0}` is not a command; use /help to see the list of commands")]
+    UnknownCommand(String),
+
+    #[error("`{0}` is not a supported language")]
+    UnknownLanguage(String),
+
+    #[error("`{0}` is not a known timezone; use a name such as Europe/Rome")]
+    UnknownTimezone(String),
+
+    #[error(
+        "`{0}` is not a valid option for /export; use active or all, \
+         csv, ledger or beancount, and a currency such as EUR"
+    )]
+    InvalidExportOption(String),
+
+    #[error("reply to a CSV document with /import to import its expenses")]
+    ImportWithoutDocument,
+
+    #[error(
+        "`{0}` is not a valid option for /import; use csv or splitwise, \
+         and dry-run to check the file without saving"
+    )]
+    InvalidImportOption(String),
+
+    #[error("invalid CSV file: {0}")]
+    InvalidCsv(CsvProblem),
+
+    #[error("`{0}` is not a valid option for /balance; use --at YYYY-MM-DD or --before ID")]
+    InvalidBalanceOption(String),
+
+    #[error("/between needs two different participants, optionally followed by --list")]
+    InvalidBetweenParticipants,
+
+    #[error(
+        "/renameparticipant needs the current and the new name of the participant, \
+         optionally followed by --keep-alias"
+    )]
+    InvalidRenameParticipant,
+
+    #[error("/renamegroup needs the current and the new name of the group")]
+    InvalidRenameGroup,
+
+    #[error("/addgroup needs the name of the group, optionally followed by --household")]
+    InvalidAddGroup,
+
+    #[error(
+        "/mergeparticipants needs the participant to keep and the participant to merge into it"
+    )]
+    InvalidMergeParticipants,
+
+    #[error(
+        "`{0}` is not a valid settlement constraint; use only p1 p2, forbid p1 p2 or noroute p1"
+    )]
+    InvalidSettlementConstraint(String),
+
+    #[error("`{0}` is not a valid option for /report; use active or all")]
+    InvalidReportOption(String),
+
+    #[error(
+        "`{0}` is not a valid option for /chart; use people, categories or time, and active or all"
+    )]
+    InvalidChartOption(String),
+
+    #[error("there are no expenses to draw")]
+    NothingToChart,
+
+    #[error("reply to a backup file with /restore to restore it")]
+    RestoreWithoutDocument,
+
+    #[error(
+        "this chat already has participants, groups or expenses: \
+         a backup can only be restored in a new chat"
+    )]
+    ChatNotEmpty,
+
+    #[error("invalid backup file: {0}")]
+    InvalidBackup(BackupProblem),
+}
+
+/// The reason why an expense is not valid.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpenseProblem {
+    #[error("there are neither debtors nor creditors in this expense!")]
+    NoParticipants,
+
+    #[error("there are no creditors in this expense!")]
+    NoCreditors,
+
+    #[error("the money that people paid are more than the total expense amount!")]
+    CreditAboveAmount,
+
+    #[error("all creditors paid a fixed amount and the total is less than the expense amount!")]
+    FixedCreditBelowAmount,
+
+    #[error("the money owed by people are more than the total expense amount!")]
+    DebtAboveAmount,
+
+    #[error("all debtors owe a fixed amount and the total is less than the expense amount!")]
+    FixedDebtBelowAmount,
+
+    #[error("there are creditors appearing multiple times with custom amounts!")]
+    DuplicateCreditorAmounts,
+
+    #[error("there are debtors appearing multiple times with custom amounts!")]
+    DuplicateDebtorAmounts,
+}
+
+/// Where an expense cannot be parsed, and what was expected there.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error(
+    "expected {expected} at column {column}, {}\n\n{}",
+    found(.token),
+    self.marked_line()
+)]
+pub struct ExpenseSyntaxError {
+    /// The line of the expense where parsing failed.
+    pub line: String,
+    /// The position of the offending token in the line, counting characters from 1.
+    pub column: usize,
+    pub expected: ExpectedToken,
+    /// The offending token, empty if the expense ended too early.
+    pub token: String,
+}
+
+impl ExpenseSyntaxError {
+    /// The line of the expense, with a caret under the offending token.
+    pub fn marked_line(&self) -> String {
+        format!("{}\n{}^", self.line, " ".repeat(self.column - 1))
+    }
+}
+
+fn found(token: &str) -> String {
+    if token.is_empty() {
+        "but the expense ended".to_string()
+    } else {
+        format!("found `{token}`")
+    }
+}
+
+/// What the expense parser expected where it failed.
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedToken {
+    #[error("the amount")]
+    Amount,
+
+    #[error("an amount after `/`")]
+    ParticipantAmount,
+
+    #[error("a participant name or `- ` before the message")]
+    ParticipantOrMessage,
+}
+
+/// The reason why a CSV file, or one of its rows, cannot be imported.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum CsvProblem {
+    #[error("the file cannot be read ({0})")]
+    Unreadable(String),
+
+    #[error("the file is larger than {0} KB")]
+    TooLarge(u32),
+
+    #[error("the column `{0}` is missing")]
+    MissingColumn(String),
+
+    #[error("the column `{0}` is empty")]
+    EmptyValue(String),
+
+    #[error("`{0}` is not a valid date, expected YYYY-MM-DD")]
+    InvalidDate(String),
+
+    #[error("`{0}` is not a valid amount")]
+    InvalidAmount(String),
+
+    #[error("`{0}` is not a valid role, expected creditor or debtor")]
+    InvalidRole(String),
+
+    #[error("`{0}` is not a valid state, expected active or settled")]
+    InvalidState(String),
+
+    #[error("the rows of expense `{0}` do not agree on date, amount, message or state")]
+    InconsistentRows(String),
+
+    #[error("the column `{0}` is not a valid participant name")]
+    InvalidParticipantColumn(String),
+
+    #[error("the columns `{0}` and `{1}` are the same participant")]
+    DuplicateParticipantColumn(String, String),
+
+    #[error("the currency `{0}` is different from the currency of the other expenses ({1})")]
+    OtherCurrency(String, String),
+
+    #[error("the amounts of the participants do not add up to zero, or they exceed the cost")]
+    UnbalancedExpense,
+}
+
+/// The reason why a backup file cannot be restored.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BackupProblem {
+    #[error("the file cannot be read ({0})")]
+    Unreadable(String),
+
+    #[error("the file is larger than {0} KB")]
+    TooLarge(u32),
+
+    #[error("version {0} is not supported")]
+    UnsupportedVersion(u32),
+
+    #[error("there is no participant with ID {0}")]
+    UnknownParticipant(i64),
+
+    #[error("there is no group with ID {0}")]
+    UnknownGroup(i64),
+
+    #[error("the group with ID {0} contains itself")]
+    GroupCycle(i64),
 }
 
 impl InputError {
-    // TODO: it should be possible to improve nom error messages.
-    pub fn invalid_expense_syntax(e: nom::Err<nom::error::Error<&str>>) -> Self {
-        InputError::InvalidExpenseSyntax(e.to_string())
+    pub fn invalid_expense_syntax(error: ExpenseSyntaxError) -> Self {
+        InputError::InvalidExpenseSyntax(error)
     }
 
-    pub fn invalid_expense(reason: String, expense: String) -> Self {
-        InputError::InvalidExpense(reason, expense)
+    pub fn invalid_expense(problem: ExpenseProblem, expense: String) -> Self {
+        InputError::InvalidExpense(problem, expense)
     }
 
     pub fn invalid_participant_name(name: String) -> Self {
@@ -83,8 +310,8 @@ impl InputError {
         InputError::InvalidGroupName(name)
     }
 
-    pub fn unregistered_participant(name: String) -> Self {
-        InputError::UnregisteredParticipant(name)
+    pub fn unregistered_participant(name: String, suggestions: Vec<String>) -> Self {
+        InputError::UnregisteredParticipant(name, suggestions)
     }
 
     pub fn alias_registered_as_participant(name: String) -> Self {
@@ -99,8 +326,24 @@ impl InputError {
         InputError::AliasNotRegisteredAsAlias(name, participant)
     }
 
-    pub fn unregistered_group(name: String) -> Self {
-        InputError::UnregisteredGroup(name)
+    pub fn unregistered_group(name: String, suggestions: Vec<String>) -> Self {
+        InputError::UnregisteredGroup(name, suggestions)
+    }
+
+    pub fn group_already_registered(name: String) -> Self {
+        InputError::GroupAlreadyRegistered(name)
+    }
+
+    pub fn participant_in_other_household(participant: String, household: String) -> Self {
+        InputError::ParticipantInOtherHousehold(participant, household)
+    }
+
+    pub fn subgroup_in_household(household: String) -> Self {
+        InputError::SubgroupInHousehold(household)
+    }
+
+    pub fn group_cycle(group: String, subgroup: String) -> Self {
+        InputError::GroupCycle(group, subgroup)
     }
 
     pub fn participants_not_provided() -> Self {
@@ -122,6 +365,86 @@ impl InputError {
     pub fn invalid_expense_id(id: String) -> Self {
         InputError::InvalidExpenseId(id)
     }
+
+    pub fn unknown_command(name: String) -> Self {
+        InputError::UnknownCommand(name)
+    }
+
+    pub fn unknown_language(code: String) -> Self {
+        InputError::UnknownLanguage(code)
+    }
+
+    pub fn unknown_timezone(name: String) -> Self {
+        InputError::UnknownTimezone(name)
+    }
+
+    pub fn invalid_export_option(option: String) -> Self {
+        InputError::InvalidExportOption(option)
+    }
+
+    pub fn import_without_document() -> Self {
+        InputError::ImportWithoutDocument
+    }
+
+    pub fn invalid_import_option(option: String) -> Self {
+        InputError::InvalidImportOption(option)
+    }
+
+    pub fn invalid_csv(problem: CsvProblem) -> Self {
+        InputError::InvalidCsv(problem)
+    }
+
+    pub fn invalid_balance_option(option: String) -> Self {
+        InputError::InvalidBalanceOption(option)
+    }
+
+    pub fn invalid_between_participants() -> Self {
+        InputError::InvalidBetweenParticipants
+    }
+
+    pub fn invalid_rename_participant() -> Self {
+        InputError::InvalidRenameParticipant
+    }
+
+    pub fn invalid_rename_group() -> Self {
+        InputError::InvalidRenameGroup
+    }
+
+    pub fn invalid_add_group() -> Self {
+        InputError::InvalidAddGroup
+    }
+
+    pub fn invalid_merge_participants() -> Self {
+        InputError::InvalidMergeParticipants
+    }
+
+    pub fn invalid_settlement_constraint(constraint: String) -> Self {
+        InputError::InvalidSettlementConstraint(constraint)
+    }
+
+    pub fn invalid_report_option(option: String) -> Self {
+        InputError::InvalidReportOption(option)
+    }
+
+    pub fn invalid_chart_option(option: String) -> Self {
+        InputError::InvalidChartOption(option)
+    }
+
+    pub fn nothing_to_chart() -> Self {
+        InputError::NothingToChart
+    }
+
+    pub fn restore_without_document() -> Self {
+        InputError::RestoreWithoutDocument
+    }
+
+    pub fn chat_not_empty() -> Self {
+        InputError::ChatNotEmpty
+    }
+
+    pub fn invalid_backup(problem: BackupProblem) -> Self {
+        InputError::InvalidBackup(problem)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -134,6 +457,23 @@ pub enum DatabaseError {
     ConcurrencyError(String),
 }
 
+/// The end of an error about an unregistered name, listing the registered
+/// names that the user may have meant.
+fn did_you_mean(suggestions: &[String]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [suggestion] => format!("; did you mean `{suggestion}`?"),
+        [first @ .., last] => {
+            let first = first
+                .iter()
+                .map(|s| format!("`{s}`"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("; did you mean {first} or `{last}`?")
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 #[error("Cannot communicate with Telegram server, please try again later.")]
 pub struct TelegramError {
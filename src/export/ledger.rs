@@ -0,0 +1,331 @@
+//! Export expenses for plain-text accounting programs (Ledger, hledger and Beancount).
+//!
+//! Each expense becomes a balanced transaction, with two accounts per participant:
+//! what they paid is credited to `Liabilities:Payable:<Name>` and their share of the
+//! expense is debited to `Assets:Receivable:<Name>`. Shares are computed as in
+//! `bot_logic`, so the sum of the two accounts of a participant is what they owe
+//! (if positive) or what they are owed (if negative), as in `/balance`.
+
+use std::collections::HashMap;
+
+use crate::{
+    bot_logic::compute_expense_shares,
+    settings::ChatSettings,
+    types::{Amount, SavedExpense},
+};
+
+use super::{format_amount, DATE_FORMAT};
+
+/// Beancount needs a currency for every amount.
+const DEFAULT_BEANCOUNT_CURRENCY: &str = "EUR";
+
+const RECEIVABLE_ACCOUNT: &str = "Assets:Receivable";
+const PAYABLE_ACCOUNT: &str = "Liabilities:Payable";
+
+/// A line of a transaction.
+#[derive(Debug, PartialEq, Eq)]
+struct Posting {
+    account: String,
+    amount: Amount,
+}
+
+pub fn export_ledger(
+    expenses: &[SavedExpense],
+    currency: Option<&str>,
+    settings: &ChatSettings,
+) -> String {
+    sorted(expenses)
+        .into_iter()
+        .map(|expense| {
+            let date = settings.local_date(expense.message_ts).format(DATE_FORMAT);
+            let payee = expense
+                .message
+                .as_deref()
+                .map(single_line)
+                .unwrap_or_else(|| format!("expense {}", expense.id));
+            let postings = postings(expense)
+                .iter()
+                .map(|p| format_posting(p, currency))
+                .collect::<String>();
+            format!("{date} * {payee}\n    ; expense {}\n{postings}", expense.id)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn export_beancount(
+    expenses: &[SavedExpense],
+    currency: Option<&str>,
+    settings: &ChatSettings,
+) -> String {
+    let currency = currency.unwrap_or(DEFAULT_BEANCOUNT_CURRENCY);
+    let expenses = sorted(expenses);
+
+    // Beancount only accepts accounts that were opened: they are all opened on the date
+    // of the first expense.
+    let mut accounts: Vec<_> = expenses
+        .iter()
+        .flat_map(|e| postings(e).into_iter().map(|p| p.account))
+        .collect();
+    accounts.sort();
+    accounts.dedup();
+    let opening = expenses
+        .iter()
+        .map(|e| settings.local_date(e.message_ts))
+        .min()
+        .map(|date| {
+            let date = date.format(DATE_FORMAT);
+            accounts
+                .iter()
+                .map(|a| format!("{date} open {a}\n"))
+                .collect::<String>()
+        })
+        .unwrap_or_default();
+
+    let transactions = expenses.into_iter().map(|expense| {
+        let date = settings.local_date(expense.message_ts).format(DATE_FORMAT);
+        let narration = expense
+            .message
+            .as_deref()
+            .map(single_line)
+            .unwrap_or_else(|| format!("expense {}", expense.id))
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        let postings = postings(expense)
+            .iter()
+            .map(|p| format_posting(p, Some(currency)))
+            .collect::<String>();
+        format!(
+            "{date} * \"{narration}\"\n    expense_id: {}\n{postings}",
+            expense.id
+        )
+    });
+
+    std::iter::once(opening)
+        .chain(transactions)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn sorted(expenses: &[SavedExpense]) -> Vec<&SavedExpense> {
+    let mut expenses: Vec<_> = expenses.iter().collect();
+    expenses.sort_by_key(|e| e.id);
+    expenses
+}
+
+/// The postings of an expense: first what each participant paid, then their shares,
+/// each sorted by name. Both add up to the amount of the expense, so the transaction
+/// is balanced.
+fn postings(expense: &SavedExpense) -> Vec<Posting> {
+    let shares = compute_expense_shares(expense);
+
+    let paid = round_amounts(&shares.paid, expense.amount)
+        .into_iter()
+        .map(|(name, amount)| Posting {
+            account: account(PAYABLE_ACCOUNT, &name),
+            amount: -amount,
+        });
+    let owed = round_amounts(&shares.owed, expense.amount)
+        .into_iter()
+        .map(|(name, amount)| Posting {
+            account: account(RECEIVABLE_ACCOUNT, &name),
+            amount,
+        });
+
+    paid.chain(owed).filter(|p| p.amount != 0).collect()
+}
+
+/// Round amounts to whole cents so that they add up to `total`, sorted by name.
+///
+/// Amounts are rounded down, then the cents that are left go to the amounts with the
+/// largest fractional part (ties are broken by name, to get consistent results).
+fn round_amounts(amounts: &HashMap<String, f64>, total: Amount) -> Vec<(String, Amount)> {
+    let mut rounded: Vec<_> = amounts
+        .iter()
+        .map(|(name, &a)| (name.clone(), a.floor() as Amount, a - a.floor()))
+        .collect();
+    rounded.sort_by(|x, y| x.0.cmp(&y.0));
+
+    let mut by_remainder: Vec<_> = (0..rounded.len()).collect();
+    by_remainder.sort_by(|&x, &y| rounded[y].2.total_cmp(&rounded[x].2));
+
+    let left: Amount = total - rounded.iter().map(|r| r.1).sum::<Amount>();
+    if left > 0 {
+        for &i in by_remainder.iter().cycle().take(left as usize) {
+            rounded[i].1 += 1;
+        }
+    } else if left < 0 {
+        for &i in by_remainder.iter().rev().cycle().take(-left as usize) {
+            rounded[i].1 -= 1;
+        }
+    }
+
+    rounded.into_iter().map(|(name, a, _)| (name, a)).collect()
+}
+
+/// Account names must start with a capital letter.
+fn account(parent: &str, name: &str) -> String {
+    let mut chars = name.chars();
+    let name = match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    };
+    format!("{parent}:{name}")
+}
+
+fn format_posting(posting: &Posting, currency: Option<&str>) -> String {
+    let amount = format_amount(posting.amount);
+    match currency {
+        Some(currency) => format!("    {:<38}  {:>10} {currency}\n", posting.account, amount),
+        None => format!("    {:<38}  {:>10}\n", posting.account, amount),
+    }
+}
+
+fn single_line(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+
+    use crate::{bot_logic::compute_exchanges, types::SavedParticipant};
+
+    use super::*;
+
+    fn settings() -> ChatSettings {
+        ChatSettings {
+            timezone: Some(Tz::Europe__Rome),
+            ..Default::default()
+        }
+    }
+
+    fn make_expenses() -> Vec<SavedExpense> {
+        let message_ts = DateTime::parse_from_rfc3339("2023-05-01T22:30:00Z")
+            .expect("test")
+            .into();
+        vec![
+            SavedExpense::new(
+                7,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("bb", None),
+                    SavedParticipant::new_debtor("aa", None),
+                ],
+                1000,
+                None,
+                message_ts,
+            ),
+            SavedExpense::new(
+                3,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("aa", None),
+                    SavedParticipant::new_debtor("bb", Some(123)),
+                    SavedParticipant::new_debtor("cc", None),
+                ],
+                1000,
+                Some("pizza \"margherita\"".to_string()),
+                message_ts,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_round_amounts() {
+        let amounts = HashMap::from([
+            ("aa".to_string(), 1000.0 / 3.0),
+            ("bb".to_string(), 1000.0 / 3.0),
+            ("cc".to_string(), 1000.0 / 3.0),
+        ]);
+        assert_eq!(
+            vec![
+                ("aa".to_string(), 334),
+                ("bb".to_string(), 333),
+                ("cc".to_string(), 333)
+            ],
+            round_amounts(&amounts, 1000)
+        );
+
+        let amounts = HashMap::from([("aa".to_string(), 438.5), ("bb".to_string(), 438.5)]);
+        assert_eq!(
+            vec![("aa".to_string(), 439), ("bb".to_string(), 438)],
+            round_amounts(&amounts, 877)
+        );
+    }
+
+    #[test]
+    fn test_export_ledger() {
+        let result = export_ledger(&make_expenses(), None, &settings());
+        assert_eq!(
+            "2023-05-02 * pizza \"margherita\"
+    ; expense 3
+    Liabilities:Payable:Aa                      -10.00
+    Assets:Receivable:Aa                          4.39
+    Assets:Receivable:Bb                          1.23
+    Assets:Receivable:Cc                          4.38
+
+2023-05-02 * expense 7
+    ; expense 7
+    Liabilities:Payable:Bb                      -10.00
+    Assets:Receivable:Aa                          5.00
+    Assets:Receivable:Bb                          5.00
+",
+            result
+        );
+    }
+
+    #[test]
+    fn test_export_beancount() {
+        let result = export_beancount(&make_expenses()[1..], Some("USD"), &settings());
+        assert_eq!(
+            "2023-05-02 open Assets:Receivable:Aa
+2023-05-02 open Assets:Receivable:Bb
+2023-05-02 open Assets:Receivable:Cc
+2023-05-02 open Liabilities:Payable:Aa
+
+2023-05-02 * \"pizza \\\"margherita\\\"\"
+    expense_id: 3
+    Liabilities:Payable:Aa                      -10.00 USD
+    Assets:Receivable:Aa                          4.39 USD
+    Assets:Receivable:Bb                          1.23 USD
+    Assets:Receivable:Cc                          4.38 USD
+",
+            result
+        );
+
+        assert_eq!("", export_beancount(&[], None, &settings()));
+    }
+
+    /// The sum of the accounts of each participant is their position in `/balance`.
+    #[test]
+    fn test_postings_match_balance() {
+        let expenses = make_expenses();
+
+        let mut totals: HashMap<String, Amount> = HashMap::new();
+        for expense in &expenses {
+            let postings = postings(expense);
+            assert_eq!(0, postings.iter().map(|p| p.amount).sum::<Amount>());
+            for p in postings {
+                let name = p.account.rsplit(':').next().expect("test").to_lowercase();
+                *totals.entry(name).or_default() += p.amount;
+            }
+        }
+
+        let mut from_exchanges: HashMap<String, Amount> = HashMap::new();
+        for exchange in compute_exchanges(expenses, &[]) {
+            *from_exchanges.entry(exchange.debtor).or_default() += exchange.amount;
+            *from_exchanges.entry(exchange.creditor).or_default() -= exchange.amount;
+        }
+
+        for (name, total) in totals {
+            let expected = from_exchanges.get(&name).copied().unwrap_or_default();
+            assert!(
+                (total - expected).abs() <= 1,
+                "{name}: {total} in postings, {expected} in the balance"
+            );
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Produce the files that are sent by `/export`.
+//!
+//! Unlike the messages produced in `formatter`, exported files are meant to be read by
+//! other programs: numbers always use a decimal point and dates are in ISO format,
+//! whatever the language of the chat. Dates are still in the timezone of the chat.
+
+mod ledger;
+
+use crate::{
+    settings::ChatSettings,
+    types::{Amount, SavedExpense},
+};
+
+const AMOUNT_TO_FLOAT_DIVISOR: f64 = 100.0;
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Spreadsheets read a cell that starts with one of these characters as a formula.
+pub const FORMULA_PREFIXES: [char; 4] = ['=', '+', '-', '@'];
+
+const CSV_HEADER: [&str; 8] = [
+    "id",
+    "date",
+    "amount",
+    "participant",
+    "role",
+    "custom_amount",
+    "message",
+    "state",
+];
+
+/// The format of an exported file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    /// The journal format of Ledger, which is also read by hledger.
+    Ledger,
+    Beancount,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> Option<ExportFormat> {
+        match s {
+            "csv" => Some(ExportFormat::Csv),
+            "ledger" | "hledger" => Some(ExportFormat::Ledger),
+            "beancount" => Some(ExportFormat::Beancount),
+            _ => None,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ledger => "ledger",
+            ExportFormat::Beancount => "beancount",
+        }
+    }
+}
+
+/// What `/export` should produce.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub only_active: bool,
+    pub format: ExportFormat,
+    /// The currency of amounts, used by plain-text accounting formats.
+    pub currency: Option<String>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            only_active: true,
+            format: ExportFormat::Csv,
+            currency: None,
+        }
+    }
+}
+
+/// A file ready to be sent as a Telegram document.
+#[derive(Clone, Debug)]
+pub struct ExportedFile {
+    pub file_name: String,
+    pub content: String,
+}
+
+pub fn export_expenses(
+    expenses: &[SavedExpense],
+    options: ExportOptions,
+    settings: &ChatSettings,
+) -> anyhow::Result<ExportedFile> {
+    let currency = options.currency.as_deref();
+    let content = match options.format {
+        ExportFormat::Csv => export_csv(expenses, settings)?,
+        ExportFormat::Ledger => ledger::export_ledger(expenses, currency, settings),
+        ExportFormat::Beancount => ledger::export_beancount(expenses, currency, settings),
+    };
+
+    let scope = if options.only_active { "active" } else { "all" };
+    Ok(ExportedFile {
+        file_name: format!("expenses-{}.{}", scope, options.format.extension()),
+        content,
+    })
+}
+
+/// One row per participant of each expense, sorted by expense ID.
+///
+/// The `amount` column is the total amount of the expense, while `custom_amount`
+/// is the amount given to the participant in the expense, if any.
+pub fn export_csv(expenses: &[SavedExpense], settings: &ChatSettings) -> anyhow::Result<String> {
+    let mut expenses: Vec<_> = expenses.iter().collect();
+    expenses.sort_by_key(|e| e.id);
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(CSV_HEADER)?;
+
+    for expense in expenses {
+        let id = expense.id.to_string();
+        let date = settings
+            .local_date(expense.message_ts)
+            .format(DATE_FORMAT)
+            .to_string();
+        let amount = format_amount(expense.amount);
+        let message = escape_formula(expense.message.as_deref().unwrap_or(""));
+        let state = if expense.is_active {
+            "active"
+        } else {
+            "settled"
+        };
+
+        // Creditors first, as in `/list`.
+        let mut participants: Vec<_> = expense.participants.iter().collect();
+        participants.sort_by_key(|p| !p.is_creditor());
+
+        for participant in participants {
+            let role = if participant.is_creditor() {
+                "creditor"
+            } else {
+                "debtor"
+            };
+            let custom_amount = participant.amount.map(format_amount).unwrap_or_default();
+            writer.write_record([
+                id.as_str(),
+                &date,
+                &amount,
+                &participant.name,
+                role,
+                &custom_amount,
+                &message,
+                state,
+            ])?;
+        }
+    }
+
+    let bytes = writer.into_inner()?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Prefix a cell that a spreadsheet would read as a formula with `'`, so that it is
+/// shown as text. `/import` removes the prefix. Cells that already start with `'` get
+/// it too, so that they are not changed by `/import`.
+fn escape_formula(cell: &str) -> String {
+    if cell.starts_with(FORMULA_PREFIXES) || cell.starts_with('\'') {
+        format!("'{cell}")
+    } else {
+        cell.to_string()
+    }
+}
+
+fn format_amount(amount: Amount) -> String {
+    let amount = amount as f64 / AMOUNT_TO_FLOAT_DIVISOR;
+    format!("{:.2}", amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+
+    use crate::{i18n::Language, types::SavedParticipant};
+
+    use super::*;
+
+    fn settings() -> ChatSettings {
+        // Fixed timezone and a language with a decimal comma, which must not affect exports.
+        ChatSettings {
+            language: Language::Italian,
+            timezone: Some(Tz::Europe__Rome),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_export_csv() -> anyhow::Result<()> {
+        let message_ts = DateTime::parse_from_rfc3339("2023-05-01T22:30:00Z")?.into();
+        let expenses = vec![
+            SavedExpense::new(
+                7,
+                false,
+                vec![
+                    SavedParticipant::new_debtor("bb", None),
+                    SavedParticipant::new_creditor("aa", None),
+                ],
+                1000,
+                Some("pizza, beer and \"dessert\"".to_string()),
+                message_ts,
+            ),
+            SavedExpense::new(
+                3,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("aa", None),
+                    SavedParticipant::new_debtor("bb", Some(123)),
+                    SavedParticipant::new_debtor("cc", None),
+                ],
+                4343,
+                None,
+                message_ts,
+            ),
+        ];
+
+        let result = export_csv(&expenses, &settings())?;
+        assert_eq!(
+            "id,date,amount,participant,role,custom_amount,message,state\n\
+             3,2023-05-02,43.43,aa,creditor,,,active\n\
+             3,2023-05-02,43.43,bb,debtor,1.23,,active\n\
+             3,2023-05-02,43.43,cc,debtor,,,active\n\
+             7,2023-05-02,10.00,aa,creditor,,\"pizza, beer and \"\"dessert\"\"\",settled\n\
+             7,2023-05-02,10.00,bb,debtor,,\"pizza, beer and \"\"dessert\"\"\",settled\n",
+            result
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_expenses() -> anyhow::Result<()> {
+        let file = export_expenses(&[], ExportOptions::default(), &settings())?;
+        assert_eq!("expenses-active.csv", file.file_name);
+        assert_eq!(
+            "id,date,amount,participant,role,custom_amount,message,state\n",
+            file.content
+        );
+
+        let options = ExportOptions {
+            only_active: false,
+            format: ExportFormat::Beancount,
+            currency: None,
+        };
+        let file = export_expenses(&[], options, &settings())?;
+        assert_eq!("expenses-all.beancount", file.file_name);
+
+        Ok(())
+    }
+}
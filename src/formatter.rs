@@ -1,39 +1,49 @@
 //! Produce the strings that are sent as bot messages.
 //! The formatting consists in using basic markdown formatting, emojis
-//! and composing the actual output string.
+//! and composing the actual output string. Numbers, dates and fixed
+//! sentences follow the settings of the chat.
 
-use chrono::{DateTime, Local};
-use std::iter::repeat;
-use teloxide::utils::markdown::{bold, code_inline, escape};
+use std::{cmp::Ordering, iter::repeat_n};
+use teloxide::utils::markdown::{bold, code_block, code_inline, escape};
 
-use crate::types::{Amount, MoneyExchange, SavedExpense, SavedParticipant};
+use crate::{
+    bot_logic::{ExpenseContribution, ImpossibleSettlement, PairwiseContribution},
+    error::InputError,
+    i18n::{format_error, Language},
+    import::ImportReport,
+    settings::{ChatSettings, Setting, SettingKey},
+    types::{
+        Amount, GroupTree, MoneyExchange, NetPosition, ParsedExpense, SavedExpense,
+        SavedParticipant, SettlementConstraint,
+    },
+};
 
 const AMOUNT_TO_FLOAT_DIVISOR: f64 = 100.0;
 
-pub fn format_list_expenses(expenses: &[SavedExpense]) -> String {
+pub fn format_list_expenses(expenses: &[SavedExpense], settings: &ChatSettings) -> String {
     if expenses.is_empty() {
-        escape("Nothing to show!")
+        escape(settings.language.catalog().nothing_to_show())
     } else {
         expenses
             .iter()
-            .map(format_expense)
+            .map(|e| format_expense(e, settings))
             .fold(String::new(), |a, b| a + &b + "\n")
     }
 }
 
-fn format_expense(expense: &SavedExpense) -> String {
+fn format_expense(expense: &SavedExpense, settings: &ChatSettings) -> String {
     let prefix = if expense.is_active { "💰" } else { "🧧" };
+    let date = settings
+        .local_date(expense.message_ts)
+        .format(settings.language.catalog().date_format());
     let result = format!(
         "{}  {} {}: {} {} {}",
         prefix,
         bold(&format!("{}", expense.id)),
-        escape(&format!(
-            "({})",
-            DateTime::<Local>::from(expense.message_ts).date_naive()
-        )),
-        format_participants(expense, true),
-        bold(&escape(&format_amount(expense.amount))),
-        format_participants(expense, false)
+        escape(&format!("({})", date)),
+        format_participants(expense, true, settings),
+        bold(&escape(&format_amount(expense.amount, settings))),
+        format_participants(expense, false, settings)
     );
 
     if expense.message.is_some() {
@@ -50,35 +60,198 @@ fn format_expense(expense: &SavedExpense) -> String {
     }
 }
 
-fn format_amount(amount: Amount) -> String {
+/// Format the expenses that make up the balance of a participant, one per line, followed
+/// by the balance.
+pub fn format_why(
+    participant: &str,
+    contributions: &[ExpenseContribution],
+    settings: &ChatSettings,
+) -> String {
+    let catalog = settings.language.catalog();
+    let round = |a: f64| a.round() as Amount;
+
+    let Some(last) = contributions.last() else {
+        return escape(catalog.nothing_to_show());
+    };
+    let lines = contributions
+        .iter()
+        .map(|c| {
+            let date = settings
+                .local_date(c.expense.message_ts)
+                .format(catalog.date_format());
+            let message = c
+                .expense
+                .message
+                .as_ref()
+                .map(|m| format!(" - {m}"))
+                .unwrap_or_default();
+            let step = catalog.why_step(
+                &format_amount(round(c.paid), settings),
+                &format_amount(round(c.share), settings),
+                &format_signed_amount(round(c.net), settings),
+            );
+            format!(
+                "🧾 {} {}\n    {}\n",
+                bold(&c.expense.id.to_string()),
+                escape(&format!("({date}){message}")),
+                escape(&step)
+            )
+        })
+        .collect::<String>();
+
+    let net = round(last.net);
+    let total = match net.cmp(&0) {
+        Ordering::Greater => catalog.why_is_owed(participant, &format_amount(net, settings)),
+        Ordering::Less => catalog.why_owes(participant, &format_amount(-net, settings)),
+        Ordering::Equal => catalog.why_is_even(participant),
+    };
+    format!("{lines}\n{}", bold(&escape(&total)))
+}
+
+/// Format the debt between two participants and, if asked, the expenses that make it up:
+/// a positive amount is owed by `debtor` to `creditor`.
+pub fn format_between(
+    debtor: &str,
+    creditor: &str,
+    contributions: &[PairwiseContribution],
+    list: bool,
+    settings: &ChatSettings,
+) -> String {
+    let catalog = settings.language.catalog();
+    let round = |a: f64| a.round() as Amount;
+
+    let lines = if list && !contributions.is_empty() {
+        contributions
+            .iter()
+            .map(|c| {
+                let date = settings
+                    .local_date(c.expense.message_ts)
+                    .format(catalog.date_format());
+                let message = c
+                    .expense
+                    .message
+                    .as_ref()
+                    .map(|m| format!(" - {m}"))
+                    .unwrap_or_default();
+                format!(
+                    "🧾 {} {}: {}\n",
+                    bold(&c.expense.id.to_string()),
+                    escape(&format!("({date}){message}")),
+                    escape(&format_signed_amount(round(c.amount), settings))
+                )
+            })
+            .collect::<String>()
+            + "\n"
+    } else {
+        String::new()
+    };
+
+    let total = round(contributions.iter().map(|c| c.amount).sum());
+    let total = match total.cmp(&0) {
+        Ordering::Greater => {
+            catalog.between_owes(debtor, creditor, &format_amount(total, settings))
+        }
+        Ordering::Less => catalog.between_owes(creditor, debtor, &format_amount(-total, settings)),
+        Ordering::Equal => catalog.between_even(debtor, creditor),
+    };
+    format!("{lines}{}", bold(&escape(&total)))
+}
+
+/// An amount with its sign, also when it is positive.
+fn format_signed_amount(amount: Amount, settings: &ChatSettings) -> String {
+    if amount > 0 {
+        format!("+{}", format_amount(amount, settings))
+    } else {
+        format_amount(amount, settings)
+    }
+}
+
+pub fn format_amount(amount: Amount, settings: &ChatSettings) -> String {
     let amount = amount as f64 / AMOUNT_TO_FLOAT_DIVISOR;
-    format!("{:.2}", amount)
+    localize_number(format!("{:.2}", amount), settings)
 }
 
-fn format_participants(expense: &SavedExpense, are_creditors: bool) -> String {
+/// Replace the decimal point with the decimal separator of the chat.
+fn localize_number(number: String, settings: &ChatSettings) -> String {
+    let separator = settings.decimal_separator();
+    if separator == '.' {
+        number
+    } else {
+        number.replace('.', &separator.to_string())
+    }
+}
+
+fn format_participants(
+    expense: &SavedExpense,
+    are_creditors: bool,
+    settings: &ChatSettings,
+) -> String {
     expense
         .participants
         .iter()
         .filter(|p| p.is_creditor() == are_creditors)
-        .map(|p| escape(&format_participant(p)))
+        .map(|p| escape(&format_participant(p, settings)))
         .fold(String::new(), |a, b| a + &b + " ")
 }
 
-fn format_participant(participant: &SavedParticipant) -> String {
-    if participant.amount.is_some() {
-        let amount = participant
-            .amount
-            .expect("just checked the amount is non-empty!") as f64
-            / AMOUNT_TO_FLOAT_DIVISOR;
-        format!("{}/{:.2}", participant.name, amount)
+/// A participant with their custom amount, if any, as plain text.
+pub fn format_participant(participant: &SavedParticipant, settings: &ChatSettings) -> String {
+    if let Some(amount) = participant.amount {
+        format!("{}/{}", participant.name, format_amount(amount, settings))
     } else {
         participant.name.to_string()
     }
 }
 
-pub fn format_balance(exchanges: &Vec<MoneyExchange>) -> String {
+/// An expense in the syntax of `/expense`, as plain text.
+pub fn format_parsed_expense(expense: &ParsedExpense, settings: &ChatSettings) -> String {
+    let participants = |are_creditors: bool| {
+        expense
+            .participants
+            .iter()
+            .filter(|p| p.is_creditor() == are_creditors)
+            .map(|p| {
+                let name = if p.is_group() {
+                    format!("#{}", p.name)
+                } else {
+                    p.name.clone()
+                };
+                match p.amount {
+                    Some(amount) => format!("{name}/{}", format_amount(amount, settings)),
+                    None => name,
+                }
+            })
+            .fold(String::new(), |a, b| a + &b + " ")
+    };
+
+    let mut result = format!(
+        "{}{} {}",
+        participants(true),
+        format_amount(expense.amount, settings),
+        participants(false)
+    );
+    if let Some(message) = &expense.message {
+        result += &format!("- {message}");
+    }
+    result.trim_end().to_string()
+}
+
+/// An error message as MarkdownV2. The line of an expense with a syntax error goes in
+/// a code block, whose monospace font keeps the caret under the offending token.
+pub fn format_error_message(error: &anyhow::Error, language: Language) -> String {
+    let message = format_error(error, language);
+    if let Some(InputError::InvalidExpenseSyntax(e)) = error.downcast_ref::<InputError>() {
+        let marked_line = e.marked_line();
+        if let Some(text) = message.strip_suffix(&marked_line) {
+            return format!("{}{}", escape(text), code_block(&marked_line));
+        }
+    }
+    escape(&message)
+}
+
+pub fn format_balance(exchanges: &[MoneyExchange], settings: &ChatSettings) -> String {
     if exchanges.is_empty() {
-        escape("All clean!")
+        escape(settings.language.catalog().all_clean())
     } else {
         let max_debtor_length = exchanges
             .iter()
@@ -87,12 +260,76 @@ pub fn format_balance(exchanges: &Vec<MoneyExchange>) -> String {
             .expect("just checked there are exchanges!");
         exchanges
             .iter()
-            .map(|e| format_exchange(e, max_debtor_length))
+            .map(|e| format_exchange(e, max_debtor_length, settings))
             .fold(String::new(), |a, b| a + &b + "\n")
     }
 }
 
-fn format_exchange(exchange: &MoneyExchange, target_length: usize) -> String {
+/// Format the net position of each participant, with the amounts aligned.
+pub fn format_net_positions(positions: &[NetPosition], settings: &ChatSettings) -> String {
+    if positions.iter().all(|p| p.amount == 0) {
+        return escape(settings.language.catalog().all_clean());
+    }
+    format_positions(positions, settings)
+}
+
+/// Format net positions, one per line and with the amounts aligned.
+fn format_positions(positions: &[NetPosition], settings: &ChatSettings) -> String {
+    let max_participant_length = positions
+        .iter()
+        .map(|p| p.participant.len())
+        .max()
+        .unwrap_or_default();
+    positions
+        .iter()
+        .map(|p| {
+            let participant = p.participant.clone()
+                + &make_string_of_char(' ', max_participant_length - p.participant.len());
+            format!(
+                "{} {} {}\n",
+                if p.amount < 0 { "🔻" } else { "🔺" },
+                code_inline(&participant),
+                bold(&escape(&format_signed_amount(p.amount, settings)))
+            )
+        })
+        .collect()
+}
+
+/// Format a balance of the past, with a title that says when it is from.
+pub fn format_past_balance(title: &str, formatted_balance: &str) -> String {
+    format!("{}\n\n{}", escape(title), formatted_balance)
+}
+
+/// Format the positions that cannot be settled because of the settlement constraints.
+pub fn format_impossible_settlement(
+    impossible: &ImpossibleSettlement,
+    settings: &ChatSettings,
+) -> String {
+    format!(
+        "⚠️ {}\n\n{}",
+        escape(settings.language.catalog().impossible_settlement()),
+        format_positions(&impossible.unsettled, settings)
+    )
+}
+
+/// Format the settlement constraints of a chat, one per line.
+pub fn format_settlement_constraints(
+    constraints: &[SettlementConstraint],
+    settings: &ChatSettings,
+) -> String {
+    let catalog = settings.language.catalog();
+    let constraints: Vec<_> = constraints
+        .iter()
+        .map(|c| catalog.describe_settlement_constraint(c))
+        .collect();
+    format_simple_list(&constraints, settings)
+}
+
+fn format_exchange(
+    exchange: &MoneyExchange,
+    target_length: usize,
+    settings: &ChatSettings,
+) -> String {
     // We make sure that the amounts are always aligned, by padding the debtors where needed.
     let debtor = if exchange.debtor.len() < target_length {
         exchange.debtor.clone() + &make_string_of_char(' ', target_length - exchange.debtor.len())
@@ -108,18 +345,18 @@ fn format_exchange(exchange: &MoneyExchange, target_length: usize) -> String {
     format!(
         "💸 {} {} {}",
         code_inline(&debtor),
-        bold(&escape(&format!("{:2}", amount))),
+        bold(&escape(&localize_number(format!("{:2}", amount), settings))),
         code_inline(&exchange.creditor)
     )
 }
 
 fn make_string_of_char(c: char, length: usize) -> String {
-    repeat(c).take(length).collect::<String>()
+    repeat_n(c, length).collect::<String>()
 }
 
-pub fn format_simple_list<T: AsRef<str>>(elements: &[T]) -> String {
+pub fn format_simple_list<T: AsRef<str>>(elements: &[T], settings: &ChatSettings) -> String {
     if elements.is_empty() {
-        "Nothing to show!".to_string()
+        settings.language.catalog().nothing_to_show().to_string()
     } else {
         elements
             .iter()
@@ -128,10 +365,88 @@ pub fn format_simple_list<T: AsRef<str>>(elements: &[T]) -> String {
     }
 }
 
+/// Format the members of a group as a plain list, where the members of each subgroup
+/// are indented below it.
+pub fn format_group_tree(tree: &GroupTree, settings: &ChatSettings) -> String {
+    if tree.participants.is_empty() && tree.subgroups.is_empty() {
+        settings.language.catalog().nothing_to_show().to_string()
+    } else {
+        let mut result = String::new();
+        format_group_members(tree, 0, &mut result);
+        result
+    }
+}
+
+fn format_group_members(tree: &GroupTree, depth: usize, result: &mut String) {
+    let indent = make_string_of_char(' ', 2 * depth);
+    for participant in &tree.participants {
+        result.push_str(&format!("{indent}- {participant}\n"));
+    }
+    for subgroup in &tree.subgroups {
+        result.push_str(&format!("{indent}- #{}\n", subgroup.name));
+        format_group_members(subgroup, depth + 1, result);
+    }
+}
+
+/// The outcome of `/import`, as plain text.
+pub fn format_import_report(report: &ImportReport, settings: &ChatSettings) -> String {
+    let catalog = settings.language.catalog();
+    if !report.errors.is_empty() {
+        let errors = report
+            .errors
+            .iter()
+            .map(|(line, e)| {
+                let error = format_error(e, settings.language);
+                format!("- {}", catalog.import_error(*line, &error))
+            })
+            .fold(String::new(), |a, b| a + &b + "\n");
+        format!("{}\n\n{}", catalog.import_failed(), errors)
+    } else if report.dry_run {
+        catalog.import_checked(report.num_expenses)
+    } else {
+        catalog.import_saved(report.num_expenses)
+    }
+}
+
+/// The `/settings` message, as plain text.
+pub fn format_settings(settings: &ChatSettings) -> String {
+    let lines = SettingKey::ALL
+        .iter()
+        .map(|k| format!("- {}", format_setting(settings, *k)))
+        .fold(String::new(), |a, b| a + &b + "\n");
+    format!(
+        "{}\n\n{}",
+        settings.language.catalog().settings_title(),
+        lines
+    )
+}
+
+/// A setting with its current value, e.g. "Page size: 15".
+pub fn format_setting(settings: &ChatSettings, key: SettingKey) -> String {
+    let catalog = settings.language.catalog();
+    let value = match settings.get(key) {
+        Setting::PageSize(size) => size.to_string(),
+        Setting::DecimalSeparator(Some(separator)) => separator.to_string(),
+        Setting::DecimalSeparator(None) => format!(
+            "{} ({})",
+            catalog.setting_default(),
+            settings.decimal_separator()
+        ),
+        Setting::AutoRegister(true) => catalog.setting_on().to_string(),
+        Setting::AutoRegister(false) => catalog.setting_off().to_string(),
+        Setting::Language(language) => language.name().to_string(),
+        Setting::Timezone(Some(timezone)) => timezone.name().to_string(),
+        Setting::Timezone(None) => catalog.server_timezone().to_string(),
+    };
+    format!("{}: {}", catalog.setting_name(key), value)
+}
+
 #[cfg(test)]
 mod tests {
     use chrono::DateTime;
 
+    use crate::{error::CsvProblem, parser::parse_expense, types::ParsedParticipant};
+
     use super::*;
 
     #[test]
@@ -146,7 +461,7 @@ mod tests {
             DateTime::from(DateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S %z").unwrap());
         // Active expense.
         let expense = SavedExpense::new(1, true, participants.clone(), 4343, None, message_ts);
-        let result = format_expense(&expense);
+        let result = format_expense(&expense, &ChatSettings::default());
         assert_eq!(
             "💰  *1* \\(2023\\-05\\-01\\): cccc  *43\\.43* aa bbb/1\\.23 ",
             result
@@ -154,11 +469,42 @@ mod tests {
 
         // Settled expense.
         let expense = SavedExpense::new(1, false, participants.clone(), 4343, None, message_ts);
-        let result = format_expense(&expense);
+        let result = format_expense(&expense, &ChatSettings::default());
         assert_eq!(
             "🧧  *1* \\(2023\\-05\\-01\\): cccc  *43\\.43* aa bbb/1\\.23 ",
             result
         );
+
+        // Italian uses a different decimal separator and date format.
+        let mut italian = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        let expense = SavedExpense::new(1, true, participants, 4343, None, message_ts);
+        let result = format_expense(&expense, &italian);
+        assert_eq!(
+            "💰  *1* \\(01/05/2023\\): cccc  *43,43* aa bbb/1,23 ",
+            result
+        );
+
+        // Dates are shown in the timezone of the chat.
+        let tokyo = ChatSettings {
+            timezone: Some(chrono_tz::Tz::Asia__Tokyo),
+            ..Default::default()
+        };
+        let late_ts = DateTime::from(
+            DateTime::parse_from_str("2023-05-01 23:30:00 +02:00", "%Y-%m-%d %H:%M:%S %z").unwrap(),
+        );
+        let late_expense = SavedExpense::new(1, true, vec![], 100, None, late_ts);
+        assert!(format_expense(&late_expense, &tokyo).contains("\\(2023\\-05\\-02\\)"));
+
+        // The decimal separator can be changed independently of the language.
+        italian.set(Setting::DecimalSeparator(Some('.')));
+        let result = format_expense(&expense, &italian);
+        assert_eq!(
+            "💰  *1* \\(01/05/2023\\): cccc  *43\\.43* aa bbb/1\\.23 ",
+            result
+        );
     }
 
     #[test]
@@ -169,7 +515,7 @@ mod tests {
             MoneyExchange::new("abc", "bb", 32323),
         ];
 
-        let result = format_balance(&exchanges);
+        let result = format_balance(&exchanges, &ChatSettings::default());
 
         assert_eq!(
             r"💸 `aa  ` *34* `bb`
@@ -183,8 +529,275 @@ mod tests {
     #[test]
     fn test_format_simple_list() {
         let elements = vec!["g1", "g2", "g3"];
-        let result = format_simple_list(&elements);
+        let result = format_simple_list(&elements, &ChatSettings::default());
 
         assert_eq!("- g1\n- g2\n- g3\n", result);
+
+        let italian = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        let result = format_simple_list::<&str>(&[], &italian);
+        assert_eq!("Niente da mostrare!", result);
+    }
+
+    #[test]
+    fn test_format_balance_italian() {
+        let italian = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        let exchanges = vec![MoneyExchange::new("aa", "bb", 2112)];
+        let result = format_balance(&exchanges, &italian);
+        assert_eq!("💸 `aa` *21,12* `bb`\n", result);
+
+        let result = format_balance(&[], &italian);
+        assert_eq!("Tutto in pari\\!", result);
+    }
+
+    #[test]
+    fn test_format_net_positions() {
+        let positions = vec![
+            NetPosition::new("aacc", 3520),
+            NetPosition::new("b", 0),
+            NetPosition::new("abc", -3520),
+        ];
+
+        let result = format_net_positions(&positions, &ChatSettings::default());
+        assert_eq!(
+            "🔺 `aacc` *\\+35\\.20*\n🔺 `b   ` *0\\.00*\n🔻 `abc ` *\\-35\\.20*\n",
+            result
+        );
+
+        let italian = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        assert_eq!("Tutto in pari\\!", format_net_positions(&[], &italian));
+    }
+
+    #[test]
+    fn test_format_impossible_settlement() {
+        let impossible = ImpossibleSettlement {
+            unsettled: vec![NetPosition::new("aa", 500), NetPosition::new("bbb", -500)],
+        };
+        assert_eq!(
+            "⚠️ The settlement constraints do not allow to settle these positions:\n\n\
+             🔺 `aa ` *\\+5\\.00*\n🔻 `bbb` *\\-5\\.00*\n",
+            format_impossible_settlement(&impossible, &ChatSettings::default())
+        );
+    }
+
+    #[test]
+    fn test_format_settlement_constraints() {
+        let constraints = vec![
+            SettlementConstraint::OnlyPays("aa".to_string(), "bb".to_string()),
+            SettlementConstraint::Forbidden("aa".to_string(), "cc".to_string()),
+            SettlementConstraint::NoRouting("bb".to_string()),
+        ];
+        assert_eq!(
+            "- aa only pays bb\n- aa and cc never pay each other\n- bb never passes money on\n",
+            format_settlement_constraints(&constraints, &ChatSettings::default())
+        );
+    }
+
+    #[test]
+    fn test_format_why() {
+        let message_ts = DateTime::from(
+            DateTime::parse_from_str("2023-05-01 10:00:00 +02:00", "%Y-%m-%d %H:%M:%S %z").unwrap(),
+        );
+        let first = SavedExpense::new(1, true, vec![], 3000, Some("pizza".into()), message_ts);
+        let second = SavedExpense::new(2, true, vec![], 1000, None, message_ts);
+        let contributions = vec![
+            ExpenseContribution {
+                expense: &first,
+                share: 1000.0,
+                paid: 3000.0,
+                net: 2000.0,
+            },
+            ExpenseContribution {
+                expense: &second,
+                share: 2500.4,
+                paid: 0.0,
+                net: -500.4,
+            },
+        ];
+
+        let result = format_why("aa", &contributions, &ChatSettings::default());
+        assert_eq!(
+            "🧾 *1* \\(2023\\-05\\-01\\) \\- pizza\n    paid 30\\.00, share 10\\.00, net \\+20\\.00\n\
+             🧾 *2* \\(2023\\-05\\-01\\)\n    paid 0\\.00, share 25\\.00, net \\-5\\.00\n\
+             \n*aa owes 5\\.00*",
+            result
+        );
+
+        let italian = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        let result = format_why("aa", &contributions[..1], &italian);
+        assert!(result.ends_with("*aa deve ricevere 20,00*"));
+        assert_eq!("Niente da mostrare\\!", format_why("aa", &[], &italian));
+    }
+
+    #[test]
+    fn test_format_between() {
+        let message_ts = DateTime::from(
+            DateTime::parse_from_str("2023-05-01 10:00:00 +02:00", "%Y-%m-%d %H:%M:%S %z").unwrap(),
+        );
+        let first = SavedExpense::new(1, true, vec![], 3000, Some("pizza".into()), message_ts);
+        let second = SavedExpense::new(2, true, vec![], 1000, None, message_ts);
+        let contributions = vec![
+            PairwiseContribution {
+                expense: &first,
+                amount: 1000.0,
+            },
+            PairwiseContribution {
+                expense: &second,
+                amount: -1500.0,
+            },
+        ];
+
+        let result = format_between("aa", "bb", &contributions, true, &ChatSettings::default());
+        assert_eq!(
+            "🧾 *1* \\(2023\\-05\\-01\\) \\- pizza: \\+10\\.00\n\
+             🧾 *2* \\(2023\\-05\\-01\\): \\-15\\.00\n\
+             \n*bb owes 5\\.00 to aa*",
+            result
+        );
+
+        let italian = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        let result = format_between("aa", "bb", &contributions[..1], false, &italian);
+        assert_eq!("*aa deve dare 10,00 a bb*", result);
+        let result = format_between("aa", "bb", &[], true, &italian);
+        assert_eq!("*aa e bb sono in pari*", result);
+    }
+
+    #[test]
+    fn test_format_settings() {
+        let mut settings = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        settings.set(Setting::AutoRegister(true));
+
+        assert_eq!(
+            "Impostazioni di questa chat (tocca un'impostazione per cambiarla):\n\n\
+             - Spese per pagina: 15\n\
+             - Separatore decimale: predefinito (,)\n\
+             - Registrazione automatica: sì\n\
+             - Lingua: Italiano\n\
+             - Fuso orario: server\n",
+            format_settings(&settings)
+        );
+
+        settings.set(Setting::DecimalSeparator(Some('.')));
+        assert_eq!(
+            "Separatore decimale: .",
+            format_setting(&settings, SettingKey::DecimalSeparator)
+        );
+    }
+
+    #[test]
+    fn test_format_import_report() {
+        let mut report = ImportReport {
+            dry_run: true,
+            num_expenses: 2,
+            errors: vec![],
+        };
+        assert_eq!(
+            "All 2 expenses are valid. Nothing was saved: repeat the command without dry-run \
+             to import them.",
+            format_import_report(&report, &ChatSettings::default())
+        );
+
+        report.errors = vec![
+            (
+                2,
+                InputError::unregistered_participant("aa".to_string(), vec![]).into(),
+            ),
+            (
+                5,
+                InputError::invalid_csv(CsvProblem::InvalidAmount("x".to_string())).into(),
+            ),
+        ];
+        assert_eq!(
+            "Nothing was imported because of these errors:\n\n\
+             - line 2: `aa` is not a registered participant\n\
+             - line 5: invalid CSV file: `x` is not a valid amount\n",
+            format_import_report(&report, &ChatSettings::default())
+        );
+    }
+
+    #[test]
+    fn test_format_group_tree() {
+        let settings = ChatSettings::default();
+        let tree = |name: &str, participants: &[&str], subgroups| GroupTree {
+            name: name.to_string(),
+            participants: participants.iter().map(|p| p.to_string()).collect(),
+            subgroups,
+        };
+
+        let everyone = tree(
+            "everyone",
+            &["zz"],
+            vec![
+                tree("flat1", &["aa", "bb"], vec![tree("empty", &[], vec![])]),
+                tree("flat2", &["cc"], vec![]),
+            ],
+        );
+        assert_eq!(
+            "- zz\n- #flat1\n  - aa\n  - bb\n  - #empty\n- #flat2\n  - cc\n",
+            format_group_tree(&everyone, &settings)
+        );
+        assert_eq!(
+            "Nothing to show!",
+            format_group_tree(&tree("empty", &[], vec![]), &settings)
+        );
+    }
+
+    #[test]
+    fn test_format_error_message() {
+        let error: anyhow::Error = parse_expense("c1 12d d1 - a.b").expect_err("test").into();
+        assert_eq!(
+            "invalid syntax for an expense: expected the amount at column 4, found \\`12d\\`\n\n\
+             ```\nc1 12d d1 - a.b\n   ^\n```",
+            format_error_message(&error, Language::English)
+        );
+
+        let error: anyhow::Error =
+            InputError::unregistered_participant("a.b".to_string(), vec![]).into();
+        assert_eq!(
+            "\\`a\\.b\\` is not a registered participant",
+            format_error_message(&error, Language::English)
+        );
+    }
+
+    #[test]
+    fn test_format_parsed_expense() {
+        let mut settings = ChatSettings::default();
+        let mut expense = ParsedExpense::new(
+            vec![
+                ParsedParticipant::new_creditor("luca", None),
+                ParsedParticipant::new_debtor("anna", Some(550)),
+                ParsedParticipant::new_debtor_group("friends", None),
+            ],
+            1200,
+            Some("taxi".to_string()),
+        );
+        assert_eq!(
+            "luca 12.00 anna/5.50 #friends - taxi",
+            format_parsed_expense(&expense, &settings)
+        );
+
+        settings.set(Setting::DecimalSeparator(Some(',')));
+        expense.message = None;
+        assert_eq!(
+            "luca 12,00 anna/5,50 #friends",
+            format_parsed_expense(&expense, &settings)
+        );
     }
 }
@@ -0,0 +1,326 @@
+//! Help for bot commands.
+//!
+//! Commands are grouped in sections, as in INSTRUCTIONS.md. The same data is used to
+//! render `/help` and to register the command menu with Telegram, where shortcuts are
+//! omitted to keep the menu short. All text comes from the [`Catalog`] of the language.
+
+use teloxide::{
+    types::BotCommand,
+    utils::markdown::{bold, code_inline, escape},
+};
+
+use crate::i18n::{Catalog, Language};
+
+/// The topic of a group of commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelpTopic {
+    Expenses,
+    Participants,
+    Aliases,
+    Groups,
+    SettlementConstraints,
+    Other,
+}
+
+/// A group of related commands.
+pub struct HelpSection {
+    pub topic: HelpTopic,
+    pub commands: &'static [CommandHelp],
+}
+
+/// A command, together with its optional shortcut.
+///
+/// The short description and the detailed help page of each command are in the
+/// [`Catalog`]. Help pages are embedded in the binary from `src/help/*.md` (English) and
+/// `src/help/it/*.md` (Italian). They use a small subset of Markdown: lines starting with
+/// `## ` are headings and text between backticks is code, everything else is plain text
+/// (see [`format_help_page`]).
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub shortcut: Option<&'static str>,
+    pub in_private_chats: bool,
+}
+
+impl CommandHelp {
+    const fn new(name: &'static str, shortcut: Option<&'static str>) -> Self {
+        CommandHelp {
+            name,
+            shortcut,
+            in_private_chats: true,
+        }
+    }
+
+    /// Commands that only make sense when several people share the chat are not shown
+    /// in the menu of private chats (they still work if typed).
+    const fn group_chats_only(mut self) -> Self {
+        self.in_private_chats = false;
+        self
+    }
+}
+
+pub const HELP_SECTIONS: &[HelpSection] = &[
+    HelpSection {
+        topic: HelpTopic::Expenses,
+        commands: &[
+            CommandHelp::new("expense", Some("e")),
+            CommandHelp::new("balance", Some("b")),
+            CommandHelp::new("net", None),
+            CommandHelp::new("between", None),
+            CommandHelp::new("why", None),
+            CommandHelp::new("reset", None),
+            CommandHelp::new("list", Some("l")),
+            CommandHelp::new("listall", Some("la")),
+            CommandHelp::new("delete", None),
+            CommandHelp::new("export", None),
+            CommandHelp::new("report", None),
+            CommandHelp::new("chart", None),
+            CommandHelp::new("import", None),
+            CommandHelp::new("backup", None),
+            CommandHelp::new("restore", None),
+        ],
+    },
+    HelpSection {
+        topic: HelpTopic::Participants,
+        commands: &[
+            CommandHelp::new("addparticipants", Some("ap")),
+            CommandHelp::new("removeparticipants", Some("rp")),
+            CommandHelp::new("renameparticipant", Some("rnp")),
+            CommandHelp::new("mergeparticipants", Some("mp")),
+            CommandHelp::new("listparticipants", Some("lp")),
+        ],
+    },
+    HelpSection {
+        topic: HelpTopic::Aliases,
+        commands: &[
+            CommandHelp::new("addparticipantaliases", Some("apa")).group_chats_only(),
+            CommandHelp::new("removeparticipantaliases", Some("rpa")).group_chats_only(),
+            CommandHelp::new("listparticipantaliases", Some("lpa")).group_chats_only(),
+        ],
+    },
+    HelpSection {
+        topic: HelpTopic::Groups,
+        commands: &[
+            CommandHelp::new("addgroup", Some("ag")).group_chats_only(),
+            CommandHelp::new("removegroup", Some("rg")).group_chats_only(),
+            CommandHelp::new("renamegroup", Some("rng")).group_chats_only(),
+            CommandHelp::new("addgroupmembers", Some("agm")).group_chats_only(),
+            CommandHelp::new("removegroupmembers", Some("rgm")).group_chats_only(),
+            CommandHelp::new("listgroups", Some("lg")).group_chats_only(),
+            CommandHelp::new("listgroupmembers", Some("lgm")).group_chats_only(),
+        ],
+    },
+    HelpSection {
+        topic: HelpTopic::SettlementConstraints,
+        commands: &[
+            CommandHelp::new("addconstraint", Some("ac")).group_chats_only(),
+            CommandHelp::new("removeconstraint", Some("rc")).group_chats_only(),
+            CommandHelp::new("listconstraints", Some("lc")).group_chats_only(),
+        ],
+    },
+    HelpSection {
+        topic: HelpTopic::Other,
+        commands: &[
+            CommandHelp::new("help", None),
+            CommandHelp::new("toggleautoregister", None),
+            CommandHelp::new("isautoregister", None),
+            CommandHelp::new("language", None),
+            CommandHelp::new("settings", None),
+            CommandHelp::new("timezone", None),
+        ],
+    },
+];
+
+/// The commands to show in the Telegram menu: shortcuts are left out.
+///
+/// If `private_chat` is true, commands that only make sense in group chats are left out too.
+pub fn menu_commands(private_chat: bool, language: Language) -> Vec<BotCommand> {
+    let catalog = language.catalog();
+    HELP_SECTIONS
+        .iter()
+        .flat_map(|s| s.commands.iter())
+        .filter(|c| c.in_private_chats || !private_chat)
+        .map(|c| BotCommand::new(c.name, catalog.command_description(c.name)))
+        .collect()
+}
+
+/// Find the help of a command, given either its name or its shortcut.
+pub fn find_command(name: &str) -> Option<&'static CommandHelp> {
+    let name = name.trim().trim_start_matches('/').to_lowercase();
+    HELP_SECTIONS
+        .iter()
+        .flat_map(|s| s.commands.iter())
+        .find(|c| c.name == name || c.shortcut == Some(name.as_str()))
+}
+
+/// The `/help` message, formatted as MarkdownV2.
+pub fn format_help(language: Language) -> String {
+    let catalog = language.catalog();
+    let sections = HELP_SECTIONS
+        .iter()
+        .map(|s| {
+            let commands = s
+                .commands
+                .iter()
+                .map(|c| format_command_help(c, catalog))
+                .fold(String::new(), |a, b| a + &b + "\n");
+            format!("*{}*:\n\n{}", escape(catalog.help_topic(s.topic)), commands)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "{}\n\n{}\n{}",
+        escape(catalog.help_intro()),
+        sections,
+        escape(catalog.help_details())
+    )
+}
+
+fn format_command_help(command: &CommandHelp, catalog: &dyn Catalog) -> String {
+    let names = match command.shortcut {
+        Some(shortcut) => catalog.command_with_shortcut(command.name, shortcut),
+        None => format!("/{}", command.name),
+    };
+    escape(&format!(
+        "- {}: {}",
+        names,
+        catalog.command_description(command.name)
+    ))
+}
+
+/// The detailed help of a single command, formatted as MarkdownV2.
+pub fn format_help_page(command: &CommandHelp, language: Language) -> String {
+    let title = match command.shortcut {
+        Some(shortcut) => format!("/{} (/{})", command.name, shortcut),
+        None => format!("/{}", command.name),
+    };
+
+    let page = language
+        .catalog()
+        .help_page(command.name)
+        .trim_end()
+        .lines()
+        .map(format_help_line)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("{}\n\n{}", bold(&escape(&title)), page)
+}
+
+fn format_help_line(line: &str) -> String {
+    if let Some(heading) = line.strip_prefix("## ") {
+        return bold(&escape(heading));
+    }
+
+    // Text between backticks is code: since backticks come in pairs, every odd
+    // segment is code and every even segment is plain text.
+    line.split('`')
+        .enumerate()
+        .map(|(i, segment)| {
+            if i % 2 == 1 {
+                code_inline(segment)
+            } else {
+                escape(segment)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::utils::command::BotCommands;
+
+    use crate::bot_commands::Command;
+
+    use super::*;
+
+    #[test]
+    fn test_all_commands_are_documented() {
+        for command in Command::bot_commands() {
+            let name = command.command.trim_start_matches('/');
+            let help = find_command(name);
+            assert!(
+                help.is_some(),
+                "command /{name} is missing from the help sections"
+            );
+            // Shortcuts are documented by their command.
+            let name = help.expect("test").name;
+            for language in Language::ALL {
+                let catalog = language.catalog();
+                assert!(
+                    !catalog.command_description(name).is_empty(),
+                    "command /{name} has no description in {}",
+                    language.name()
+                );
+                assert!(
+                    !catalog.help_page(name).trim().is_empty(),
+                    "command /{name} has an empty help page in {}",
+                    language.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_help_pages_have_balanced_backticks() {
+        for command in HELP_SECTIONS.iter().flat_map(|s| s.commands.iter()) {
+            for language in Language::ALL {
+                for line in language.catalog().help_page(command.name).lines() {
+                    assert!(
+                        line.matches('`').count() % 2 == 0,
+                        "unbalanced backticks in the help page of /{} in {}: {line}",
+                        command.name,
+                        language.name()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_format_help_line() {
+        assert_eq!("*Syntax*", format_help_line("## Syntax"));
+        assert_eq!("`/delete 12`", format_help_line("`/delete 12`"));
+        assert_eq!(
+            "p2 owes 4\\. Use `/list`\\.",
+            format_help_line("p2 owes 4. Use `/list`.")
+        );
+    }
+
+    #[test]
+    fn test_find_command() {
+        assert_eq!("expense", find_command(" /E ").expect("test").name);
+        assert_eq!("listall", find_command("listall").expect("test").name);
+        assert!(find_command("nonexistent").is_none());
+    }
+
+    #[test]
+    fn test_menu_commands() {
+        let group_menu = menu_commands(false, Language::English);
+        let private_menu = menu_commands(true, Language::English);
+
+        assert!(group_menu.iter().any(|c| c.command == "addgroup"));
+        assert!(!private_menu.iter().any(|c| c.command == "addgroup"));
+        assert!(private_menu.iter().any(|c| c.command == "expense"));
+
+        // No shortcuts in the menu.
+        assert!(!group_menu.iter().any(|c| c.command == "e"));
+
+        let italian_menu = menu_commands(false, Language::Italian);
+        assert_eq!(group_menu.len(), italian_menu.len());
+        assert_eq!("registra una nuova spesa", italian_menu[0].description);
+    }
+
+    #[test]
+    fn test_format_command_help() {
+        let command = find_command("e").expect("test");
+        assert_eq!(
+            "\\- /expense or /e: register a new expense",
+            format_command_help(command, Language::English.catalog())
+        );
+        assert_eq!(
+            "\\- /expense o /e: registra una nuova spesa",
+            format_command_help(command, Language::Italian.catalog())
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! English messages.
+
+use chrono_tz::Tz;
+
+use crate::{
+    chart::ChartKind, error::InputError, help::HelpTopic, report::ReportLabel,
+    settings::SettingKey, types::SettlementConstraint,
+};
+
+use super::{format_available_languages, Catalog, Language};
+
+pub struct English;
+
+impl Catalog for English {
+    fn decimal_separator(&self) -> char {
+        '.'
+    }
+
+    fn date_format(&self) -> &'static str {
+        "%Y-%m-%d"
+    }
+
+    fn nothing_to_show(&self) -> &'static str {
+        "Nothing to show!"
+    }
+
+    fn why_step(&self, paid: &str, share: &str, net: &str) -> String {
+        format!("paid {paid}, share {share}, net {net}")
+    }
+
+    fn why_is_owed(&self, participant: &str, amount: &str) -> String {
+        format!("{participant} is owed {amount}")
+    }
+
+    fn why_owes(&self, participant: &str, amount: &str) -> String {
+        format!("{participant} owes {amount}")
+    }
+
+    fn why_is_even(&self, participant: &str) -> String {
+        format!("{participant} is even")
+    }
+
+    fn between_owes(&self, debtor: &str, creditor: &str, amount: &str) -> String {
+        format!("{debtor} owes {amount} to {creditor}")
+    }
+
+    fn between_even(&self, p1: &str, p2: &str) -> String {
+        format!("{p1} and {p2} are even")
+    }
+
+    fn balance_at(&self, date: &str) -> String {
+        format!("Balance at the end of {date}:")
+    }
+
+    fn balance_before(&self, expense_id: i64) -> String {
+        format!("Balance before expense {expense_id}:")
+    }
+
+    fn all_clean(&self) -> &'static str {
+        "All clean!"
+    }
+
+    fn impossible_settlement(&self) -> &'static str {
+        "The settlement constraints do not allow to settle these positions:"
+    }
+
+    fn describe_settlement_constraint(&self, constraint: &SettlementConstraint) -> String {
+        match constraint {
+            SettlementConstraint::OnlyPays(p1, p2) => format!("{p1} only pays {p2}"),
+            SettlementConstraint::Forbidden(p1, p2) => {
+                format!("{p1} and {p2} never pay each other")
+            }
+            SettlementConstraint::NoRouting(p) => format!("{p} never passes money on"),
+        }
+    }
+
+    fn previous_page(&self) -> &'static str {
+        "Previous"
+    }
+
+    fn next_page(&self) -> &'static str {
+        "Next"
+    }
+
+    fn expense_corrected(&self, expense: &str) -> String {
+        format!("Saved the corrected expense: {expense}")
+    }
+
+    fn confirm_expense(&self, expense: &str) -> String {
+        format!("I understood this expense:\n{expense}\nShall I save it?")
+    }
+
+    fn save_expense(&self) -> &'static str {
+        "✅ Save"
+    }
+
+    fn cancel_expense(&self) -> &'static str {
+        "❌ Cancel"
+    }
+
+    fn expense_saved(&self, expense: &str) -> String {
+        format!("Saved the expense: {expense}")
+    }
+
+    fn expense_not_saved(&self) -> &'static str {
+        "The expense was not saved."
+    }
+
+    fn expense_already_confirmed(&self) -> &'static str {
+        "This expense was already saved or discarded."
+    }
+
+    fn auto_register_enabled(&self) -> &'static str {
+        "The 'auto register' mode is ENABLED: participants used in expenses will \
+         be automatically registered as participants if they are not already."
+    }
+
+    fn auto_register_disabled(&self) -> &'static str {
+        "The 'auto register' mode is DISABLED: using unregistered participants in \
+         expenses will result in an error."
+    }
+
+    fn auto_register_is_enabled(&self) -> &'static str {
+        "The 'auto register' mode is ENABLED."
+    }
+
+    fn auto_register_is_disabled(&self) -> &'static str {
+        "The 'auto register' mode is DISABLED."
+    }
+
+    fn current_language(&self, language: Language) -> String {
+        let name = match language {
+            Language::English => "English",
+            Language::Italian => "Italian",
+        };
+        format!(
+            "The language of this chat is {name}. Available languages: {}.",
+            format_available_languages()
+        )
+    }
+
+    fn language_changed(&self) -> &'static str {
+        "The language of this chat is now English."
+    }
+
+    fn current_timezone(&self, timezone: Option<Tz>) -> String {
+        match timezone {
+            Some(timezone) => format!("The timezone of this chat is {}.", timezone.name()),
+            None => "This chat uses the timezone of the server. Use /timezone followed by a \
+                     name such as Europe/Rome to change it."
+                .to_string(),
+        }
+    }
+
+    fn timezone_changed(&self, timezone: Option<Tz>) -> String {
+        match timezone {
+            Some(timezone) => format!("The timezone of this chat is now {}.", timezone.name()),
+            None => "This chat now uses the timezone of the server.".to_string(),
+        }
+    }
+
+    fn server_timezone(&self) -> &'static str {
+        "server"
+    }
+
+    fn import_saved(&self, num_expenses: usize) -> String {
+        format!("Imported {num_expenses} expenses.")
+    }
+
+    fn import_checked(&self, num_expenses: usize) -> String {
+        format!(
+            "All {num_expenses} expenses are valid. Nothing was saved: repeat the command \
+             without dry-run to import them."
+        )
+    }
+
+    fn import_failed(&self) -> &'static str {
+        "Nothing was imported because of these errors:"
+    }
+
+    fn import_error(&self, line: usize, error: &str) -> String {
+        format!("line {line}: {error}")
+    }
+
+    fn backup_restored(&self) -> &'static str {
+        "The backup was restored."
+    }
+
+    fn report_label(&self, label: ReportLabel) -> &'static str {
+        match label {
+            ReportLabel::Title => "Expense report",
+            ReportLabel::Settlement => "Settlement",
+            ReportLabel::People => "People",
+            ReportLabel::Categories => "Categories",
+            ReportLabel::Expenses => "Expenses",
+            ReportLabel::Debtor => "Who pays",
+            ReportLabel::Creditor => "To whom",
+            ReportLabel::Amount => "Amount",
+            ReportLabel::Person => "Person",
+            ReportLabel::Paid => "Paid",
+            ReportLabel::Spent => "Spent",
+            ReportLabel::Balance => "Balance",
+            ReportLabel::Category => "Category",
+            ReportLabel::NumExpenses => "Expenses",
+            ReportLabel::Date => "Date",
+            ReportLabel::Description => "Description",
+            ReportLabel::PaidBy => "Paid by",
+            ReportLabel::SplitAmong => "Split among",
+            ReportLabel::NoDescription => "No description",
+        }
+    }
+
+    fn report_summary(&self, num_expenses: usize, first: &str, last: &str, total: &str) -> String {
+        match num_expenses {
+            1 => format!("1 expense on {first}, for a total of {total}."),
+            n => format!("{n} expenses from {first} to {last}, for a total of {total}."),
+        }
+    }
+
+    fn chart_title(&self, kind: ChartKind) -> &'static str {
+        match kind {
+            ChartKind::People => "Paid and spent by person",
+            ChartKind::Categories => "Biggest expenses by description",
+            ChartKind::Time => "Total spent over time",
+        }
+    }
+
+    fn help_intro(&self) -> &'static str {
+        "This bot keeps track of debts and credits in a group. Supported commands:"
+    }
+
+    fn help_details(&self) -> &'static str {
+        "Use /help followed by a command (e.g. /help expense) for the details."
+    }
+
+    fn help_topic(&self, topic: HelpTopic) -> &'static str {
+        match topic {
+            HelpTopic::Expenses => "commands to manage expenses",
+            HelpTopic::Participants => "commands to manage participants",
+            HelpTopic::Aliases => "commands to manage aliases",
+            HelpTopic::Groups => "commands to manage groups",
+            HelpTopic::SettlementConstraints => "commands to manage settlement constraints",
+            HelpTopic::Other => "other commands",
+        }
+    }
+
+    fn command_with_shortcut(&self, name: &str, shortcut: &str) -> String {
+        format!("/{name} or /{shortcut}")
+    }
+
+    fn command_description(&self, command: &str) -> &'static str {
+        match command {
+            "expense" => "register a new expense",
+            "balance" => "show the current or a past balance",
+            "net" => "show how much each participant is owed or owes",
+            "between" => "show the debt between two participants",
+            "why" => "explain the balance of a participant",
+            "reset" => "cancel all outstanding debts",
+            "list" => "show the expenses corresponding to outstanding debts",
+            "listall" => "show all expenses",
+            "delete" => "delete an expense by ID",
+            "export" => "send a file with the expenses",
+            "report" => "send a report with expenses, totals and settlement",
+            "chart" => "draw charts of the expenses",
+            "import" => "import the expenses of a CSV file",
+            "backup" => "send a file with all the data of the chat",
+            "restore" => "restore a backup in a new chat",
+            "addparticipants" => "add participants that can be used in expenses",
+            "removeparticipants" => {
+                "remove participants that should not appear in expenses anymore"
+            }
+            "renameparticipant" => "rename a participant, keeping their expenses",
+            "mergeparticipants" => "merge two participants that are the same person",
+            "listparticipants" => "show all registered participants",
+            "addparticipantaliases" => "add aliases for a participant",
+            "removeparticipantaliases" => "remove aliases for a participant",
+            "listparticipantaliases" => "show all aliases of a participant",
+            "addgroup" => "create a group of participants",
+            "removegroup" => "remove a group of participants",
+            "renamegroup" => "rename a group",
+            "addgroupmembers" => "add members to a group",
+            "removegroupmembers" => "remove members from a group",
+            "listgroups" => "show all existing groups",
+            "listgroupmembers" => "show all members of a group",
+            "addconstraint" => "add a constraint on how debts are settled",
+            "removeconstraint" => "remove a constraint on how debts are settled",
+            "listconstraints" => "show all constraints on how debts are settled",
+            "help" => "show the list of commands or the help of a command",
+            "toggleautoregister" => "enable or disable the automatic registration of participants",
+            "isautoregister" => "check if the automatic registration of participants is enabled",
+            "language" => "show or change the language of the chat",
+            "settings" => "show and change the settings of the chat",
+            "timezone" => "show or change the timezone of the chat",
+            _ => "",
+        }
+    }
+
+    fn help_page(&self, command: &str) -> &'static str {
+        match command {
+            "expense" => include_str!("../help/expense.md"),
+            "balance" => include_str!("../help/balance.md"),
+            "net" => include_str!("../help/net.md"),
+            "between" => include_str!("../help/between.md"),
+            "why" => include_str!("../help/why.md"),
+            "reset" => include_str!("../help/reset.md"),
+            "list" => include_str!("../help/list.md"),
+            "listall" => include_str!("../help/listall.md"),
+            "delete" => include_str!("../help/delete.md"),
+            "export" => include_str!("../help/export.md"),
+            "report" => include_str!("../help/report.md"),
+            "chart" => include_str!("../help/chart.md"),
+            "import" => include_str!("../help/import.md"),
+            "backup" => include_str!("../help/backup.md"),
+            "restore" => include_str!("../help/restore.md"),
+            "addparticipants" => include_str!("../help/addparticipants.md"),
+            "removeparticipants" => include_str!("../help/removeparticipants.md"),
+            "renameparticipant" => include_str!("../help/renameparticipant.md"),
+            "mergeparticipants" => include_str!("../help/mergeparticipants.md"),
+            "listparticipants" => include_str!("../help/listparticipants.md"),
+            "addparticipantaliases" => include_str!("../help/addparticipantaliases.md"),
+            "removeparticipantaliases" => include_str!("../help/removeparticipantaliases.md"),
+            "listparticipantaliases" => include_str!("../help/listparticipantaliases.md"),
+            "addgroup" => include_str!("../help/addgroup.md"),
+            "removegroup" => include_str!("../help/removegroup.md"),
+            "renamegroup" => include_str!("../help/renamegroup.md"),
+            "addgroupmembers" => include_str!("../help/addgroupmembers.md"),
+            "removegroupmembers" => include_str!("../help/removegroupmembers.md"),
+            "listgroups" => include_str!("../help/listgroups.md"),
+            "listgroupmembers" => include_str!("../help/listgroupmembers.md"),
+            "addconstraint" => include_str!("../help/addconstraint.md"),
+            "removeconstraint" => include_str!("../help/removeconstraint.md"),
+            "listconstraints" => include_str!("../help/listconstraints.md"),
+            "help" => include_str!("../help/help.md"),
+            "toggleautoregister" => include_str!("../help/toggleautoregister.md"),
+            "isautoregister" => include_str!("../help/isautoregister.md"),
+            "language" => include_str!("../help/language.md"),
+            "settings" => include_str!("../help/settings.md"),
+            "timezone" => include_str!("../help/timezone.md"),
+            _ => "",
+        }
+    }
+
+    fn settings_title(&self) -> &'static str {
+        "Settings of this chat (tap a setting to change it):"
+    }
+
+    fn setting_name(&self, key: SettingKey) -> &'static str {
+        match key {
+            SettingKey::PageSize => "Page size",
+            SettingKey::DecimalSeparator => "Decimal separator",
+            SettingKey::AutoRegister => "Auto register",
+            SettingKey::Language => "Language",
+            SettingKey::Timezone => "Timezone",
+        }
+    }
+
+    fn setting_default(&self) -> &'static str {
+        "default"
+    }
+
+    fn setting_on(&self) -> &'static str {
+        "on"
+    }
+
+    fn setting_off(&self) -> &'static str {
+        "off"
+    }
+
+    fn input_error(&self, error: &InputError) -> String {
+        // Error messages are written in English in the first place.
+        format!("{error}")
+    }
+
+    fn database_error(&self) -> &'static str {
+        "Cannot query the database, please try again later."
+    }
+
+    fn telegram_error(&self) -> &'static str {
+        "Cannot communicate with Telegram server, please try again later."
+    }
+}
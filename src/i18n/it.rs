@@ -0,0 +1,609 @@
+//! Italian messages.
+
+use chrono_tz::Tz;
+
+use crate::{
+    chart::ChartKind,
+    error::{BackupProblem, CsvProblem, ExpectedToken, ExpenseProblem, InputError},
+    help::HelpTopic,
+    report::ReportLabel,
+    settings::SettingKey,
+    types::SettlementConstraint,
+};
+
+use super::{format_available_languages, Catalog, Language};
+
+pub struct Italian;
+
+impl Catalog for Italian {
+    fn decimal_separator(&self) -> char {
+        ','
+    }
+
+    fn date_format(&self) -> &'static str {
+        "%d/%m/%Y"
+    }
+
+    fn nothing_to_show(&self) -> &'static str {
+        "Niente da mostrare!"
+    }
+
+    fn why_step(&self, paid: &str, share: &str, net: &str) -> String {
+        format!("pagato {paid}, quota {share}, netto {net}")
+    }
+
+    fn why_is_owed(&self, participant: &str, amount: &str) -> String {
+        format!("{participant} deve ricevere {amount}")
+    }
+
+    fn why_owes(&self, participant: &str, amount: &str) -> String {
+        format!("{participant} deve dare {amount}")
+    }
+
+    fn why_is_even(&self, participant: &str) -> String {
+        format!("{participant} è in pari")
+    }
+
+    fn between_owes(&self, debtor: &str, creditor: &str, amount: &str) -> String {
+        format!("{debtor} deve dare {amount} a {creditor}")
+    }
+
+    fn between_even(&self, p1: &str, p2: &str) -> String {
+        format!("{p1} e {p2} sono in pari")
+    }
+
+    fn balance_at(&self, date: &str) -> String {
+        format!("Saldo alla fine del {date}:")
+    }
+
+    fn balance_before(&self, expense_id: i64) -> String {
+        format!("Saldo prima della spesa {expense_id}:")
+    }
+
+    fn all_clean(&self) -> &'static str {
+        "Tutto in pari!"
+    }
+
+    fn impossible_settlement(&self) -> &'static str {
+        "I vincoli di pagamento non permettono di saldare queste posizioni:"
+    }
+
+    fn describe_settlement_constraint(&self, constraint: &SettlementConstraint) -> String {
+        match constraint {
+            SettlementConstraint::OnlyPays(p1, p2) => format!("{p1} paga solo {p2}"),
+            SettlementConstraint::Forbidden(p1, p2) => format!("{p1} e {p2} non si pagano mai"),
+            SettlementConstraint::NoRouting(p) => format!("{p} non fa mai da tramite"),
+        }
+    }
+
+    fn previous_page(&self) -> &'static str {
+        "Precedente"
+    }
+
+    fn next_page(&self) -> &'static str {
+        "Successiva"
+    }
+
+    fn expense_corrected(&self, expense: &str) -> String {
+        format!("Ho salvato la spesa corretta: {expense}")
+    }
+
+    fn confirm_expense(&self, expense: &str) -> String {
+        format!("Ho capito questa spesa:\n{expense}\nLa salvo?")
+    }
+
+    fn save_expense(&self) -> &'static str {
+        "✅ Salva"
+    }
+
+    fn cancel_expense(&self) -> &'static str {
+        "❌ Annulla"
+    }
+
+    fn expense_saved(&self, expense: &str) -> String {
+        format!("Ho salvato la spesa: {expense}")
+    }
+
+    fn expense_not_saved(&self) -> &'static str {
+        "La spesa non è stata salvata."
+    }
+
+    fn expense_already_confirmed(&self) -> &'static str {
+        "Questa spesa è già stata salvata o scartata."
+    }
+
+    fn auto_register_enabled(&self) -> &'static str {
+        "La registrazione automatica è ATTIVA: i partecipanti usati nelle spese \
+         verranno registrati automaticamente se non lo sono già."
+    }
+
+    fn auto_register_disabled(&self) -> &'static str {
+        "La registrazione automatica è DISATTIVATA: usare nelle spese partecipanti \
+         non registrati causerà un errore."
+    }
+
+    fn auto_register_is_enabled(&self) -> &'static str {
+        "La registrazione automatica è ATTIVA."
+    }
+
+    fn auto_register_is_disabled(&self) -> &'static str {
+        "La registrazione automatica è DISATTIVATA."
+    }
+
+    fn current_language(&self, language: Language) -> String {
+        let name = match language {
+            Language::English => "l'inglese",
+            Language::Italian => "l'italiano",
+        };
+        format!(
+            "La lingua di questa chat è {name}. Lingue disponibili: {}.",
+            format_available_languages()
+        )
+    }
+
+    fn language_changed(&self) -> &'static str {
+        "La lingua di questa chat ora è l'italiano."
+    }
+
+    fn current_timezone(&self, timezone: Option<Tz>) -> String {
+        match timezone {
+            Some(timezone) => format!("Il fuso orario di questa chat è {}.", timezone.name()),
+            None => "Questa chat usa il fuso orario del server. Usa /timezone seguito da un \
+                     nome come Europe/Rome per cambiarlo."
+                .to_string(),
+        }
+    }
+
+    fn timezone_changed(&self, timezone: Option<Tz>) -> String {
+        match timezone {
+            Some(timezone) => format!("Il fuso orario di questa chat ora è {}.", timezone.name()),
+            None => "Questa chat ora usa il fuso orario del server.".to_string(),
+        }
+    }
+
+    fn server_timezone(&self) -> &'static str {
+        "server"
+    }
+
+    fn import_saved(&self, num_expenses: usize) -> String {
+        format!("Spese importate: {num_expenses}.")
+    }
+
+    fn import_checked(&self, num_expenses: usize) -> String {
+        format!(
+            "Tutte le spese ({num_expenses}) sono valide. Non è stato salvato nulla: ripeti \
+             il comando senza dry-run per importarle."
+        )
+    }
+
+    fn import_failed(&self) -> &'static str {
+        "Non è stato importato nulla a causa di questi errori:"
+    }
+
+    fn import_error(&self, line: usize, error: &str) -> String {
+        format!("riga {line}: {error}")
+    }
+
+    fn backup_restored(&self) -> &'static str {
+        "Il backup è stato ripristinato."
+    }
+
+    fn report_label(&self, label: ReportLabel) -> &'static str {
+        match label {
+            ReportLabel::Title => "Resoconto delle spese",
+            ReportLabel::Settlement => "Pareggio dei conti",
+            ReportLabel::People => "Persone",
+            ReportLabel::Categories => "Categorie",
+            ReportLabel::Expenses => "Spese",
+            ReportLabel::Debtor => "Chi paga",
+            ReportLabel::Creditor => "A chi",
+            ReportLabel::Amount => "Importo",
+            ReportLabel::Person => "Persona",
+            ReportLabel::Paid => "Pagato",
+            ReportLabel::Spent => "Speso",
+            ReportLabel::Balance => "Saldo",
+            ReportLabel::Category => "Categoria",
+            ReportLabel::NumExpenses => "Spese",
+            ReportLabel::Date => "Data",
+            ReportLabel::Description => "Descrizione",
+            ReportLabel::PaidBy => "Pagato da",
+            ReportLabel::SplitAmong => "Diviso tra",
+            ReportLabel::NoDescription => "Senza descrizione",
+        }
+    }
+
+    fn report_summary(&self, num_expenses: usize, first: &str, last: &str, total: &str) -> String {
+        match num_expenses {
+            1 => format!("1 spesa il {first}, per un totale di {total}."),
+            n => format!("{n} spese dal {first} al {last}, per un totale di {total}."),
+        }
+    }
+
+    fn chart_title(&self, kind: ChartKind) -> &'static str {
+        match kind {
+            ChartKind::People => "Pagato e speso per persona",
+            ChartKind::Categories => "Spese maggiori per descrizione",
+            ChartKind::Time => "Totale speso nel tempo",
+        }
+    }
+
+    fn help_intro(&self) -> &'static str {
+        "Questo bot tiene traccia di debiti e crediti in un gruppo. Comandi disponibili:"
+    }
+
+    fn help_details(&self) -> &'static str {
+        "Usa /help seguito da un comando (per esempio /help expense) per i dettagli."
+    }
+
+    fn help_topic(&self, topic: HelpTopic) -> &'static str {
+        match topic {
+            HelpTopic::Expenses => "comandi per gestire le spese",
+            HelpTopic::Participants => "comandi per gestire i partecipanti",
+            HelpTopic::Aliases => "comandi per gestire gli alias",
+            HelpTopic::Groups => "comandi per gestire i gruppi",
+            HelpTopic::SettlementConstraints => "comandi per gestire i vincoli di pagamento",
+            HelpTopic::Other => "altri comandi",
+        }
+    }
+
+    fn command_with_shortcut(&self, name: &str, shortcut: &str) -> String {
+        format!("/{name} o /{shortcut}")
+    }
+
+    fn command_description(&self, command: &str) -> &'static str {
+        match command {
+            "expense" => "registra una nuova spesa",
+            "balance" => "mostra il saldo attuale o un saldo passato",
+            "net" => "mostra quanto ogni partecipante deve ricevere o dare",
+            "between" => "mostra il debito tra due partecipanti",
+            "why" => "spiega il saldo di un partecipante",
+            "reset" => "cancella tutti i debiti in sospeso",
+            "list" => "mostra le spese che corrispondono ai debiti in sospeso",
+            "listall" => "mostra tutte le spese",
+            "delete" => "elimina una spesa dato il suo ID",
+            "export" => "invia un file con le spese",
+            "report" => "invia un resoconto con spese, totali e pareggio dei conti",
+            "chart" => "disegna dei grafici delle spese",
+            "import" => "importa le spese di un file CSV",
+            "backup" => "invia un file con tutti i dati della chat",
+            "restore" => "ripristina un backup in una nuova chat",
+            "addparticipants" => "aggiunge partecipanti da usare nelle spese",
+            "removeparticipants" => "rimuove partecipanti che non devono più comparire nelle spese",
+            "renameparticipant" => "rinomina un partecipante, mantenendo le sue spese",
+            "mergeparticipants" => "unisce due partecipanti che sono la stessa persona",
+            "listparticipants" => "mostra tutti i partecipanti registrati",
+            "addparticipantaliases" => "aggiunge alias per un partecipante",
+            "removeparticipantaliases" => "rimuove alias di un partecipante",
+            "listparticipantaliases" => "mostra tutti gli alias di un partecipante",
+            "addgroup" => "crea un gruppo di partecipanti",
+            "removegroup" => "rimuove un gruppo di partecipanti",
+            "renamegroup" => "rinomina un gruppo",
+            "addgroupmembers" => "aggiunge membri a un gruppo",
+            "removegroupmembers" => "rimuove membri da un gruppo",
+            "listgroups" => "mostra tutti i gruppi esistenti",
+            "listgroupmembers" => "mostra tutti i membri di un gruppo",
+            "addconstraint" => "aggiunge un vincolo su come vengono saldati i debiti",
+            "removeconstraint" => "rimuove un vincolo su come vengono saldati i debiti",
+            "listconstraints" => "mostra tutti i vincoli su come vengono saldati i debiti",
+            "help" => "mostra l'elenco dei comandi o l'aiuto di un comando",
+            "toggleautoregister" => {
+                "attiva o disattiva la registrazione automatica dei partecipanti"
+            }
+            "isautoregister" => {
+                "controlla se la registrazione automatica dei partecipanti è attiva"
+            }
+            "language" => "mostra o cambia la lingua della chat",
+            "settings" => "mostra e cambia le impostazioni della chat",
+            "timezone" => "mostra o cambia il fuso orario della chat",
+            _ => "",
+        }
+    }
+
+    fn help_page(&self, command: &str) -> &'static str {
+        match command {
+            "expense" => include_str!("../help/it/expense.md"),
+            "balance" => include_str!("../help/it/balance.md"),
+            "net" => include_str!("../help/it/net.md"),
+            "between" => include_str!("../help/it/between.md"),
+            "why" => include_str!("../help/it/why.md"),
+            "reset" => include_str!("../help/it/reset.md"),
+            "list" => include_str!("../help/it/list.md"),
+            "listall" => include_str!("../help/it/listall.md"),
+            "delete" => include_str!("../help/it/delete.md"),
+            "export" => include_str!("../help/it/export.md"),
+            "report" => include_str!("../help/it/report.md"),
+            "chart" => include_str!("../help/it/chart.md"),
+            "import" => include_str!("../help/it/import.md"),
+            "backup" => include_str!("../help/it/backup.md"),
+            "restore" => include_str!("../help/it/restore.md"),
+            "addparticipants" => include_str!("../help/it/addparticipants.md"),
+            "removeparticipants" => include_str!("../help/it/removeparticipants.md"),
+            "renameparticipant" => include_str!("../help/it/renameparticipant.md"),
+            "mergeparticipants" => include_str!("../help/it/mergeparticipants.md"),
+            "listparticipants" => include_str!("../help/it/listparticipants.md"),
+            "addparticipantaliases" => include_str!("../help/it/addparticipantaliases.md"),
+            "removeparticipantaliases" => include_str!("../help/it/removeparticipantaliases.md"),
+            "listparticipantaliases" => include_str!("../help/it/listparticipantaliases.md"),
+            "addgroup" => include_str!("../help/it/addgroup.md"),
+            "removegroup" => include_str!("../help/it/removegroup.md"),
+            "renamegroup" => include_str!("../help/it/renamegroup.md"),
+            "addgroupmembers" => include_str!("../help/it/addgroupmembers.md"),
+            "removegroupmembers" => include_str!("../help/it/removegroupmembers.md"),
+            "listgroups" => include_str!("../help/it/listgroups.md"),
+            "listgroupmembers" => include_str!("../help/it/listgroupmembers.md"),
+            "addconstraint" => include_str!("../help/it/addconstraint.md"),
+            "removeconstraint" => include_str!("../help/it/removeconstraint.md"),
+            "listconstraints" => include_str!("../help/it/listconstraints.md"),
+            "help" => include_str!("../help/it/help.md"),
+            "toggleautoregister" => include_str!("../help/it/toggleautoregister.md"),
+            "isautoregister" => include_str!("../help/it/isautoregister.md"),
+            "language" => include_str!("../help/it/language.md"),
+            "settings" => include_str!("../help/it/settings.md"),
+            "timezone" => include_str!("../help/it/timezone.md"),
+            _ => "",
+        }
+    }
+
+    fn settings_title(&self) -> &'static str {
+        "Impostazioni di questa chat (tocca un'impostazione per cambiarla):"
+    }
+
+    fn setting_name(&self, key: SettingKey) -> &'static str {
+        match key {
+            SettingKey::PageSize => "Spese per pagina",
+            SettingKey::DecimalSeparator => "Separatore decimale",
+            SettingKey::AutoRegister => "Registrazione automatica",
+            SettingKey::Language => "Lingua",
+            SettingKey::Timezone => "Fuso orario",
+        }
+    }
+
+    fn setting_default(&self) -> &'static str {
+        "predefinito"
+    }
+
+    fn setting_on(&self) -> &'static str {
+        "sì"
+    }
+
+    fn setting_off(&self) -> &'static str {
+        "no"
+    }
+
+    fn input_error(&self, error: &InputError) -> String {
+        use InputError::*;
+        match error {
+            InvalidExpenseSyntax(error) => format!(
+                "sintassi della spesa non valida: alla colonna {} ci vuole {}, {}\n\n{}",
+                error.column,
+                expected_token(&error.expected),
+                if error.token.is_empty() {
+                    "ma la spesa è finita".to_string()
+                } else {
+                    format!("ma c'è `{}`", error.token)
+                },
+                error.marked_line()
+            ),
+            InvalidExpense(problem, _) => {
+                format!("spesa non valida: {}", expense_problem(problem))
+            }
+            InvalidParticipantName(name) => format!(
+                "nome di partecipante `{name}` non valido: i nomi dei partecipanti devono \
+                 iniziare con una lettera e contenere solo lettere e numeri"
+            ),
+            InvalidAliasName(name) => format!(
+                "alias `{name}` non valido: gli alias devono iniziare con una lettera \
+                 e contenere solo lettere e numeri"
+            ),
+            InvalidGroupName(name) => format!(
+                "nome di gruppo `{name}` non valido: i nomi dei gruppi devono iniziare con \
+                 una lettera e contenere solo lettere e numeri. Nelle spese vanno preceduti \
+                 da `#`, negli altri casi no"
+            ),
+            UnregisteredParticipant(name, suggestions) => format!(
+                "`{name}` non è un partecipante registrato{}",
+                did_you_mean(suggestions)
+            ),
+            AliasRegisteredAsParticipant(name) => {
+                format!("`{name}` è già usato come nome di un partecipante")
+            }
+            AliasRegisteredAsAlias(name, participant) => {
+                format!("`{name}` è già usato come alias del partecipante `{participant}`")
+            }
+            AliasNotRegisteredAsAlias(name, participant) => {
+                format!("`{name}` non è un alias del partecipante `{participant}`")
+            }
+            UnregisteredGroup(name, suggestions) => format!(
+                "`{name}` non è un gruppo registrato{}",
+                did_you_mean(suggestions)
+            ),
+            GroupAlreadyRegistered(name) => format!("`{name}` è già un gruppo registrato"),
+            ParticipantInOtherHousehold(participant, household) => {
+                format!("`{participant}` fa già parte del nucleo familiare `{household}`")
+            }
+            SubgroupInHousehold(household) => {
+                format!("il nucleo familiare `{household}` può contenere solo partecipanti")
+            }
+            GroupCycle(group, subgroup) => format!(
+                "`#{subgroup}` non può far parte di `#{group}`, perché `#{group}` conterrebbe sé stesso"
+            ),
+            ParticipantsNotProvided => "serve almeno un partecipante. Il formato deve essere \
+                                        'nome_partecipante [nome_partecipante...]'"
+                .to_string(),
+            ParticipantNotProvidedInAliasCommand => "manca il nome del partecipante. Il formato \
+                                                     deve essere 'partecipante [alias1...]'"
+                .to_string(),
+            GroupNotProvided => "manca il nome del gruppo. Il formato deve essere \
+                                 'nome_gruppo [nome_membro...]'"
+                .to_string(),
+            GroupWithCustomAmount => {
+                "non è possibile indicare importi personalizzati per i gruppi!".to_string()
+            }
+            InvalidExpenseId(id) => {
+                format!("valore `{id}` non valido per l'ID della spesa: serve un numero intero")
+            }
+            UnknownCommand(name) => {
+                format!("`{name}` non è un comando; usa /help per vedere la lista dei comandi")
+            }
+            UnknownLanguage(code) => format!("la lingua `{code}` non è supportata"),
+            UnknownTimezone(name) => {
+                format!("`{name}` non è un fuso orario conosciuto; usa un nome come Europe/Rome")
+            }
+            InvalidExportOption(option) => {
+                format!(
+                    "`{option}` non è un'opzione valida per /export; usa active o all, \
+                     csv, ledger o beancount, e una valuta come EUR"
+                )
+            }
+            ImportWithoutDocument => {
+                "rispondi a un documento CSV con /import per importarne le spese".to_string()
+            }
+            InvalidImportOption(option) => format!(
+                "`{option}` non è un'opzione valida per /import; usa csv o splitwise, \
+                 e dry-run per controllare il file senza salvarlo"
+            ),
+            InvalidCsv(problem) => format!("file CSV non valido: {}", csv_problem(problem)),
+            InvalidBalanceOption(option) => format!(
+                "`{option}` non è un'opzione valida per /balance; usa --at AAAA-MM-GG o --before ID"
+            ),
+            InvalidBetweenParticipants => {
+                "/between richiede due partecipanti diversi, seguiti facoltativamente da --list"
+                    .to_string()
+            }
+            InvalidRenameParticipant => "/renameparticipant richiede il nome attuale e il nuovo \
+                                         nome del partecipante, seguiti facoltativamente da \
+                                         --keep-alias"
+                .to_string(),
+            InvalidRenameGroup => {
+                "/renamegroup richiede il nome attuale e il nuovo nome del gruppo".to_string()
+            }
+            InvalidAddGroup => {
+                "/addgroup richiede il nome del gruppo, seguito facoltativamente da --household"
+                    .to_string()
+            }
+            InvalidMergeParticipants => "/mergeparticipants richiede il partecipante da tenere \
+                                         e il partecipante da unire a lui"
+                .to_string(),
+            InvalidSettlementConstraint(constraint) => format!(
+                "`{constraint}` non è un vincolo valido; usa only p1 p2, forbid p1 p2 o noroute p1"
+            ),
+            InvalidReportOption(option) => {
+                format!("`{option}` non è un'opzione valida per /report; usa active o all")
+            }
+            InvalidChartOption(option) => format!(
+                "`{option}` non è un'opzione valida per /chart; usa people, categories o time, e active o all"
+            ),
+            NothingToChart => "non ci sono spese da disegnare".to_string(),
+            RestoreWithoutDocument => {
+                "rispondi a un file di backup con /restore per ripristinarlo".to_string()
+            }
+            ChatNotEmpty => "questa chat ha già partecipanti, gruppi o spese: \
+                             un backup può essere ripristinato solo in una chat nuova"
+                .to_string(),
+            InvalidBackup(problem) => {
+                format!("file di backup non valido: {}", backup_problem(problem))
+            }
+        }
+    }
+
+    fn database_error(&self) -> &'static str {
+        "Impossibile interrogare il database, riprova più tardi."
+    }
+
+    fn telegram_error(&self) -> &'static str {
+        "Impossibile comunicare con i server di Telegram, riprova più tardi."
+    }
+}
+
+fn expense_problem(problem: &ExpenseProblem) -> &'static str {
+    use ExpenseProblem::*;
+    match problem {
+        NoParticipants => "non ci sono né debitori né creditori in questa spesa!",
+        NoCreditors => "non ci sono creditori in questa spesa!",
+        CreditAboveAmount => "i soldi pagati superano l'importo totale della spesa!",
+        FixedCreditBelowAmount => {
+            "tutti i creditori hanno pagato un importo fisso e il totale è inferiore \
+             all'importo della spesa!"
+        }
+        DebtAboveAmount => "i soldi dovuti superano l'importo totale della spesa!",
+        FixedDebtBelowAmount => {
+            "tutti i debitori devono un importo fisso e il totale è inferiore \
+             all'importo della spesa!"
+        }
+        DuplicateCreditorAmounts => {
+            "ci sono creditori che compaiono più volte con importi personalizzati!"
+        }
+        DuplicateDebtorAmounts => {
+            "ci sono debitori che compaiono più volte con importi personalizzati!"
+        }
+    }
+}
+
+fn expected_token(expected: &ExpectedToken) -> &'static str {
+    use ExpectedToken::*;
+    match expected {
+        Amount => "l'importo",
+        ParticipantAmount => "un importo dopo `/`",
+        ParticipantOrMessage => "un nome di partecipante o `- ` prima del messaggio",
+    }
+}
+
+fn csv_problem(problem: &CsvProblem) -> String {
+    use CsvProblem::*;
+    match problem {
+        Unreadable(reason) => format!("il file non può essere letto ({reason})"),
+        TooLarge(size) => format!("il file è più grande di {size} KB"),
+        MissingColumn(column) => format!("manca la colonna `{column}`"),
+        EmptyValue(column) => format!("la colonna `{column}` è vuota"),
+        InvalidDate(date) => format!("`{date}` non è una data valida, serve AAAA-MM-GG"),
+        InvalidAmount(amount) => format!("`{amount}` non è un importo valido"),
+        InvalidRole(role) => {
+            format!("`{role}` non è un ruolo valido, serve creditor o debtor")
+        }
+        InvalidState(state) => {
+            format!("`{state}` non è uno stato valido, serve active o settled")
+        }
+        InconsistentRows(id) => format!(
+            "le righe della spesa `{id}` non coincidono per data, importo, messaggio o stato"
+        ),
+        InvalidParticipantColumn(column) => {
+            format!("la colonna `{column}` non è un nome di partecipante valido")
+        }
+        DuplicateParticipantColumn(first, second) => {
+            format!("le colonne `{first}` e `{second}` sono lo stesso partecipante")
+        }
+        OtherCurrency(currency, expected) => {
+            format!("la valuta `{currency}` è diversa dalla valuta delle altre spese ({expected})")
+        }
+        UnbalancedExpense => "gli importi dei partecipanti non hanno somma zero, \
+                              oppure superano il costo"
+            .to_string(),
+    }
+}
+
+fn backup_problem(problem: &BackupProblem) -> String {
+    use BackupProblem::*;
+    match problem {
+        Unreadable(reason) => format!("il file non può essere letto ({reason})"),
+        TooLarge(size) => format!("il file è più grande di {size} KB"),
+        UnsupportedVersion(version) => format!("la versione {version} non è supportata"),
+        UnknownParticipant(id) => format!("non esiste un partecipante con ID {id}"),
+        UnknownGroup(id) => format!("non esiste un gruppo con ID {id}"),
+        GroupCycle(id) => format!("il gruppo con ID {id} contiene sé stesso"),
+    }
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [suggestion] => format!("; forse intendevi `{suggestion}`?"),
+        [first @ .., last] => {
+            let first = first
+                .iter()
+                .map(|s| format!("`{s}`"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("; forse intendevi {first} o `{last}`?")
+        }
+    }
+}
@@ -0,0 +1,261 @@
+//! Localization of the bot replies.
+//!
+//! Every chat has a [`Language`], which selects a [`Catalog`] of messages. Error
+//! messages keep their English `Display` implementation for logging, while the text
+//! sent to users always goes through the catalog.
+
+mod en;
+mod it;
+
+use chrono_tz::Tz;
+
+use crate::{
+    chart::ChartKind,
+    error::{DatabaseError, InputError, TelegramError},
+    help::HelpTopic,
+    report::ReportLabel,
+    settings::SettingKey,
+    types::SettlementConstraint,
+};
+
+/// A language supported by the bot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Italian,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::Italian];
+
+    /// The ISO 639-1 code of the language, used to save it and to select it with `/language`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Italian => "it",
+        }
+    }
+
+    /// The name of the language, in the language itself.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Italian => "Italiano",
+        }
+    }
+
+    /// Parse a language from its code or from its name (in English or in the language itself).
+    pub fn parse(s: &str) -> Option<Language> {
+        match s.trim().to_lowercase().as_str() {
+            "en" | "english" | "inglese" => Some(Language::English),
+            "it" | "italian" | "italiano" => Some(Language::Italian),
+            _ => None,
+        }
+    }
+
+    pub fn catalog(&self) -> &'static dyn Catalog {
+        match self {
+            Language::English => &en::English,
+            Language::Italian => &it::Italian,
+        }
+    }
+}
+
+/// All the text that the bot sends to users, in a given language.
+pub trait Catalog: Sync {
+    /// The separator between the integer and the fractional part of amounts.
+    fn decimal_separator(&self) -> char;
+
+    /// The `chrono` format string used for dates.
+    fn date_format(&self) -> &'static str;
+
+    fn nothing_to_show(&self) -> &'static str;
+    /// What an expense means for the balance of a participant, in `/why`.
+    fn why_step(&self, paid: &str, share: &str, net: &str) -> String;
+    fn why_is_owed(&self, participant: &str, amount: &str) -> String;
+    fn why_owes(&self, participant: &str, amount: &str) -> String;
+    fn why_is_even(&self, participant: &str) -> String;
+    /// The debt between two participants, in `/between`.
+    fn between_owes(&self, debtor: &str, creditor: &str, amount: &str) -> String;
+    fn between_even(&self, p1: &str, p2: &str) -> String;
+    /// The title of a past balance, at the end of the given day.
+    fn balance_at(&self, date: &str) -> String;
+    /// The title of a past balance, before the expense with the given ID.
+    fn balance_before(&self, expense_id: i64) -> String;
+    fn all_clean(&self) -> &'static str;
+    /// The header of a balance that the settlement constraints make impossible.
+    fn impossible_settlement(&self) -> &'static str;
+    fn describe_settlement_constraint(&self, constraint: &SettlementConstraint) -> String;
+    fn previous_page(&self) -> &'static str;
+    fn next_page(&self) -> &'static str;
+    /// The reply to an expense that was saved after fixing the names that were not registered.
+    fn expense_corrected(&self, expense: &str) -> String;
+    /// The question before saving an expense that was written as a sentence.
+    fn confirm_expense(&self, expense: &str) -> String;
+    fn save_expense(&self) -> &'static str;
+    fn cancel_expense(&self) -> &'static str;
+    fn expense_saved(&self, expense: &str) -> String;
+    fn expense_not_saved(&self) -> &'static str;
+    fn expense_already_confirmed(&self) -> &'static str;
+
+    fn auto_register_enabled(&self) -> &'static str;
+    fn auto_register_disabled(&self) -> &'static str;
+    fn auto_register_is_enabled(&self) -> &'static str;
+    fn auto_register_is_disabled(&self) -> &'static str;
+
+    fn current_language(&self, language: Language) -> String;
+    fn language_changed(&self) -> &'static str;
+
+    /// `None` means that the timezone of the server is used.
+    fn current_timezone(&self, timezone: Option<Tz>) -> String;
+    fn timezone_changed(&self, timezone: Option<Tz>) -> String;
+    fn server_timezone(&self) -> &'static str;
+
+    fn import_saved(&self, num_expenses: usize) -> String;
+    fn import_checked(&self, num_expenses: usize) -> String;
+    fn import_failed(&self) -> &'static str;
+    fn import_error(&self, line: usize, error: &str) -> String;
+
+    fn backup_restored(&self) -> &'static str;
+
+    fn report_label(&self, label: ReportLabel) -> &'static str;
+    /// The first line of the report: `first` and `last` are the dates of the first and
+    /// the last expense.
+    fn report_summary(&self, num_expenses: usize, first: &str, last: &str, total: &str) -> String;
+
+    fn chart_title(&self, kind: ChartKind) -> &'static str;
+
+    /// The first line of `/help`.
+    fn help_intro(&self) -> &'static str;
+    /// The last line of `/help`, which points to the help pages.
+    fn help_details(&self) -> &'static str;
+    fn help_topic(&self, topic: HelpTopic) -> &'static str;
+    /// A command and its shortcut, e.g. `/expense or /e`.
+    fn command_with_shortcut(&self, name: &str, shortcut: &str) -> String;
+    /// The short description of a command, shown in `/help` and in the command menu.
+    /// It is empty for unknown commands.
+    fn command_description(&self, command: &str) -> &'static str;
+    /// The detailed help page of a command (see [`crate::help::CommandHelp`]). It is empty
+    /// for unknown commands.
+    fn help_page(&self, command: &str) -> &'static str;
+
+    fn settings_title(&self) -> &'static str;
+    fn setting_name(&self, key: SettingKey) -> &'static str;
+    fn setting_default(&self) -> &'static str;
+    fn setting_on(&self) -> &'static str;
+    fn setting_off(&self) -> &'static str;
+
+    fn input_error(&self, error: &InputError) -> String;
+    fn database_error(&self) -> &'static str;
+    fn telegram_error(&self) -> &'static str;
+}
+
+/// The message to send to users when an error occurs.
+///
+/// Errors that are not known to the bot are shown as they are.
+pub fn format_error(error: &anyhow::Error, language: Language) -> String {
+    let catalog = language.catalog();
+    if let Some(e) = error.downcast_ref::<InputError>() {
+        catalog.input_error(e)
+    } else if error.downcast_ref::<DatabaseError>().is_some() {
+        catalog.database_error().to_string()
+    } else if error.downcast_ref::<TelegramError>().is_some() {
+        catalog.telegram_error().to_string()
+    } else {
+        format!("{error}")
+    }
+}
+
+/// The list of supported languages, as shown in the `/language` reply.
+fn format_available_languages() -> String {
+    Language::ALL
+        .iter()
+        .map(|l| format!("{} ({})", l.code(), l.name()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::ExpenseProblem;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_language() {
+        assert_eq!(Some(Language::Italian), Language::parse(" IT "));
+        assert_eq!(Some(Language::Italian), Language::parse("italiano"));
+        assert_eq!(Some(Language::English), Language::parse("en"));
+        assert_eq!(None, Language::parse("fr"));
+
+        for language in Language::ALL {
+            assert_eq!(Some(language), Language::parse(language.code()));
+        }
+    }
+
+    #[test]
+    fn test_format_error() {
+        let error: anyhow::Error =
+            InputError::unregistered_participant("aa".to_string(), vec![]).into();
+        assert_eq!(
+            "`aa` is not a registered participant",
+            format_error(&error, Language::English)
+        );
+        assert_eq!(
+            "`aa` non è un partecipante registrato",
+            format_error(&error, Language::Italian)
+        );
+
+        let error: anyhow::Error = InputError::unregistered_group(
+            "famiglie".to_string(),
+            vec!["famiglia".to_string(), "famigli".to_string()],
+        )
+        .into();
+        assert_eq!(
+            "`famiglie` is not a registered group; did you mean `famiglia` or `famigli`?",
+            format_error(&error, Language::English)
+        );
+        assert_eq!(
+            "`famiglie` non è un gruppo registrato; forse intendevi `famiglia` o `famigli`?",
+            format_error(&error, Language::Italian)
+        );
+
+        let error: anyhow::Error = crate::parser::parse_expense("c1 12d d1")
+            .expect_err("test")
+            .into();
+        assert_eq!(
+            "invalid syntax for an expense: expected the amount at column 4, found `12d`\n\n\
+             c1 12d d1\n   ^",
+            format_error(&error, Language::English)
+        );
+        assert_eq!(
+            "sintassi della spesa non valida: alla colonna 4 ci vuole l'importo, ma c'è `12d`\n\n\
+             c1 12d d1\n   ^",
+            format_error(&error, Language::Italian)
+        );
+
+        let error: anyhow::Error =
+            InputError::invalid_expense(ExpenseProblem::NoCreditors, "expense".to_string()).into();
+        assert_eq!(
+            "spesa non valida: non ci sono creditori in questa spesa!",
+            format_error(&error, Language::Italian)
+        );
+
+        let error: anyhow::Error = DatabaseError::concurrency("test").into();
+        assert_eq!(
+            Language::Italian.catalog().database_error(),
+            format_error(&error, Language::Italian)
+        );
+    }
+
+    #[test]
+    fn test_current_language() {
+        assert_eq!(
+            "The language of this chat is English. Available languages: en (English), it (Italiano).",
+            Language::English
+                .catalog()
+                .current_language(Language::English)
+        );
+    }
+}
@@ -0,0 +1,560 @@
+//! Read the files that are sent to `/import`.
+//!
+//! The default format is the CSV produced by `/export`: one row per participant of
+//! each expense, with rows of the same expense sharing the same `id`. Columns are
+//! found by their name, so their order does not matter and unknown columns are
+//! ignored. Ids are only used to group rows: imported expenses get new ones.
+//!
+//! Files exported from other applications are read by the submodules.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use csv::StringRecord;
+
+use crate::{
+    error::{CsvProblem, InputError},
+    export::FORMULA_PREFIXES,
+    parser::{parse_plain_amount, parse_plain_custom_amount},
+    settings::ChatSettings,
+    types::{ImportedExpense, ParsedExpense, ParsedParticipant},
+    validator::{validate_group_name, validate_participant_name},
+};
+
+mod splitwise;
+
+pub use splitwise::read_splitwise_expenses;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The format of an imported file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// The CSV produced by `/export`.
+    Csv,
+    /// The CSV export of a Splitwise group.
+    Splitwise,
+}
+
+impl ImportFormat {
+    pub fn parse(s: &str) -> Option<ImportFormat> {
+        match s {
+            "csv" => Some(ImportFormat::Csv),
+            "splitwise" => Some(ImportFormat::Splitwise),
+            _ => None,
+        }
+    }
+
+    /// Whether all the participants of the file are registered, even if auto register
+    /// is disabled. This is the case for files coming from other applications, since
+    /// participants cannot be expected to have the same names in the bot.
+    pub fn registers_participants(&self) -> bool {
+        match self {
+            ImportFormat::Csv => false,
+            ImportFormat::Splitwise => true,
+        }
+    }
+}
+
+/// What `/import` should do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub dry_run: bool,
+    pub format: ImportFormat,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            dry_run: false,
+            format: ImportFormat::Csv,
+        }
+    }
+}
+
+/// The result of reading one expense: since an expense spans several rows, it is
+/// identified by the line of its first row.
+pub type ReadExpense = (usize, Result<ImportedExpense, InputError>);
+
+/// The outcome of `/import`.
+#[derive(Debug)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub num_expenses: usize,
+    /// The errors, with the line where the expense that caused them starts.
+    pub errors: Vec<(usize, anyhow::Error)>,
+}
+
+/// Read the expenses in a file with the given format.
+pub fn read_expenses(
+    content: &str,
+    format: ImportFormat,
+    settings: &ChatSettings,
+) -> Result<Vec<ReadExpense>, InputError> {
+    match format {
+        ImportFormat::Csv => read_csv_expenses(content, settings),
+        ImportFormat::Splitwise => read_splitwise_expenses(content, settings),
+    }
+}
+
+/// Read the expenses in a CSV file.
+///
+/// An error is returned only if the file as a whole cannot be read: errors in single
+/// expenses are returned together with the expenses.
+pub fn read_csv_expenses(
+    content: &str,
+    settings: &ChatSettings,
+) -> Result<Vec<ReadExpense>, InputError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(content.as_bytes());
+    let columns = Columns::new(reader.headers().map_err(unreadable)?)?;
+
+    // Group rows by expense, keeping the order of the file.
+    let mut ids = vec![];
+    let mut rows_by_id: HashMap<String, Vec<(usize, StringRecord)>> = HashMap::new();
+    for record in reader.records() {
+        let record = record.map_err(unreadable)?;
+        let line = line_of(content, &record);
+        let id = record.get(columns.id).unwrap_or("").to_string();
+        if !rows_by_id.contains_key(&id) {
+            ids.push(id.clone());
+        }
+        rows_by_id.entry(id).or_default().push((line, record));
+    }
+
+    let expenses = ids
+        .into_iter()
+        .map(|id| {
+            let rows = rows_by_id
+                .remove(&id)
+                .expect("each id has at least one row");
+            let line = rows[0].0;
+            (line, read_expense(&id, &rows, &columns, settings))
+        })
+        .collect();
+
+    Ok(expenses)
+}
+
+/// The position of each column in the file.
+struct Columns {
+    id: usize,
+    date: usize,
+    amount: usize,
+    participant: usize,
+    role: usize,
+    custom_amount: Option<usize>,
+    message: Option<usize>,
+    state: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &StringRecord) -> Result<Self, InputError> {
+        let find = |name: &str| header.iter().position(|h| h.eq_ignore_ascii_case(name));
+        let require = |name: &str| {
+            find(name)
+                .ok_or_else(|| InputError::invalid_csv(CsvProblem::MissingColumn(name.to_string())))
+        };
+
+        Ok(Columns {
+            id: require("id")?,
+            date: require("date")?,
+            amount: require("amount")?,
+            participant: require("participant")?,
+            role: require("role")?,
+            custom_amount: find("custom_amount"),
+            message: find("message"),
+            state: find("state"),
+        })
+    }
+}
+
+fn read_expense(
+    id: &str,
+    rows: &[(usize, StringRecord)],
+    columns: &Columns,
+    settings: &ChatSettings,
+) -> Result<ImportedExpense, InputError> {
+    if id.is_empty() {
+        return Err(empty_value("id"));
+    }
+
+    // These values are the same in all rows of an expense.
+    let expense_values = |record: &StringRecord| {
+        [
+            Some(columns.date),
+            Some(columns.amount),
+            columns.message,
+            columns.state,
+        ]
+        .map(|c| c.and_then(|c| record.get(c)).unwrap_or("").to_string())
+    };
+    let first = &rows[0].1;
+    if rows
+        .iter()
+        .any(|(_, r)| expense_values(r) != expense_values(first))
+    {
+        return Err(InputError::invalid_csv(CsvProblem::InconsistentRows(
+            id.to_string(),
+        )));
+    }
+
+    let message_ts = read_date(required_value(first, columns.date, "date")?, settings)?;
+
+    let amount = read_amount(required_value(first, columns.amount, "amount")?)?;
+
+    let message = optional_value(first, columns.message).map(unescape_formula);
+
+    let is_active = match optional_value(first, columns.state) {
+        None | Some("active") => true,
+        Some("settled") => false,
+        Some(state) => {
+            return Err(InputError::invalid_csv(CsvProblem::InvalidState(
+                state.to_string(),
+            )))
+        }
+    };
+
+    let participants = rows
+        .iter()
+        .map(|(_, record)| read_participant(record, columns))
+        .collect::<Result<_, _>>()?;
+
+    Ok(ImportedExpense {
+        expense: ParsedExpense::new(participants, amount, message),
+        message_ts,
+        is_active,
+    })
+}
+
+/// Read a participant, with the same rules as in `/expense`: names are case-insensitive,
+/// a leading `@` is ignored and a leading `#` denotes a group.
+fn read_participant(
+    record: &StringRecord,
+    columns: &Columns,
+) -> Result<ParsedParticipant, InputError> {
+    let name = required_value(record, columns.participant, "participant")?.to_lowercase();
+    let is_group = name.starts_with('#');
+    let name = name.trim_start_matches(['@', '#']);
+    if is_group {
+        validate_group_name(name)?;
+    } else {
+        validate_participant_name(name)?;
+    }
+
+    let amount = optional_value(record, columns.custom_amount)
+        .map(read_custom_amount)
+        .transpose()?;
+
+    let role = required_value(record, columns.role, "role")?;
+    let participant = match (role.to_lowercase().as_str(), is_group) {
+        ("creditor", false) => ParsedParticipant::new_creditor(name, amount),
+        ("creditor", true) => ParsedParticipant::new_creditor_group(name, amount),
+        ("debtor", false) => ParsedParticipant::new_debtor(name, amount),
+        ("debtor", true) => ParsedParticipant::new_debtor_group(name, amount),
+        _ => {
+            return Err(InputError::invalid_csv(CsvProblem::InvalidRole(
+                role.to_string(),
+            )))
+        }
+    };
+    Ok(participant)
+}
+
+/// Read a date in the same format as `/export`, as the start of that day in the
+/// timezone of the chat.
+fn read_date(s: &str, settings: &ChatSettings) -> Result<DateTime<Utc>, InputError> {
+    NaiveDate::parse_from_str(s, DATE_FORMAT)
+        .map(|date| settings.start_of_day(date))
+        .map_err(|_| InputError::invalid_csv(CsvProblem::InvalidDate(s.to_string())))
+}
+
+fn read_amount(s: &str) -> Result<i64, InputError> {
+    parse_plain_amount(s)
+        .ok_or_else(|| InputError::invalid_csv(CsvProblem::InvalidAmount(s.to_string())))
+}
+
+fn read_custom_amount(s: &str) -> Result<i64, InputError> {
+    parse_plain_custom_amount(s)
+        .ok_or_else(|| InputError::invalid_csv(CsvProblem::InvalidAmount(s.to_string())))
+}
+
+/// Remove the `'` that `/export` puts before cells that a spreadsheet would read as
+/// a formula.
+fn unescape_formula(cell: &str) -> String {
+    match cell.strip_prefix('\'') {
+        Some(escaped) if escaped.starts_with(FORMULA_PREFIXES) || escaped.starts_with('\'') => {
+            escaped.to_string()
+        }
+        _ => cell.to_string(),
+    }
+}
+
+fn required_value<'a>(
+    record: &'a StringRecord,
+    column: usize,
+    name: &str,
+) -> Result<&'a str, InputError> {
+    optional_value(record, Some(column)).ok_or_else(|| empty_value(name))
+}
+
+fn optional_value(record: &StringRecord, column: Option<usize>) -> Option<&str> {
+    column.and_then(|c| record.get(c)).filter(|v| !v.is_empty())
+}
+
+fn empty_value(name: &str) -> InputError {
+    InputError::invalid_csv(CsvProblem::EmptyValue(name.to_string()))
+}
+
+/// The line where a record starts, counting from one.
+///
+/// The line number kept by the CSV reader does not count empty lines, so it is computed
+/// from the position of the record in the file. That position is before the empty
+/// lines that precede the record, if any.
+fn line_of(content: &str, record: &StringRecord) -> usize {
+    let offset = record.position().map(|p| p.byte() as usize).unwrap_or(0);
+    let bytes = content.as_bytes();
+    let mut line = bytes[..offset.min(bytes.len())]
+        .iter()
+        .filter(|b| **b == b'\n')
+        .count()
+        + 1;
+    for b in bytes.iter().skip(offset) {
+        match b {
+            b'\n' => line += 1,
+            b'\r' => {}
+            _ => break,
+        }
+    }
+    line
+}
+
+fn unreadable(e: csv::Error) -> InputError {
+    InputError::invalid_csv(CsvProblem::Unreadable(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+
+    use crate::export::export_csv;
+    use crate::types::{SavedExpense, SavedParticipant};
+
+    use super::*;
+
+    fn settings() -> ChatSettings {
+        ChatSettings {
+            timezone: Some(Tz::Europe__Rome),
+            ..Default::default()
+        }
+    }
+
+    fn expect_error(result: &ReadExpense) -> CsvProblem {
+        match &result.1 {
+            Err(InputError::InvalidCsv(problem)) => problem.clone(),
+            r => panic!("unexpected result: {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_read_exported_expenses() -> anyhow::Result<()> {
+        let message_ts: DateTime<Utc> =
+            DateTime::parse_from_rfc3339("2023-05-01T22:30:00Z")?.into();
+        let expenses = vec![
+            SavedExpense::new(
+                3,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("aa", None),
+                    SavedParticipant::new_debtor("bb", Some(123)),
+                    SavedParticipant::new_debtor("cc", None),
+                ],
+                4343,
+                Some("pizza, beer".to_string()),
+                message_ts,
+            ),
+            SavedExpense::new(
+                7,
+                false,
+                vec![
+                    SavedParticipant::new_creditor("bb", None),
+                    SavedParticipant::new_debtor("aa", None),
+                ],
+                1000,
+                None,
+                message_ts,
+            ),
+        ];
+        let content = export_csv(&expenses, &settings())?;
+
+        let result = read_csv_expenses(&content, &settings())?;
+        assert_eq!(2, result.len());
+
+        let (line, expense) = &result[0];
+        assert_eq!(2, *line);
+        let expense = expense.as_ref().expect("test");
+        assert!(expense.is_active);
+        assert_eq!(4343, expense.expense.amount);
+        assert_eq!(Some("pizza, beer".to_string()), expense.expense.message);
+        assert_eq!(3, expense.expense.participants.len());
+        let bb = &expense.expense.participants[1];
+        assert_eq!(
+            ("bb", Some(123), true),
+            (bb.name.as_str(), bb.amount, bb.is_debtor())
+        );
+        // The date is kept, the time of the day is not.
+        assert_eq!(
+            DateTime::parse_from_rfc3339("2023-05-01T22:00:00Z")?,
+            expense.message_ts
+        );
+
+        let (line, expense) = &result[1];
+        assert_eq!(5, *line);
+        assert!(!expense.as_ref().expect("test").is_active);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_exported_negative_custom_amount() -> anyhow::Result<()> {
+        let message_ts: DateTime<Utc> =
+            DateTime::parse_from_rfc3339("2023-05-01T22:30:00Z")?.into();
+        let expenses = vec![SavedExpense::new(
+            3,
+            true,
+            vec![
+                SavedParticipant::new_creditor("aa", None),
+                SavedParticipant::new_creditor("bb", Some(-2110)),
+                SavedParticipant::new_debtor("cc", None),
+            ],
+            1000,
+            None,
+            message_ts,
+        )];
+        let content = export_csv(&expenses, &settings())?;
+
+        let result = read_csv_expenses(&content, &settings())?;
+        let expense = &result[0].1.as_ref().expect("test").expense;
+        assert_eq!(1000, expense.amount);
+        assert_eq!(Some(-2110), expense.participants[1].amount);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_exported_formulas() -> anyhow::Result<()> {
+        let message_ts: DateTime<Utc> =
+            DateTime::parse_from_rfc3339("2023-05-01T22:30:00Z")?.into();
+        let messages = ["=1+2", "+pizza", "-beer", "@home", "'quoted'", "'=A1"];
+        let expenses: Vec<_> = messages
+            .iter()
+            .enumerate()
+            .map(|(id, message)| {
+                SavedExpense::new(
+                    id as i64,
+                    true,
+                    vec![
+                        SavedParticipant::new_creditor("aa", None),
+                        SavedParticipant::new_debtor("bb", None),
+                    ],
+                    1000,
+                    Some(message.to_string()),
+                    message_ts,
+                )
+            })
+            .collect();
+        let content = export_csv(&expenses, &settings())?;
+        assert!(content.contains(",'=1+2,"));
+        assert!(content.contains(",'@home,"));
+        assert!(content.contains(",''=A1,"));
+
+        let result = read_csv_expenses(&content, &settings())?;
+        let imported: Vec<_> = result
+            .iter()
+            .map(|(_, e)| e.as_ref().expect("test").expense.message.clone())
+            .collect();
+        let expected: Vec<_> = messages.iter().map(|m| Some(m.to_string())).collect();
+        assert_eq!(expected, imported);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_groups_and_shuffled_columns() -> anyhow::Result<()> {
+        let content = "role,participant,amount,date,id,notes\n\
+                       creditor,@AA,10,2023-05-01,x,hello\n\
+                       debtor,#All,10,2023-05-01,x,\n";
+        let result = read_csv_expenses(content, &settings())?;
+        assert_eq!(1, result.len());
+
+        let expense = &result[0].1.as_ref().expect("test").expense;
+        assert_eq!(1000, expense.amount);
+        assert_eq!(None, expense.message);
+        assert_eq!("aa", expense.participants[0].name);
+        assert!(!expense.participants[0].is_group());
+        assert_eq!("all", expense.participants[1].name);
+        assert!(expense.participants[1].is_group());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid_rows() -> anyhow::Result<()> {
+        let content = "id,date,amount,participant,role\n\
+                       1,2023-05-01,10,aa,creditor\n\
+                       1,2023-05-02,10,bb,debtor\n\
+                       2,01/05/2023,10,aa,creditor\n\
+                       3,2023-05-01,ten,aa,creditor\n\
+                       4,2023-05-01,10,aa,payer\n\
+                       5,2023-05-01,10,1aa,creditor\n\
+                       6,2023-05-01,10,,creditor\n\
+                       7,2023-05-01,10,aa,creditor\n\
+                       8,2023-05-01,-10,aa,creditor\n";
+        let result = read_csv_expenses(content, &settings())?;
+        assert_eq!(8, result.len());
+
+        assert_eq!(
+            CsvProblem::InconsistentRows("1".to_string()),
+            expect_error(&result[0])
+        );
+        assert_eq!(4, result[1].0);
+        assert_eq!(
+            CsvProblem::InvalidDate("01/05/2023".to_string()),
+            expect_error(&result[1])
+        );
+        assert_eq!(
+            CsvProblem::InvalidAmount("ten".to_string()),
+            expect_error(&result[2])
+        );
+        assert_eq!(
+            CsvProblem::InvalidRole("payer".to_string()),
+            expect_error(&result[3])
+        );
+        assert!(matches!(
+            result[4].1,
+            Err(InputError::InvalidParticipantName(_))
+        ));
+        assert_eq!(
+            CsvProblem::EmptyValue("participant".to_string()),
+            expect_error(&result[5])
+        );
+        assert!(result[6].1.is_ok());
+        // Only custom amounts can be negative.
+        assert_eq!(
+            CsvProblem::InvalidAmount("-10".to_string()),
+            expect_error(&result[7])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_missing_column() {
+        let result = read_csv_expenses("id,date,amount,role\n", &settings());
+        assert!(matches!(
+            result,
+            Err(InputError::InvalidCsv(CsvProblem::MissingColumn(c))) if c == "participant"
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+//! Read the CSV export of a Splitwise group.
+//!
+//! The file has one row per expense, with the columns `Date`, `Description`, `Category`,
+//! `Cost` and `Currency`, followed by one column per person. The value in a person
+//! column is how much the expense changes the balance of that person: positive if the
+//! person paid more than their share, negative otherwise. The last row of the file is
+//! the total balance of each person, which is not an expense.
+//!
+//! Each row becomes an expense where people with a positive value are creditors and
+//! people with a negative value are debtors, all with custom amounts. The amount of the
+//! expense is the money that changes hands, which is less than the cost if the people
+//! who paid also had a share of the expense. The message is the description followed
+//! by the category in parentheses, e.g. `Dinner (Dining out)`.
+
+use std::collections::HashMap;
+
+use csv::StringRecord;
+
+use crate::{
+    error::{CsvProblem, InputError},
+    settings::ChatSettings,
+    types::{Amount, ImportedExpense, ParsedExpense, ParsedParticipant},
+    validator::is_valid_name,
+};
+
+use super::{line_of, read_amount, read_date, required_value, unreadable, ReadExpense};
+
+/// The columns that come before the person columns.
+const EXPENSE_COLUMNS: [&str; 5] = ["date", "description", "category", "cost", "currency"];
+
+/// Read the expenses in a Splitwise CSV export.
+///
+/// The total balance is skipped, as well as rows that do not change any balance. All expenses must have the same currency.
+pub fn read_splitwise_expenses(
+    content: &str,
+    settings: &ChatSettings,
+) -> Result<Vec<ReadExpense>, InputError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let columns = Columns::new(reader.headers().map_err(unreadable)?)?;
+
+    let mut expenses = vec![];
+    let mut currency = None;
+    for record in reader.records() {
+        let record = record.map_err(unreadable)?;
+        let line = line_of(content, &record);
+        // The total balance has neither a date nor a cost.
+        let is_empty = |c| record.get(c).unwrap_or("").is_empty();
+        if is_empty(columns.date) && is_empty(columns.cost) {
+            continue;
+        }
+
+        let expense = read_expense(&record, &columns, &mut currency, settings);
+        match expense {
+            Ok(Some(expense)) => expenses.push((line, Ok(expense))),
+            Ok(None) => {}
+            Err(e) => expenses.push((line, Err(e))),
+        }
+    }
+
+    Ok(expenses)
+}
+
+/// The position of each column in the file, with the participant of each person column.
+struct Columns {
+    date: usize,
+    description: usize,
+    category: usize,
+    cost: usize,
+    currency: usize,
+    participants: Vec<(usize, String)>,
+}
+
+impl Columns {
+    fn new(header: &StringRecord) -> Result<Self, InputError> {
+        let find = |name: &str| {
+            header
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(name))
+                .ok_or_else(|| InputError::invalid_csv(CsvProblem::MissingColumn(name.to_string())))
+        };
+        let positions = EXPENSE_COLUMNS
+            .iter()
+            .map(|name| find(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Person columns are the ones after the expense columns.
+        let first_person = positions.iter().max().map(|p| p + 1).unwrap_or(0);
+        let mut participants = vec![];
+        let mut columns_by_name: HashMap<String, &str> = HashMap::new();
+        for (position, column) in header.iter().enumerate().skip(first_person) {
+            let name = participant_name(column)?;
+            if let Some(other) = columns_by_name.insert(name.clone(), column) {
+                return Err(InputError::invalid_csv(
+                    CsvProblem::DuplicateParticipantColumn(other.to_string(), column.to_string()),
+                ));
+            }
+            participants.push((position, name));
+        }
+
+        Ok(Columns {
+            date: positions[0],
+            description: positions[1],
+            category: positions[2],
+            cost: positions[3],
+            currency: positions[4],
+            participants,
+        })
+    }
+}
+
+/// Splitwise uses full names, which are turned into participant names by removing
+/// everything but letters and digits: `Mary Jane` becomes `maryjane`.
+fn participant_name(column: &str) -> Result<String, InputError> {
+    let name: String = column
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+    if name.is_empty() || !is_valid_name(&name) {
+        Err(InputError::invalid_csv(
+            CsvProblem::InvalidParticipantColumn(column.to_string()),
+        ))
+    } else {
+        Ok(name)
+    }
+}
+
+/// Read an expense, or `None` if it does not change any balance.
+fn read_expense(
+    record: &StringRecord,
+    columns: &Columns,
+    currency: &mut Option<String>,
+    settings: &ChatSettings,
+) -> Result<Option<ImportedExpense>, InputError> {
+    let message_ts = read_date(required_value(record, columns.date, "date")?, settings)?;
+    let cost = read_amount(required_value(record, columns.cost, "cost")?)?;
+
+    let record_currency = required_value(record, columns.currency, "currency")?;
+    match currency {
+        Some(c) if c != record_currency => {
+            return Err(InputError::invalid_csv(CsvProblem::OtherCurrency(
+                record_currency.to_string(),
+                c.clone(),
+            )))
+        }
+        Some(_) => {}
+        None => *currency = Some(record_currency.to_string()),
+    }
+
+    let mut participants = vec![];
+    let mut total_credit: Amount = 0;
+    let mut total_debt: Amount = 0;
+    for (position, name) in &columns.participants {
+        let value = record.get(*position).unwrap_or("");
+        if value.is_empty() {
+            continue;
+        }
+        let (is_debtor, amount) = match value.strip_prefix('-') {
+            Some(value) => (true, read_amount(value)?),
+            None => (false, read_amount(value)?),
+        };
+        if amount == 0 {
+            continue;
+        }
+        if is_debtor {
+            total_debt += amount;
+            participants.push(ParsedParticipant::new_debtor(name, Some(amount)));
+        } else {
+            total_credit += amount;
+            participants.push(ParsedParticipant::new_creditor(name, Some(amount)));
+        }
+    }
+
+    if total_credit != total_debt || total_credit > cost {
+        return Err(InputError::invalid_csv(CsvProblem::UnbalancedExpense));
+    }
+    if participants.is_empty() {
+        return Ok(None);
+    }
+
+    let description = record.get(columns.description).unwrap_or("").trim();
+    let category = record.get(columns.category).unwrap_or("").trim();
+    let message = match (description, category) {
+        ("", "") => None,
+        (description, "") => Some(description.to_string()),
+        ("", category) => Some(format!("({category})")),
+        // Payments between people have the same description and category.
+        (description, category) if description.eq_ignore_ascii_case(category) => {
+            Some(description.to_string())
+        }
+        (description, category) => Some(format!("{description} ({category})")),
+    };
+
+    Ok(Some(ImportedExpense {
+        expense: ParsedExpense::new(participants, total_credit, message),
+        message_ts,
+        is_active: true,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+
+    use super::*;
+
+    fn settings() -> ChatSettings {
+        ChatSettings {
+            timezone: Some(Tz::Europe__Rome),
+            ..Default::default()
+        }
+    }
+
+    fn expect_error(result: &ReadExpense) -> CsvProblem {
+        match &result.1 {
+            Err(InputError::InvalidCsv(problem)) => problem.clone(),
+            r => panic!("unexpected result: {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_read_splitwise_expenses() -> anyhow::Result<()> {
+        let content = "Date,Description,Category,Cost,Currency,Mary Jane,Bob,Carl\n\
+                       2023-05-01,Dinner,Dining out,60.00,EUR,40.00,-20.00,-20.00\n\
+                       \n\
+                       2023-05-02,Payment,Payment,20.00,EUR,-20.00,20.00,0.00\n\
+                       2023-05-03,Own lunch,General,10.00,EUR,0.00,0.00,0.00\n\
+                       \n\
+                       ,Total balance, , ,EUR,20.00,0.00,-20.00\n";
+        let result = read_splitwise_expenses(content, &settings())?;
+        assert_eq!(2, result.len());
+
+        let (line, expense) = &result[0];
+        assert_eq!(2, *line);
+        let expense = expense.as_ref().expect("test");
+        assert!(expense.is_active);
+        assert_eq!(
+            DateTime::parse_from_rfc3339("2023-04-30T22:00:00Z")?,
+            expense.message_ts
+        );
+        assert_eq!(4000, expense.expense.amount);
+        assert_eq!(
+            Some("Dinner (Dining out)".to_string()),
+            expense.expense.message
+        );
+        let participants: Vec<_> = expense
+            .expense
+            .participants
+            .iter()
+            .map(|p| (p.name.as_str(), p.is_creditor(), p.amount))
+            .collect();
+        assert_eq!(
+            vec![
+                ("maryjane", true, Some(4000)),
+                ("bob", false, Some(2000)),
+                ("carl", false, Some(2000))
+            ],
+            participants
+        );
+
+        let (line, expense) = &result[1];
+        assert_eq!(4, *line);
+        let expense = &expense.as_ref().expect("test").expense;
+        assert_eq!(2000, expense.amount);
+        assert_eq!(Some("Payment".to_string()), expense.message);
+        assert_eq!(2, expense.participants.len());
+        assert!(expense.participants[0].is_debtor());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_invalid_splitwise_expenses() -> anyhow::Result<()> {
+        let content = "Date,Description,Category,Cost,Currency,Mary,Bob\n\
+                       2023-05-01,Dinner,Dining out,60.00,EUR,30.00,-20.00\n\
+                       2023-05-01,Dinner,Dining out,10.00,EUR,30.00,-30.00\n\
+                       2023-05-01,Dinner,Dining out,60.00,USD,30.00,-30.00\n\
+                       2023-05-01,Dinner,Dining out,60.00,EUR,thirty,-30.00\n\
+                       2023-05-01,Dinner,Dining out,60.00,EUR,30.00,-30.00\n\
+                       ,Dinner,Dining out,60.00,EUR,30.00,-30.00\n";
+        let result = read_splitwise_expenses(content, &settings())?;
+        assert_eq!(6, result.len());
+
+        assert_eq!(CsvProblem::UnbalancedExpense, expect_error(&result[0]));
+        assert_eq!(CsvProblem::UnbalancedExpense, expect_error(&result[1]));
+        assert_eq!(
+            CsvProblem::OtherCurrency("USD".to_string(), "EUR".to_string()),
+            expect_error(&result[2])
+        );
+        assert_eq!(
+            CsvProblem::InvalidAmount("thirty".to_string()),
+            expect_error(&result[3])
+        );
+        assert!(result[4].1.is_ok());
+        assert_eq!(
+            CsvProblem::EmptyValue("date".to_string()),
+            expect_error(&result[5])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_splitwise_header() {
+        let result = read_splitwise_expenses(
+            "Date,Description,Category,Cost,Currency,Mary Jane,mary-jane\n",
+            &settings(),
+        );
+        assert!(matches!(
+            result,
+            Err(InputError::InvalidCsv(
+                CsvProblem::DuplicateParticipantColumn(_, _)
+            ))
+        ));
+
+        let result =
+            read_splitwise_expenses("Date,Description,Category,Cost,Currency,2nd\n", &settings());
+        assert!(matches!(
+            result,
+            Err(InputError::InvalidCsv(CsvProblem::InvalidParticipantColumn(c))) if c == "2nd"
+        ));
+
+        let result = read_splitwise_expenses("Date,Description,Cost,Mary\n", &settings());
+        assert!(matches!(
+            result,
+            Err(InputError::InvalidCsv(CsvProblem::MissingColumn(c))) if c == "category"
+        ));
+    }
+}
@@ -11,25 +11,32 @@ use log4rs::{
     config::{Appender, Config, Root},
     encode::pattern::PatternEncoder,
 };
-use teloxide::dispatching::dialogue::InMemStorage;
 use teloxide::prelude::*;
 use tokio::{
     sync::Mutex,
     time::{interval, Duration},
 };
 
+mod backup;
 mod bot_commands;
 mod bot_logic;
+mod chart;
 mod database;
 mod endpoints;
 mod error;
+mod export;
 mod formatter;
+mod help;
+mod i18n;
+mod import;
 mod parser;
+mod report;
+mod settings;
 mod types;
 mod validator;
 
-use crate::bot_commands::{dialogue_handler, State};
-use crate::database::sqlite::SqliteDatabase;
+use crate::bot_commands::{dialogue_handler, set_my_commands};
+use crate::database::sqlite::{SqliteDatabase, SqliteDialogueStorage};
 
 #[tokio::main]
 async fn main() {
@@ -43,13 +50,20 @@ async fn main() {
         .expect("Cannot initialize database");
 
     let database = Arc::new(Mutex::new(database));
+    let dialogue_storage = SqliteDialogueStorage::new(database.clone());
 
     info!("Starting command bot...");
 
     let bot = Bot::from_env();
 
+    info!("Registering command menu...");
+    if let Err(e) = set_my_commands(&bot).await {
+        // The bot works even without the menu, so this is not fatal.
+        error!("Cannot register command menu: {:#?}", e);
+    }
+
     Dispatcher::builder(bot, dialogue_handler())
-        .dependencies(dptree::deps![InMemStorage::<State>::new(), database])
+        .dependencies(dptree::deps![dialogue_storage, database])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
@@ -2,47 +2,89 @@
 //!
 //! Since expenses have a more or less complex syntax, we use nom.
 
-use std::{cmp::Ordering, iter::repeat, num::ParseIntError};
+use std::{cmp::Ordering, iter::repeat_n, num::ParseIntError};
 
 use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
     character::complete::{char, multispace0, multispace1},
-    combinator::{eof, map, map_res, opt, recognize, rest, verify},
-    error::{context, ErrorKind},
+    combinator::{cut, eof, map, map_res, opt, recognize, rest, verify},
+    error::{context, VerboseError, VerboseErrorKind},
     multi::many0,
     sequence::{delimited, preceded, terminated, tuple},
     IResult,
 };
 
 use crate::{
+    error::{ExpectedToken, ExpenseSyntaxError, InputError},
     types::{Amount, ParsedExpense, ParsedParticipant},
     validator::is_valid_name,
 };
 
+type ParseResult<'a, T> = IResult<&'a str, T, VerboseError<&'a str>>;
+
 /// Parse an expense submitted by the user.
 ///
 /// For the expense syntax, you can refer to the bot instructions
 /// (INSTRUCTIONS.md). Some basic checks are performed by the parser, while
 /// other checks are executed later.
-pub fn parse_expense(s: &str) -> IResult<&str, ParsedExpense> {
+pub fn parse_expense(s: &str) -> Result<ParsedExpense, InputError> {
+    match do_parse_expense(s) {
+        Ok((_, expense)) => Ok(expense),
+        Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+            Err(InputError::invalid_expense_syntax(make_syntax_error(s, &e)))
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("complete parsers never ask for more input"),
+    }
+}
+
+fn do_parse_expense(s: &str) -> ParseResult<'_, ParsedExpense> {
     let (s, creditors) = parse_participants(s, true)?;
     let (s, amount) = parse_amount(s)?;
     let (s, mut debtors) = parse_participants(s, false)?;
     let (s, message) = parse_message(s)?;
+    // Anything left is neither a participant nor a message.
+    let (s, _) = context("cannot parse participants", preceded(multispace0, eof))(s)?;
 
-    if s.trim().is_empty() {
-        let mut participants = creditors;
-        participants.append(&mut debtors);
-        let message = message.map(|m| m.to_string());
+    let mut participants = creditors;
+    participants.append(&mut debtors);
+    let message = message.map(|m| m.to_string());
 
-        Ok((s, ParsedExpense::new(participants, amount, message)))
-    } else {
-        Err(nom::Err::Error(nom::error::Error::new(s, ErrorKind::Eof)))
+    Ok((s, ParsedExpense::new(participants, amount, message)))
+}
+
+/// Describe the first error found by the parser: the innermost error gives the position
+/// of the offending token, the innermost context what was expected there.
+fn make_syntax_error(s: &str, error: &VerboseError<&str>) -> ExpenseSyntaxError {
+    let remaining = error.errors.first().map(|(input, _)| *input).unwrap_or(s);
+    let expected = error
+        .errors
+        .iter()
+        .find_map(|(_, kind)| match kind {
+            VerboseErrorKind::Context("cannot parse amount") => Some(ExpectedToken::Amount),
+            VerboseErrorKind::Context("cannot parse participant amount") => {
+                Some(ExpectedToken::ParticipantAmount)
+            }
+            VerboseErrorKind::Context(_) => Some(ExpectedToken::ParticipantOrMessage),
+            _ => None,
+        })
+        .unwrap_or(ExpectedToken::ParticipantOrMessage);
+
+    let offset = s.len() - remaining.len();
+    let line_start = s[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = s[line_start..].lines().next().unwrap_or_default();
+    let column = s[line_start..offset].chars().count() + 1;
+    let token = remaining.split_whitespace().next().unwrap_or_default();
+
+    ExpenseSyntaxError {
+        line: line.to_string(),
+        column,
+        expected,
+        token: token.to_string(),
     }
 }
 
-fn parse_participants(s: &str, are_creditors: bool) -> IResult<&str, Vec<ParsedParticipant>> {
+fn parse_participants(s: &str, are_creditors: bool) -> ParseResult<'_, Vec<ParsedParticipant>> {
     let do_parse_participant_name = |s| parse_participant_name(s, are_creditors);
 
     context(
@@ -71,7 +113,7 @@ fn parse_participants(s: &str, are_creditors: bool) -> IResult<&str, Vec<ParsedP
 /// Participant name must be alphanumeric and cannot start with a number.
 /// If there is a '@' prepended, it is stripped away.
 /// If instead '#' is prepended, the participant is considered to be a group ('#' is still stripped away).
-fn parse_participant_name(s: &str, is_creditor: bool) -> IResult<&str, ParsedParticipant> {
+fn parse_participant_name(s: &str, is_creditor: bool) -> ParseResult<'_, ParsedParticipant> {
     let do_parse = |s: &str| -> ParsedParticipant {
         let is_group = s.starts_with('#');
 
@@ -114,17 +156,19 @@ fn parse_participant_name(s: &str, is_creditor: bool) -> IResult<&str, ParsedPar
     )(s)
 }
 
-fn parse_participant_amount(s: &str) -> IResult<&str, Amount> {
+fn parse_participant_amount(s: &str) -> ParseResult<'_, Amount> {
     // The difference with parse_amount is that there is no need to expect a whitespace
     // at the end because the caller already expects (and consumes) it.
     context(
         "cannot parse participant amount",
         // Match until the next whitespace, then try to parse.
-        preceded(char('/'), map_res(is_not(" \t\r\n"), do_parse_amount)),
+        // After a '/' there must be an amount: stop here instead of trying the other branches,
+        // so that the error points to the amount.
+        preceded(char('/'), cut(map_res(is_not(" \t\r\n"), do_parse_amount))),
     )(s)
 }
 
-fn parse_amount(s: &str) -> IResult<&str, Amount> {
+fn parse_amount(s: &str) -> ParseResult<'_, Amount> {
     context(
         "cannot parse amount",
         // Match until the next whitespace, then try to parse.
@@ -136,6 +180,65 @@ fn parse_amount(s: &str) -> IResult<&str, Amount> {
     )(s)
 }
 
+/// Replace a participant or group name in an expense submitted by the user, keeping
+/// everything else as it is. Groups must have the '#' prepended, as in the expense.
+///
+/// Names are compared ignoring case, and the message of the expense is left untouched.
+pub fn replace_expense_name(s: &str, name: &str, replacement: &str) -> String {
+    let name = name.to_lowercase();
+    let mut result = String::with_capacity(s.len());
+
+    let mut pieces = s.split_inclusive(char::is_whitespace);
+    for piece in pieces.by_ref() {
+        let token = piece.trim_end();
+        if token == "-" {
+            // The rest is the message.
+            result.push_str(piece);
+            break;
+        }
+
+        let (token_name, amount) = match token.find('/') {
+            Some(i) => token.split_at(i),
+            None => (token, ""),
+        };
+        let (prefix, token_name) = match token_name.strip_prefix('@') {
+            Some(token_name) => ("@", token_name),
+            None => ("", token_name),
+        };
+
+        if token_name.to_lowercase() == name {
+            result.push_str(prefix);
+            result.push_str(replacement);
+            result.push_str(amount);
+            result.push_str(&piece[token.len()..]);
+        } else {
+            result.push_str(piece);
+        }
+    }
+    result.extend(pieces);
+
+    result
+}
+
+/// Parse an amount that is not part of an expense, e.g. a cell of an imported file.
+pub fn parse_plain_amount(s: &str) -> Option<Amount> {
+    let s = s.trim();
+    if s.starts_with(['+', '-']) {
+        return None;
+    }
+    do_parse_amount(s).ok()
+}
+
+/// Parse the custom amount of a participant that is not part of an expense. Unlike
+/// the total of an expense, it can be negative.
+pub fn parse_plain_custom_amount(s: &str) -> Option<Amount> {
+    let s = s.trim();
+    match s.strip_prefix('-') {
+        Some(s) => parse_plain_amount(s).map(|amount| -amount),
+        None => parse_plain_amount(s),
+    }
+}
+
 fn do_parse_amount(x: &str) -> Result<Amount, ParseIntError> {
     let components: Vec<_> = x.split(&[',', '.']).collect();
     if components.len() == 2 {
@@ -157,10 +260,10 @@ fn do_parse_amount(x: &str) -> Result<Amount, ParseIntError> {
 }
 
 fn make_string_of_char(c: char, length: usize) -> String {
-    repeat(c).take(length).collect::<String>()
+    repeat_n(c, length).collect::<String>()
 }
 
-fn parse_message(s: &str) -> IResult<&str, Option<&str>> {
+fn parse_message(s: &str) -> ParseResult<'_, Option<&str>> {
     context(
         "cannot parse message",
         opt(preceded(multispace0, preceded(tag("- "), rest))),
@@ -234,7 +337,7 @@ mod tests {
     #[test]
     fn test_parse() -> anyhow::Result<()> {
         // Parse an expense with too many spaces, UTF-8 characters, custom amounts, groups and a message.
-        let (rest, expense) = parse_expense(
+        let expense = parse_expense(
             " @creditor1 creditòr2/-21.1 34.3   Debtor1 debtor2/3  @debtor3/1 #ǵroup  - yoh",
         )?;
 
@@ -267,60 +370,78 @@ mod tests {
 
         assert_eq!(expense.amount, 3430);
         assert_eq!(expense.message, Some("yoh".to_string()));
-        assert_eq!(rest, "");
 
         // Parse an expense without a message.
-        let (rest, expense) =
+        let expense =
             parse_expense(" creditor1 creditor2/-21.1 34.3   debtor1 debtor2/3  debtor3/1")?;
         assert_eq!(expense.message, None);
-        assert_eq!(rest, "");
 
         // Parse an expense on multiple lines.
-        let (rest, expense) =
+        let expense =
             parse_expense("creditor1\ncreditor2/-21.1\n34.3\ndebtor1\ndebtor2/3\ndebtor3/1 - message\non\nmany\nlines")?;
         assert_eq!(expense.participants.len(), 5);
         assert_eq!(expense.amount, 3430);
         assert_eq!(expense.message.unwrap().split('\n').count(), 4);
-        assert_eq!(rest, "");
 
         Ok(())
     }
 
     #[test]
-    fn test_parse_fails() -> anyhow::Result<()> {
+    fn test_replace_expense_name() {
+        assert_eq!(
+            "@marco 12 luca/3\n#family - marcco pays",
+            replace_expense_name(
+                "@Marcco 12 luca/3\n#family - marcco pays",
+                "marcco",
+                "marco"
+            )
+        );
+        assert_eq!(
+            "marco  luca/-2 12 #family",
+            replace_expense_name("marco  luca/-2 12 #famly", "#famly", "#family")
+        );
+        assert_eq!(
+            "family 12 #famly",
+            replace_expense_name("famly 12 #famly", "famly", "family")
+        );
+    }
+
+    #[test]
+    fn test_parse_fails() {
+        let syntax_error = |s| match parse_expense(s) {
+            Err(InputError::InvalidExpenseSyntax(e)) => e,
+            result => panic!("unexpected result: {result:?}"),
+        };
+
         // All parsers finished but there are characters left.
-        let result = parse_expense("c1 34.3 d1 d2 123");
-        assert!(result.is_err());
-        let error = result.unwrap_err();
-        if let nom::Err::Error(e) = error {
-            assert_eq!(e.input, "123");
-            assert_eq!(e.code, ErrorKind::Eof);
-        } else {
-            unreachable!();
-        }
+        let error = syntax_error("c1 34.3 d1 d2 123");
+        assert_eq!(error.column, 15);
+        assert_eq!(error.expected, ExpectedToken::ParticipantOrMessage);
+        assert_eq!(error.token, "123");
+        assert_eq!(error.marked_line(), "c1 34.3 d1 d2 123\n              ^");
 
         // Parsing the amount fails.
-        let result = parse_expense("c1 12d d1");
-        assert!(result.is_err());
-        let error = result.unwrap_err();
-        if let nom::Err::Error(e) = error {
-            assert_eq!(e.input, "12d d1");
-            assert_eq!(e.code, ErrorKind::MapRes);
-        } else {
-            unreachable!();
-        }
+        let error = syntax_error("c1 12d d1");
+        assert_eq!(error.column, 4);
+        assert_eq!(error.expected, ExpectedToken::Amount);
+        assert_eq!(error.token, "12d");
 
-        // Parsing a participant amount fails.
-        let result = parse_expense("c1 12 d1/3.aa");
-        assert!(result.is_err());
-        let error = result.unwrap_err();
-        if let nom::Err::Error(e) = error {
-            assert_eq!(e.input, "d1/3.aa");
-            assert_eq!(e.code, ErrorKind::Eof);
-        } else {
-            unreachable!();
-        }
+        // The amount is missing.
+        let error = syntax_error("c1 c2");
+        assert_eq!(error.column, 6);
+        assert_eq!(error.expected, ExpectedToken::Amount);
+        assert_eq!(error.token, "");
 
-        Ok(())
+        // Parsing a participant amount fails.
+        let error = syntax_error("c1 12 d1/3.aa");
+        assert_eq!(error.column, 10);
+        assert_eq!(error.expected, ExpectedToken::ParticipantAmount);
+        assert_eq!(error.token, "3.aa");
+
+        // The error is on the second line.
+        let error = syntax_error("c1 12\nd1 -message");
+        assert_eq!(error.line, "d1 -message");
+        assert_eq!(error.column, 4);
+        assert_eq!(error.token, "-message");
     }
 }
@@ -1,10 +1,22 @@
 //! Parse the user input.
 
 mod expense;
+mod natural;
 
-pub use expense::parse_expense;
+pub use expense::{
+    parse_expense, parse_plain_amount, parse_plain_custom_amount, replace_expense_name,
+};
+pub use natural::parse_natural_expense;
 
-use crate::error::InputError;
+use chrono::NaiveDate;
+
+use crate::{
+    chart::{ChartKind, ChartOptions},
+    error::InputError,
+    export::{ExportFormat, ExportOptions},
+    import::{ImportFormat, ImportOptions},
+    types::{BalanceOptions, SettlementConstraint},
+};
 
 pub fn parse_participants(s: &str) -> Result<Vec<String>, InputError> {
     let parts: Vec<_> = s
@@ -72,6 +84,210 @@ pub fn parse_group_and_members(s: &str) -> Result<(String, Vec<String>), InputEr
     }
 }
 
+/// Parse the options of `/export`: they can be given in any order and default to
+/// active expenses in CSV format.
+///
+/// A currency is a code of three uppercase letters, such as `EUR`.
+pub fn parse_export_options(s: &str) -> Result<ExportOptions, InputError> {
+    let mut options = ExportOptions::default();
+    for part in s.split(' ').filter(|x| !x.is_empty()) {
+        if part.len() == 3 && part.chars().all(|c| c.is_ascii_uppercase()) {
+            options.currency = Some(part.to_string());
+            continue;
+        }
+
+        let part = part.to_lowercase();
+        match part.as_str() {
+            "active" => options.only_active = true,
+            "all" => options.only_active = false,
+            _ => {
+                options.format = ExportFormat::parse(&part)
+                    .ok_or_else(|| InputError::invalid_export_option(part.clone()))?
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Parse the options of `/import`: they can be given in any order and default to
+/// importing a CSV file in the format of `/export`.
+pub fn parse_import_options(s: &str) -> Result<ImportOptions, InputError> {
+    let mut options = ImportOptions::default();
+    for part in s.split(' ').filter(|x| !x.is_empty()) {
+        let part = part.to_lowercase();
+        match part.as_str() {
+            "dry-run" => options.dry_run = true,
+            _ => {
+                options.format = ImportFormat::parse(&part)
+                    .ok_or_else(|| InputError::invalid_import_option(part.clone()))?
+            }
+        }
+    }
+    Ok(options)
+}
+
+/// Parse the options of `/balance`: `--at YYYY-MM-DD` or `--before ID`.
+pub fn parse_balance_options(s: &str) -> Result<BalanceOptions, InputError> {
+    let invalid = || InputError::invalid_balance_option(s.trim().to_string());
+    let parts: Vec<_> = s.split_whitespace().collect();
+    match parts.as_slice() {
+        [] => Ok(BalanceOptions::Current),
+        [option, value] => match option.to_lowercase().as_str() {
+            "--at" => NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(BalanceOptions::AtDate)
+                .map_err(|_| invalid()),
+            "--before" => value
+                .parse()
+                .map(BalanceOptions::BeforeExpense)
+                .map_err(|_| invalid()),
+            _ => Err(invalid()),
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse the arguments of `/between`: two different participants and, optionally,
+/// `--list` to show the expenses that make up their debt.
+pub fn parse_between(s: &str) -> Result<(String, String, bool), InputError> {
+    let mut list = false;
+    let mut participants = vec![];
+    for part in s.split(' ').filter(|x| !x.is_empty()) {
+        let part = part.to_lowercase();
+        if part == "--list" {
+            list = true;
+        } else {
+            participants.push(part);
+        }
+    }
+    match <[String; 2]>::try_from(participants) {
+        Ok([p1, p2]) if p1 != p2 => Ok((p1, p2, list)),
+        _ => Err(InputError::invalid_between_participants()),
+    }
+}
+
+/// Parse the current and the new name of a participant, followed by `--keep-alias` if the
+/// current name should become an alias.
+pub fn parse_rename_participant(s: &str) -> Result<(String, String, bool), InputError> {
+    let mut keep_alias = false;
+    let mut names = vec![];
+    for part in s.split(' ').filter(|x| !x.is_empty()) {
+        let part = part.to_lowercase();
+        if part == "--keep-alias" {
+            keep_alias = true;
+        } else {
+            names.push(part);
+        }
+    }
+    match <[String; 2]>::try_from(names) {
+        Ok([old_name, new_name]) if old_name != new_name => Ok((old_name, new_name, keep_alias)),
+        _ => Err(InputError::invalid_rename_participant()),
+    }
+}
+
+/// Parse the current and the new name of a group.
+pub fn parse_rename_group(s: &str) -> Result<(String, String), InputError> {
+    parse_two_names(s).ok_or_else(InputError::invalid_rename_group)
+}
+
+/// Parse the participant to keep and the participant to drop when merging them.
+pub fn parse_merge_participants(s: &str) -> Result<(String, String), InputError> {
+    parse_two_names(s).ok_or_else(InputError::invalid_merge_participants)
+}
+
+/// Parse exactly two different names.
+fn parse_two_names(s: &str) -> Option<(String, String)> {
+    let names: Vec<_> = s
+        .split(' ')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect();
+    match <[String; 2]>::try_from(names) {
+        Ok([name1, name2]) if name1 != name2 => Some((name1, name2)),
+        _ => None,
+    }
+}
+
+/// Parse the name of a new group and the `--household` flag, which makes it a household.
+///
+/// Group names are not validated here.
+pub fn parse_add_group(s: &str) -> Result<(String, bool), InputError> {
+    let mut is_household = false;
+    let mut names = vec![];
+    for part in s.split(' ').filter(|x| !x.is_empty()) {
+        if part.to_lowercase() == "--household" {
+            is_household = true;
+        } else {
+            names.push(part);
+        }
+    }
+    match names.as_slice() {
+        [name] => Ok((name.to_string(), is_household)),
+        _ => Err(InputError::invalid_add_group()),
+    }
+}
+
+/// Parse a settlement constraint: `only p1 p2` (p1 only pays p2), `forbid p1 p2` (p1 and
+/// p2 never pay each other) or `noroute p1` (p1 never passes money on).
+///
+/// The participants of a forbidden pair are sorted, since their order does not matter.
+pub fn parse_settlement_constraint(s: &str) -> Result<SettlementConstraint, InputError> {
+    let parts: Vec<_> = s
+        .split(' ')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_lowercase())
+        .collect();
+    let constraint = match parts.as_slice() {
+        [kind, p1, p2] if kind == "only" && p1 != p2 => {
+            SettlementConstraint::OnlyPays(p1.clone(), p2.clone())
+        }
+        [kind, p1, p2] if kind == "forbid" && p1 != p2 => {
+            let (p1, p2) = if p1 < p2 { (p1, p2) } else { (p2, p1) };
+            SettlementConstraint::Forbidden(p1.clone(), p2.clone())
+        }
+        [kind, p] if kind == "noroute" => SettlementConstraint::NoRouting(p.clone()),
+        _ => {
+            return Err(InputError::invalid_settlement_constraint(
+                s.trim().to_string(),
+            ))
+        }
+    };
+    Ok(constraint)
+}
+
+/// Parse the options of `/report`: the result is true if only active expenses should be
+/// in the report, which is the default.
+pub fn parse_report_options(s: &str) -> Result<bool, InputError> {
+    match s.trim().to_lowercase().as_str() {
+        "" | "active" => Ok(true),
+        "all" => Ok(false),
+        option => Err(InputError::invalid_report_option(option.to_string())),
+    }
+}
+
+/// Parse the options of `/chart`, which can be given in any order.
+pub fn parse_chart_options(s: &str) -> Result<ChartOptions, InputError> {
+    let mut options = ChartOptions::default();
+    let mut kinds = vec![];
+    for part in s.split(' ').filter(|x| !x.is_empty()) {
+        let part = part.to_lowercase();
+        match part.as_str() {
+            "active" => options.only_active = true,
+            "all" => options.only_active = false,
+            _ => {
+                let kind = ChartKind::parse(&part)
+                    .ok_or_else(|| InputError::invalid_chart_option(part.clone()))?;
+                if !kinds.contains(&kind) {
+                    kinds.push(kind);
+                }
+            }
+        }
+    }
+    if !kinds.is_empty() {
+        options.kinds = kinds;
+    }
+    Ok(options)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,4 +325,153 @@ mod tests {
         assert_eq!(members, Vec::<String>::new());
         Ok(())
     }
+
+    #[test]
+    fn test_parse_export_options() -> anyhow::Result<()> {
+        assert_eq!(ExportOptions::default(), parse_export_options("  ")?);
+
+        let options = parse_export_options("CSV all")?;
+        assert!(!options.only_active);
+        assert_eq!(ExportFormat::Csv, options.format);
+
+        let options = parse_export_options("beancount USD")?;
+        assert_eq!(ExportFormat::Beancount, options.format);
+        assert_eq!(Some("USD".to_string()), options.currency);
+
+        assert!(parse_export_options("all xls").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_import_options() -> anyhow::Result<()> {
+        assert_eq!(ImportOptions::default(), parse_import_options(" ")?);
+        assert!(parse_import_options(" Dry-Run ")?.dry_run);
+
+        let options = parse_import_options("dry-run Splitwise")?;
+        assert!(options.dry_run);
+        assert_eq!(ImportFormat::Splitwise, options.format);
+
+        assert!(parse_import_options("now").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_report_options() -> anyhow::Result<()> {
+        assert!(parse_report_options(" ")?);
+        assert!(!parse_report_options("ALL")?);
+        assert!(parse_report_options("csv").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_chart_options() -> anyhow::Result<()> {
+        assert_eq!(ChartOptions::default(), parse_chart_options("")?);
+
+        let options = parse_chart_options("all Time people time")?;
+        assert_eq!(vec![ChartKind::Time, ChartKind::People], options.kinds);
+        assert!(!options.only_active);
+
+        assert!(parse_chart_options("pie").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_balance_options() -> anyhow::Result<()> {
+        assert_eq!(BalanceOptions::Current, parse_balance_options(" ")?);
+        assert_eq!(
+            BalanceOptions::AtDate(NaiveDate::from_ymd_opt(2026, 9, 30).expect("test")),
+            parse_balance_options("--at 2026-09-30")?
+        );
+        assert_eq!(
+            BalanceOptions::BeforeExpense(42),
+            parse_balance_options(" --BEFORE  42")?
+        );
+
+        assert!(parse_balance_options("--at 30/09/2026").is_err());
+        assert!(parse_balance_options("--before").is_err());
+        assert!(parse_balance_options("--before 4 2").is_err());
+        assert!(parse_balance_options("--after 42").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_between() -> anyhow::Result<()> {
+        assert_eq!(
+            ("p1".to_string(), "p2".to_string(), false),
+            parse_between(" P1  p2 ")?
+        );
+        assert_eq!(
+            ("p1".to_string(), "p2".to_string(), true),
+            parse_between("p1 --LIST p2")?
+        );
+
+        assert!(parse_between("p1").is_err());
+        assert!(parse_between("p1 p1").is_err());
+        assert!(parse_between("p1 p2 p3 --list").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rename() -> anyhow::Result<()> {
+        assert_eq!(
+            ("p1".to_string(), "p2".to_string(), false),
+            parse_rename_participant(" P1  p2 ")?
+        );
+        assert_eq!(
+            ("p1".to_string(), "p2".to_string(), true),
+            parse_rename_participant("p1 p2 --KEEP-ALIAS")?
+        );
+        assert!(parse_rename_participant("p1").is_err());
+        assert!(parse_rename_participant("p1 p1").is_err());
+        assert!(parse_rename_participant("p1 p2 p3").is_err());
+
+        assert_eq!(
+            ("g1".to_string(), "g2".to_string()),
+            parse_rename_group("G1 g2")?
+        );
+        assert!(parse_rename_group("g1 g2 --keep-alias").is_err());
+        assert!(parse_rename_group("g1 g1").is_err());
+
+        assert_eq!(
+            ("p1".to_string(), "p2".to_string()),
+            parse_merge_participants(" p1 P2 ")?
+        );
+        assert!(parse_merge_participants("p1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_add_group() -> anyhow::Result<()> {
+        assert_eq!(("g1".to_string(), false), parse_add_group(" g1 ")?);
+        assert_eq!(("g1".to_string(), true), parse_add_group("g1 --HOUSEHOLD")?);
+        assert_eq!(("g1".to_string(), true), parse_add_group("--household g1")?);
+        assert!(parse_add_group("g1 --household g2").is_err());
+        assert!(parse_add_group("--household").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_settlement_constraint() -> anyhow::Result<()> {
+        use SettlementConstraint::*;
+
+        assert_eq!(
+            OnlyPays("p3".to_string(), "p1".to_string()),
+            parse_settlement_constraint(" ONLY p3  P1")?
+        );
+        assert_eq!(
+            Forbidden("p1".to_string(), "p2".to_string()),
+            parse_settlement_constraint("forbid p2 p1")?
+        );
+        assert_eq!(
+            NoRouting("p5".to_string()),
+            parse_settlement_constraint("noroute p5")?
+        );
+
+        assert!(parse_settlement_constraint("").is_err());
+        assert!(parse_settlement_constraint("only p1").is_err());
+        assert!(parse_settlement_constraint("only p1 p1").is_err());
+        assert!(parse_settlement_constraint("forbid p1 p2 p3").is_err());
+        assert!(parse_settlement_constraint("never p1 p2").is_err());
+        Ok(())
+    }
 }
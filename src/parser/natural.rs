@@ -0,0 +1,277 @@
+//! Parse an expense written as a sentence, in English or in Italian.
+//!
+//! Only a few kinds of sentence are understood, e.g. "marco paid 30 for pizza
+//! with anna and luca" or "anna owes luca 5". Anything else is left to the
+//! parser of the usual syntax.
+
+use crate::{
+    types::{Amount, ParsedExpense, ParsedParticipant},
+    validator::is_valid_name,
+};
+
+use super::parse_plain_amount;
+
+/// The words that connect the parts of a sentence.
+struct Words {
+    /// Before the creditor of a debt.
+    to: &'static str,
+    /// Before the message.
+    for_message: &'static str,
+    /// Before the people that share the expense.
+    with: &'static str,
+    /// Between the people that share the expense.
+    and: &'static str,
+}
+
+const ENGLISH: Words = Words {
+    to: "to",
+    for_message: "for",
+    with: "with",
+    and: "and",
+};
+
+const ITALIAN: Words = Words {
+    to: "a",
+    for_message: "per",
+    with: "con",
+    and: "e",
+};
+
+/// Currencies that can be written before or after an amount.
+const CURRENCIES: [&str; 4] = ["€", "$", "euro", "eur"];
+
+/// Parse an expense written as a sentence. Return `None` if the sentence is not understood.
+///
+/// Sentences can be:
+/// - `payer paid amount [for message] with p1 [and p2...]`, or in Italian
+///   `payer ha pagato amount [per message] con p1 [e p2...]`; the expense is
+///   shared by the payer and the others
+/// - `debtor owes creditor amount [for message]` (or `debtor owes amount to creditor`),
+///   or in Italian `debtor deve amount a creditor [per message]`; the debtor owes
+///   the whole amount
+pub fn parse_natural_expense(s: &str) -> Option<ParsedExpense> {
+    let words: Vec<_> = s
+        .split_whitespace()
+        .map(|w| w.trim_end_matches(','))
+        .filter(|w| !w.is_empty())
+        .collect();
+    let lowercase: Vec<_> = words.iter().map(|w| w.to_lowercase()).collect();
+    let lowercase: Vec<_> = lowercase.iter().map(String::as_str).collect();
+
+    let first = *words.first()?;
+    match lowercase.as_slice() {
+        [_, "paid" | "pays", ..] => parse_paid(first, &words[2..], &lowercase[2..], &ENGLISH),
+        [_, "ha", "pagato", ..] => parse_paid(first, &words[3..], &lowercase[3..], &ITALIAN),
+        [_, "paga", ..] => parse_paid(first, &words[2..], &lowercase[2..], &ITALIAN),
+        [_, "owes", ..] => parse_owes(first, &words[2..], &lowercase[2..], &ENGLISH),
+        [_, "deve", ..] => parse_owes(first, &words[2..], &lowercase[2..], &ITALIAN),
+        _ => None,
+    }
+}
+
+fn parse_paid(
+    payer: &str,
+    words: &[&str],
+    lowercase: &[&str],
+    language: &Words,
+) -> Option<ParsedExpense> {
+    let payer = parse_name(payer).filter(|(_, is_group)| !is_group)?.0;
+    let (amount, mut i) = parse_amount(lowercase)?;
+
+    let mut message = None;
+    let mut others = vec![];
+    while i < words.len() {
+        let start = i + 1;
+        let end = lowercase[start..]
+            .iter()
+            .position(|w| *w == language.for_message || *w == language.with)
+            .map_or(words.len(), |p| start + p);
+
+        if lowercase[i] == language.for_message && message.is_none() && end > start {
+            message = Some(words[start..end].join(" "));
+        } else if lowercase[i] == language.with && others.is_empty() {
+            // Names are separated by commas (already removed) or by the word for "and".
+            for word in words[start..end]
+                .iter()
+                .filter(|w| !w.eq_ignore_ascii_case(language.and))
+            {
+                others.push(parse_name(word)?);
+            }
+        } else {
+            return None;
+        }
+        i = end;
+    }
+    if others.is_empty() {
+        return None;
+    }
+
+    let mut participants = vec![ParsedParticipant::new_creditor(&payer, None)];
+    participants.extend(others.into_iter().map(|(name, is_group)| {
+        if is_group {
+            ParsedParticipant::new_debtor_group(&name, None)
+        } else {
+            ParsedParticipant::new_debtor(&name, None)
+        }
+    }));
+    Some(ParsedExpense::new(participants, amount, message))
+}
+
+fn parse_owes(
+    debtor: &str,
+    words: &[&str],
+    lowercase: &[&str],
+    language: &Words,
+) -> Option<ParsedExpense> {
+    // Either "creditor amount" or "amount to creditor", or "to creditor amount" in Italian.
+    let (creditor, amount, i) = match lowercase {
+        [to, creditor, ..] if *to == language.to => {
+            let (amount, i) = parse_amount(&lowercase[2..])?;
+            (*creditor, amount, i + 2)
+        }
+        [creditor, ..] if parse_amount(lowercase).is_none() => {
+            let (amount, i) = parse_amount(&lowercase[1..])?;
+            (*creditor, amount, i + 1)
+        }
+        _ => {
+            let (amount, i) = parse_amount(lowercase)?;
+            match &lowercase[i..] {
+                [to, creditor, ..] if *to == language.to => (*creditor, amount, i + 2),
+                _ => return None,
+            }
+        }
+    };
+
+    let message = match &lowercase[i..] {
+        [] => None,
+        [for_message, ..] if *for_message == language.for_message && words.len() > i + 1 => {
+            Some(words[i + 1..].join(" "))
+        }
+        _ => return None,
+    };
+
+    let debtor = parse_name(debtor).filter(|(_, is_group)| !is_group)?.0;
+    let creditor = parse_name(creditor).filter(|(_, is_group)| !is_group)?.0;
+    if debtor == creditor {
+        return None;
+    }
+
+    let participants = vec![
+        ParsedParticipant::new_creditor(&creditor, None),
+        ParsedParticipant::new_debtor(&debtor, Some(amount)),
+    ];
+    Some(ParsedExpense::new(participants, amount, message))
+}
+
+/// A participant or, if '#' is prepended, a group. As in the usual syntax,
+/// a '@' prepended to a participant is stripped away.
+fn parse_name(word: &str) -> Option<(String, bool)> {
+    let (name, is_group) = match word.strip_prefix('#') {
+        Some(name) => (name, true),
+        None => (word.strip_prefix('@').unwrap_or(word), false),
+    };
+    (!name.is_empty() && is_valid_name(name)).then(|| (name.to_lowercase(), is_group))
+}
+
+/// An amount, with an optional currency before or after it. Return the amount
+/// and the number of words that were used.
+fn parse_amount(words: &[&str]) -> Option<(Amount, usize)> {
+    let (words, skipped) = match words {
+        [currency, rest @ ..] if CURRENCIES.contains(currency) => (rest, 1),
+        _ => (words, 0),
+    };
+    let word = words.first()?;
+    let amount = parse_plain_amount(
+        word.trim_start_matches(['€', '$'])
+            .trim_end_matches(['€', '$']),
+    )?;
+
+    match words.get(1) {
+        Some(currency) if CURRENCIES.contains(currency) => Some((amount, skipped + 2)),
+        _ => Some((amount, skipped + 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe(expense: &ParsedExpense) -> Vec<(String, bool, Option<Amount>)> {
+        expense
+            .participants
+            .iter()
+            .map(|p| (p.name.clone(), p.is_creditor(), p.amount))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_paid() {
+        let expense =
+            parse_natural_expense("Marco paid 30 for pizza with anna and luca").expect("test");
+        assert_eq!(expense.amount, 3000);
+        assert_eq!(expense.message, Some("pizza".to_string()));
+        assert_eq!(
+            describe(&expense),
+            vec![
+                ("marco".to_string(), true, None),
+                ("anna".to_string(), false, None),
+                ("luca".to_string(), false, None),
+            ]
+        );
+
+        let expense =
+            parse_natural_expense("marco ha pagato 12,5€ con anna, #amici per la cena di ieri")
+                .expect("test");
+        assert_eq!(expense.amount, 1250);
+        assert_eq!(expense.message, Some("la cena di ieri".to_string()));
+        assert_eq!(expense.participants.len(), 3);
+        assert!(expense.participants[2].is_group());
+
+        let expense = parse_natural_expense("marco pays € 10 with anna").expect("test");
+        assert_eq!(expense.amount, 1000);
+        assert_eq!(expense.message, None);
+
+        // Nobody to share the expense with.
+        assert!(parse_natural_expense("marco paid 30 for pizza").is_none());
+        // Not an amount.
+        assert!(parse_natural_expense("marco paid thirty with anna").is_none());
+        // Not a name.
+        assert!(parse_natural_expense("marco paid 30 with anna and 3luca").is_none());
+    }
+
+    #[test]
+    fn test_parse_owes() {
+        let expense = parse_natural_expense("anna owes luca 5").expect("test");
+        assert_eq!(expense.amount, 500);
+        assert_eq!(expense.message, None);
+        assert_eq!(
+            describe(&expense),
+            vec![
+                ("luca".to_string(), true, None),
+                ("anna".to_string(), false, Some(500)),
+            ]
+        );
+
+        let expense = parse_natural_expense("anna owes 5 to luca for the taxi").expect("test");
+        assert_eq!(expense.message, Some("the taxi".to_string()));
+        assert_eq!(expense.participants[0].name, "luca");
+
+        let expense = parse_natural_expense("anna deve 5 euro a luca per il taxi").expect("test");
+        assert_eq!(expense.amount, 500);
+        assert_eq!(expense.message, Some("il taxi".to_string()));
+        assert_eq!(expense.participants[0].name, "luca");
+
+        let expense = parse_natural_expense("anna deve a luca 5").expect("test");
+        assert_eq!(expense.participants[0].name, "luca");
+
+        assert!(parse_natural_expense("anna owes luca").is_none());
+        assert!(parse_natural_expense("anna owes anna 5").is_none());
+        assert!(parse_natural_expense("anna owes luca 5 yesterday").is_none());
+    }
+
+    #[test]
+    fn test_parse_usual_syntax() {
+        assert!(parse_natural_expense("marco 30 anna luca - pizza").is_none());
+        assert!(parse_natural_expense("marco luca/10 30 #amici").is_none());
+    }
+}
@@ -0,0 +1,573 @@
+//! Produce the HTML report that is sent by `/report`.
+//!
+//! The report is a single self-contained file, with its style inline, so that it can be
+//! shared and opened in any browser. Like bot messages, and unlike exported files, it
+//! follows the language and the settings of the chat.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    bot_logic::{compute_constrained_exchanges, compute_expense_shares, ImpossibleSettlement},
+    export::ExportedFile,
+    formatter::{format_amount, format_participant},
+    settings::ChatSettings,
+    types::{Amount, Household, MoneyExchange, SavedExpense, SettlementConstraint},
+};
+
+const REPORT_FILE_NAME: &str = "report.html";
+
+const STYLE: &str = "body { font-family: sans-serif; color: #222; max-width: 56em; margin: 2em auto; padding: 0 1em; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 2em; }
+th, td { border-bottom: 1px solid #ddd; padding: 0.4em 0.6em; text-align: left; }
+th { background: #f4f4f4; }
+.amount { text-align: right; white-space: nowrap; font-variant-numeric: tabular-nums; }
+.negative { color: #b00020; }
+.positive { color: #1b5e20; }";
+
+/// A fixed piece of text of the report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportLabel {
+    Title,
+    Settlement,
+    People,
+    Categories,
+    Expenses,
+    Debtor,
+    Creditor,
+    Amount,
+    Person,
+    Paid,
+    Spent,
+    Balance,
+    Category,
+    NumExpenses,
+    Date,
+    Description,
+    PaidBy,
+    SplitAmong,
+    NoDescription,
+}
+
+/// What each person paid and spent, before rounding.
+#[derive(Debug, Default)]
+pub struct PersonTotals {
+    pub paid: f64,
+    pub spent: f64,
+}
+
+/// Expenses with the same message, compared ignoring case.
+#[derive(Debug)]
+pub struct CategoryTotals {
+    pub name: String,
+    pub num_expenses: usize,
+    pub amount: Amount,
+}
+
+/// What each person paid and spent in the given expenses, by name.
+pub fn person_totals(expenses: &[&SavedExpense]) -> BTreeMap<String, PersonTotals> {
+    let mut people: BTreeMap<String, PersonTotals> = BTreeMap::new();
+    for expense in expenses {
+        let shares = compute_expense_shares(expense);
+        for (name, paid) in shares.paid {
+            people.entry(name).or_default().paid += paid;
+        }
+        for (name, spent) in shares.owed {
+            people.entry(name).or_default().spent += spent;
+        }
+    }
+    people
+}
+
+/// Group the given expenses by message, from the biggest total. Expenses without a
+/// message are grouped under `no_description`.
+pub fn category_totals(expenses: &[&SavedExpense], no_description: &str) -> Vec<CategoryTotals> {
+    let mut categories: Vec<CategoryTotals> = vec![];
+    let mut positions: HashMap<Option<String>, usize> = HashMap::new();
+    for expense in expenses {
+        let message = expense
+            .message
+            .as_deref()
+            .map(str::trim)
+            .filter(|m| !m.is_empty());
+        let key = message.map(str::to_lowercase);
+        let position = *positions.entry(key).or_insert_with(|| {
+            categories.push(CategoryTotals {
+                name: message.unwrap_or(no_description).to_string(),
+                num_expenses: 0,
+                amount: 0,
+            });
+            categories.len() - 1
+        });
+        categories[position].num_expenses += 1;
+        categories[position].amount += expense.amount;
+    }
+    categories.sort_by(|x, y| y.amount.cmp(&x.amount).then_with(|| x.name.cmp(&y.name)));
+    categories
+}
+
+/// Make the report of the given expenses. Settled expenses, if any, are listed but
+/// they are left out of the settlement, since their debts were already paid. The
+/// settlement respects the constraints of the chat, as the one of `/balance`.
+pub fn make_report(
+    expenses: &[SavedExpense],
+    constraints: &[SettlementConstraint],
+    households: &[Household],
+    settings: &ChatSettings,
+) -> ExportedFile {
+    let catalog = settings.language.catalog();
+    let active = expenses.iter().filter(|e| e.is_active).cloned().collect();
+    let settlement = compute_constrained_exchanges(active, constraints, households);
+    let label = |l| escape_html(catalog.report_label(l));
+
+    let mut expenses: Vec<_> = expenses.iter().collect();
+    expenses.sort_by_key(|e| (e.message_ts, e.id));
+
+    let summary = match (expenses.first(), expenses.last()) {
+        (Some(first), Some(last)) => catalog.report_summary(
+            expenses.len(),
+            &settings
+                .local_date(first.message_ts)
+                .format(catalog.date_format())
+                .to_string(),
+            &settings
+                .local_date(last.message_ts)
+                .format(catalog.date_format())
+                .to_string(),
+            &format_amount(expenses.iter().map(|e| e.amount).sum(), settings),
+        ),
+        _ => catalog.nothing_to_show().to_string(),
+    };
+
+    format_file(
+        settings,
+        &[
+            format!("<h1>{}</h1>", label(ReportLabel::Title)),
+            format!("<p>{}</p>", escape_html(&summary)),
+            format!("<h2>{}</h2>", label(ReportLabel::Settlement)),
+            format_settlement(&settlement, settings),
+            format!("<h2>{}</h2>", label(ReportLabel::People)),
+            format_people(&expenses, settings),
+            format!("<h2>{}</h2>", label(ReportLabel::Categories)),
+            format_categories(&expenses, settings),
+            format!("<h2>{}</h2>", label(ReportLabel::Expenses)),
+            format_expenses(&expenses, settings),
+        ],
+    )
+}
+
+fn format_file(settings: &ChatSettings, body: &[String]) -> ExportedFile {
+    let catalog = settings.language.catalog();
+    let content = format!(
+        "<!DOCTYPE html>
+<html lang=\"{}\">
+<head>
+<meta charset=\"utf-8\">
+<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">
+<title>{}</title>
+<style>
+{STYLE}
+</style>
+</head>
+<body>
+{}
+</body>
+</html>
+",
+        settings.language.code(),
+        escape_html(catalog.report_label(ReportLabel::Title)),
+        body.join("\n")
+    );
+
+    ExportedFile {
+        file_name: REPORT_FILE_NAME.to_string(),
+        content,
+    }
+}
+
+fn format_settlement(
+    settlement: &Result<Vec<MoneyExchange>, ImpossibleSettlement>,
+    settings: &ChatSettings,
+) -> String {
+    let catalog = settings.language.catalog();
+    let exchanges = match settlement {
+        Ok(exchanges) => exchanges,
+        Err(impossible) => return format_impossible_settlement(impossible, settings),
+    };
+    if exchanges.is_empty() {
+        return format!("<p>{}</p>", escape_html(catalog.all_clean()));
+    }
+
+    let rows = exchanges
+        .iter()
+        .map(|e| {
+            vec![
+                Cell::Text(e.debtor.clone()),
+                Cell::Text(e.creditor.clone()),
+                Cell::Amount(e.amount),
+            ]
+        })
+        .collect::<Vec<_>>();
+    format_table(
+        &[
+            Cell::Label(ReportLabel::Debtor),
+            Cell::Label(ReportLabel::Creditor),
+            Cell::AmountLabel(ReportLabel::Amount),
+        ],
+        &rows,
+        settings,
+    )
+}
+
+/// The warning of `/balance`, followed by what each participant would still owe or be owed.
+fn format_impossible_settlement(
+    impossible: &ImpossibleSettlement,
+    settings: &ChatSettings,
+) -> String {
+    let catalog = settings.language.catalog();
+    let rows = impossible
+        .unsettled
+        .iter()
+        .map(|p| vec![Cell::Text(p.participant.clone()), Cell::Balance(p.amount)])
+        .collect::<Vec<_>>();
+    format!(
+        "<p>⚠️ {}</p>\n{}",
+        escape_html(catalog.impossible_settlement()),
+        format_table(
+            &[
+                Cell::Label(ReportLabel::Person),
+                Cell::AmountLabel(ReportLabel::Balance),
+            ],
+            &rows,
+            settings,
+        )
+    )
+}
+
+fn format_people(expenses: &[&SavedExpense], settings: &ChatSettings) -> String {
+    let rows = person_totals(expenses)
+        .iter()
+        .map(|(name, totals)| {
+            vec![
+                Cell::Text(name.clone()),
+                Cell::Amount(totals.paid.round() as Amount),
+                Cell::Amount(totals.spent.round() as Amount),
+                Cell::Balance((totals.paid - totals.spent).round() as Amount),
+            ]
+        })
+        .collect::<Vec<_>>();
+    format_table(
+        &[
+            Cell::Label(ReportLabel::Person),
+            Cell::AmountLabel(ReportLabel::Paid),
+            Cell::AmountLabel(ReportLabel::Spent),
+            Cell::AmountLabel(ReportLabel::Balance),
+        ],
+        &rows,
+        settings,
+    )
+}
+
+fn format_categories(expenses: &[&SavedExpense], settings: &ChatSettings) -> String {
+    let catalog = settings.language.catalog();
+    let categories = category_totals(expenses, catalog.report_label(ReportLabel::NoDescription));
+
+    let rows = categories
+        .iter()
+        .map(|c| {
+            vec![
+                Cell::Text(c.name.clone()),
+                Cell::Number(c.num_expenses),
+                Cell::Amount(c.amount),
+            ]
+        })
+        .collect::<Vec<_>>();
+    format_table(
+        &[
+            Cell::Label(ReportLabel::Category),
+            Cell::AmountLabel(ReportLabel::NumExpenses),
+            Cell::AmountLabel(ReportLabel::Amount),
+        ],
+        &rows,
+        settings,
+    )
+}
+
+fn format_expenses(expenses: &[&SavedExpense], settings: &ChatSettings) -> String {
+    let catalog = settings.language.catalog();
+    let participants = |expense: &SavedExpense, are_creditors: bool| {
+        expense
+            .participants
+            .iter()
+            .filter(|p| p.is_creditor() == are_creditors)
+            .map(|p| format_participant(p, settings))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let rows = expenses
+        .iter()
+        .map(|e| {
+            let date = settings
+                .local_date(e.message_ts)
+                .format(catalog.date_format())
+                .to_string();
+            vec![
+                Cell::Text(date),
+                Cell::Text(e.message.clone().unwrap_or_default()),
+                Cell::Text(participants(e, true)),
+                Cell::Text(participants(e, false)),
+                Cell::Amount(e.amount),
+            ]
+        })
+        .collect::<Vec<_>>();
+    format_table(
+        &[
+            Cell::Label(ReportLabel::Date),
+            Cell::Label(ReportLabel::Description),
+            Cell::Label(ReportLabel::PaidBy),
+            Cell::Label(ReportLabel::SplitAmong),
+            Cell::AmountLabel(ReportLabel::Amount),
+        ],
+        &rows,
+        settings,
+    )
+}
+
+/// The content of a table cell.
+enum Cell {
+    Label(ReportLabel),
+    /// A label above a column of numbers.
+    AmountLabel(ReportLabel),
+    Text(String),
+    Number(usize),
+    Amount(Amount),
+    /// An amount that is colored according to its sign.
+    Balance(Amount),
+}
+
+/// A row, where cells are still to be formatted with the settings of the chat.
+type Row = Vec<Cell>;
+
+fn format_table(header: &[Cell], rows: &[Row], settings: &ChatSettings) -> String {
+    let format_cells = |cells: &[Cell], tag: &str| {
+        cells
+            .iter()
+            .map(|c| format_cell(c, tag, settings))
+            .collect::<String>()
+    };
+
+    let rows = rows
+        .iter()
+        .map(|r| format!("<tr>{}</tr>\n", format_cells(r, "td")))
+        .collect::<String>();
+    format!(
+        "<table>\n<thead>\n<tr>{}</tr>\n</thead>\n<tbody>\n{}</tbody>\n</table>",
+        format_cells(header, "th"),
+        rows
+    )
+}
+
+fn format_cell(cell: &Cell, tag: &str, settings: &ChatSettings) -> String {
+    let catalog = settings.language.catalog();
+    let (class, content) = match cell {
+        Cell::Label(l) => ("", escape_html(catalog.report_label(*l))),
+        Cell::AmountLabel(l) => ("amount", escape_html(catalog.report_label(*l))),
+        Cell::Text(t) => ("", escape_html(t)),
+        Cell::Number(n) => ("amount", n.to_string()),
+        Cell::Amount(a) => ("amount", escape_html(&format_amount(*a, settings))),
+        Cell::Balance(a) => {
+            let class = match a {
+                a if *a < 0 => "amount negative",
+                a if *a > 0 => "amount positive",
+                _ => "amount",
+            };
+            (class, escape_html(&format_amount(*a, settings)))
+        }
+    };
+
+    if class.is_empty() {
+        format!("<{tag}>{content}</{tag}>")
+    } else {
+        format!("<{tag} class=\"{class}\">{content}</{tag}>")
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Tz;
+
+    use crate::{i18n::Language, types::SavedParticipant};
+
+    use super::*;
+
+    /// Compare the output with a file in `src/snapshots`. Run the tests with the
+    /// environment variable `UPDATE_SNAPSHOTS` set to write the file instead.
+    fn assert_snapshot(name: &str, actual: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("src/snapshots")
+            .join(name);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&path, actual).expect("cannot write snapshot");
+        }
+        let expected = fs::read_to_string(&path).expect("cannot read snapshot");
+        assert!(
+            expected == actual,
+            "snapshot {name} does not match, the output is:\n{actual}"
+        );
+    }
+
+    fn make_expenses() -> Vec<SavedExpense> {
+        let day = |d: &str| -> DateTime<Utc> {
+            DateTime::parse_from_rfc3339(&format!("2023-05-0{d}T12:00:00Z"))
+                .expect("test")
+                .into()
+        };
+        vec![
+            SavedExpense::new(
+                2,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("bb", None),
+                    SavedParticipant::new_debtor("aa", None),
+                    SavedParticipant::new_debtor("cc", None),
+                ],
+                3000,
+                Some("Dinner".to_string()),
+                day("2"),
+            ),
+            SavedExpense::new(
+                1,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("aa", None),
+                    SavedParticipant::new_debtor("bb", Some(500)),
+                    SavedParticipant::new_debtor("cc", None),
+                ],
+                1550,
+                Some("Train <Rome & back>".to_string()),
+                day("1"),
+            ),
+            SavedExpense::new(
+                3,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("cc", None),
+                    SavedParticipant::new_debtor("aa", None),
+                ],
+                1000,
+                Some("dinner".to_string()),
+                day("3"),
+            ),
+            SavedExpense::new(
+                4,
+                true,
+                vec![
+                    SavedParticipant::new_creditor("aa", None),
+                    SavedParticipant::new_debtor("bb", None),
+                ],
+                250,
+                None,
+                day("3"),
+            ),
+        ]
+    }
+
+    fn settings(language: Language) -> ChatSettings {
+        ChatSettings {
+            language,
+            timezone: Some(Tz::Europe__Rome),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_report_snapshot() {
+        let expenses = make_expenses();
+
+        let report = make_report(&expenses, &[], &[], &settings(Language::English));
+        assert_eq!("report.html", report.file_name);
+        assert_snapshot("report_en.html", &report.content);
+
+        let report = make_report(&expenses, &[], &[], &settings(Language::Italian));
+        assert_snapshot("report_it.html", &report.content);
+    }
+
+    #[test]
+    fn test_empty_report_snapshot() {
+        let report = make_report(&[], &[], &[], &settings(Language::English));
+        assert_snapshot("report_empty.html", &report.content);
+    }
+
+    #[test]
+    fn test_report_settlement_skips_settled_expenses() {
+        let settings = settings(Language::English);
+        let settlement = |expenses: &[SavedExpense]| {
+            let content = make_report(expenses, &[], &[], &settings).content;
+            let start = content.find("<h2>Settlement</h2>").expect("test");
+            let end = content.find("<h2>People</h2>").expect("test");
+            content[start..end].to_string()
+        };
+
+        let mut expenses = make_expenses();
+        for expense in expenses.iter_mut().filter(|e| e.id != 4) {
+            expense.is_active = false;
+        }
+        // Only the active expense is settled: bb owes half of 2.50 to aa.
+        let settled = settlement(&expenses);
+        assert!(settled.contains("<tr><td>bb</td><td>aa</td><td class=\"amount\">1.25</td></tr>"));
+        assert!(!settled.contains("cc"));
+
+        // The settled expenses are still in the rest of the report.
+        let report = make_report(&expenses, &[], &[], &settings).content;
+        assert!(report.contains("Dinner"));
+
+        for expense in expenses.iter_mut() {
+            expense.is_active = false;
+        }
+        assert!(settlement(&expenses).contains(settings.language.catalog().all_clean()));
+    }
+
+    #[test]
+    fn test_report_settlement_respects_constraints() {
+        let settings = settings(Language::English);
+        let mut expenses = make_expenses();
+        for expense in expenses.iter_mut().filter(|e| e.id != 4) {
+            expense.is_active = false;
+        }
+
+        // bb owes aa, but they never send money to each other.
+        let constraints = [SettlementConstraint::Forbidden(
+            "aa".to_string(),
+            "bb".to_string(),
+        )];
+        let report = make_report(&expenses, &constraints, &[], &settings).content;
+        let catalog = settings.language.catalog();
+        assert!(report.contains(&escape_html(catalog.impossible_settlement())));
+        assert!(report.contains("<tr><td>aa</td><td class=\"amount positive\">1.25</td></tr>"));
+        assert!(!report.contains("<td>bb</td><td>aa</td>"));
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            "a &lt;b&gt; &amp; &quot;c&quot; &#39;d&#39;",
+            escape_html("a <b> & \"c\" 'd'")
+        );
+    }
+}
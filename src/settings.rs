@@ -0,0 +1,387 @@
+//! Per-chat settings.
+//!
+//! Settings are saved in the database as key-value pairs. A setting that was never
+//! changed is not saved at all and has its default value, so new settings can be
+//! added without touching existing chats.
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::i18n::Language;
+
+/// The possible values of the page size of expense lists.
+pub const PAGE_SIZES: [usize; 5] = [5, 10, 15, 25, 50];
+const DEFAULT_PAGE_SIZE: usize = 15;
+
+/// The possible values of the decimal separator, besides the default of the language.
+pub const DECIMAL_SEPARATORS: [char; 2] = ['.', ','];
+
+/// The key of a setting, as saved in the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingKey {
+    PageSize,
+    DecimalSeparator,
+    AutoRegister,
+    Language,
+    Timezone,
+}
+
+impl SettingKey {
+    pub const ALL: [SettingKey; 5] = [
+        SettingKey::PageSize,
+        SettingKey::DecimalSeparator,
+        SettingKey::AutoRegister,
+        SettingKey::Language,
+        SettingKey::Timezone,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SettingKey::PageSize => "page_size",
+            SettingKey::DecimalSeparator => "decimal_separator",
+            SettingKey::AutoRegister => "auto_register",
+            SettingKey::Language => "language",
+            SettingKey::Timezone => "timezone",
+        }
+    }
+
+    /// Whether the setting can be changed by tapping on it in the `/settings` keyboard.
+    ///
+    /// Settings with too many values to go through them one by one have their own command.
+    pub fn has_button(&self) -> bool {
+        *self != SettingKey::Timezone
+    }
+
+    pub fn parse(s: &str) -> Option<SettingKey> {
+        SettingKey::ALL.into_iter().find(|k| k.as_str() == s)
+    }
+}
+
+/// A setting together with its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Setting {
+    PageSize(usize),
+    /// `None` means that the separator of the language of the chat is used.
+    DecimalSeparator(Option<char>),
+    AutoRegister(bool),
+    Language(Language),
+    /// `None` means that the timezone of the server is used.
+    Timezone(Option<Tz>),
+}
+
+impl Setting {
+    pub fn key(&self) -> SettingKey {
+        match self {
+            Setting::PageSize(_) => SettingKey::PageSize,
+            Setting::DecimalSeparator(_) => SettingKey::DecimalSeparator,
+            Setting::AutoRegister(_) => SettingKey::AutoRegister,
+            Setting::Language(_) => SettingKey::Language,
+            Setting::Timezone(_) => SettingKey::Timezone,
+        }
+    }
+
+    /// The value as saved in the database. If it is `None`, the setting has its default
+    /// value and does not need to be saved.
+    pub fn value(&self) -> Option<String> {
+        match self {
+            Setting::PageSize(size) => Some(size.to_string()),
+            Setting::DecimalSeparator(separator) => separator.map(|s| s.to_string()),
+            Setting::AutoRegister(active) => Some(active.to_string()),
+            Setting::Language(language) => Some(language.code().to_string()),
+            Setting::Timezone(timezone) => timezone.map(|t| t.name().to_string()),
+        }
+    }
+
+    /// Parse a value saved in the database.
+    ///
+    /// Invalid values (e.g. written by a different version of the bot) are ignored.
+    pub fn parse(key: SettingKey, value: &str) -> Option<Setting> {
+        match key {
+            SettingKey::PageSize => value
+                .parse()
+                .ok()
+                .filter(|s| PAGE_SIZES.contains(s))
+                .map(Setting::PageSize),
+            SettingKey::DecimalSeparator => value
+                .chars()
+                .next()
+                .filter(|c| value.len() == 1 && DECIMAL_SEPARATORS.contains(c))
+                .map(|c| Setting::DecimalSeparator(Some(c))),
+            SettingKey::AutoRegister => value.parse().ok().map(Setting::AutoRegister),
+            SettingKey::Language => Language::parse(value).map(Setting::Language),
+            SettingKey::Timezone => parse_timezone(value).map(|t| Setting::Timezone(Some(t))),
+        }
+    }
+}
+
+/// All the settings of a chat.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChatSettings {
+    pub page_size: usize,
+    pub decimal_separator: Option<char>,
+    pub auto_register: bool,
+    pub language: Language,
+    pub timezone: Option<Tz>,
+}
+
+impl Default for ChatSettings {
+    fn default() -> Self {
+        ChatSettings {
+            page_size: DEFAULT_PAGE_SIZE,
+            decimal_separator: None,
+            auto_register: false,
+            language: Language::default(),
+            timezone: None,
+        }
+    }
+}
+
+impl ChatSettings {
+    pub fn get(&self, key: SettingKey) -> Setting {
+        match key {
+            SettingKey::PageSize => Setting::PageSize(self.page_size),
+            SettingKey::DecimalSeparator => Setting::DecimalSeparator(self.decimal_separator),
+            SettingKey::AutoRegister => Setting::AutoRegister(self.auto_register),
+            SettingKey::Language => Setting::Language(self.language),
+            SettingKey::Timezone => Setting::Timezone(self.timezone),
+        }
+    }
+
+    pub fn set(&mut self, setting: Setting) {
+        match setting {
+            Setting::PageSize(size) => self.page_size = size,
+            Setting::DecimalSeparator(separator) => self.decimal_separator = separator,
+            Setting::AutoRegister(active) => self.auto_register = active,
+            Setting::Language(language) => self.language = language,
+            Setting::Timezone(timezone) => self.timezone = timezone,
+        }
+    }
+
+    /// The setting with the given key, changed to the value that follows the current one.
+    ///
+    /// This is what happens when a user taps on a setting in the `/settings` keyboard:
+    /// after the last value, the first one comes again.
+    pub fn next(&self, key: SettingKey) -> Setting {
+        match key {
+            SettingKey::PageSize => Setting::PageSize(next_value(&PAGE_SIZES, &self.page_size)),
+            SettingKey::DecimalSeparator => {
+                let values = [None, Some('.'), Some(',')];
+                Setting::DecimalSeparator(next_value(&values, &self.decimal_separator))
+            }
+            SettingKey::AutoRegister => Setting::AutoRegister(!self.auto_register),
+            SettingKey::Language => Setting::Language(next_value(&Language::ALL, &self.language)),
+            // The timezone is changed with `/timezone`.
+            SettingKey::Timezone => Setting::Timezone(self.timezone),
+        }
+    }
+
+    /// The separator between the integer and the fractional part of amounts.
+    pub fn decimal_separator(&self) -> char {
+        self.decimal_separator
+            .unwrap_or_else(|| self.language.catalog().decimal_separator())
+    }
+
+    /// The day of the given instant, in the timezone of the chat.
+    pub fn local_date(&self, ts: DateTime<Utc>) -> NaiveDate {
+        match self.timezone {
+            Some(timezone) => ts.with_timezone(&timezone).date_naive(),
+            None => ts.with_timezone(&Local).date_naive(),
+        }
+    }
+
+    /// The instant when the given day starts, in the timezone of the chat.
+    pub fn start_of_day(&self, date: NaiveDate) -> DateTime<Utc> {
+        let midnight = date.and_time(NaiveTime::MIN);
+        match self.timezone {
+            Some(timezone) => local_to_utc(&timezone, midnight),
+            None => local_to_utc(&Local, midnight),
+        }
+    }
+}
+
+fn local_to_utc<T: TimeZone>(timezone: &T, datetime: NaiveDateTime) -> DateTime<Utc> {
+    // Some timezones skip midnight when daylight saving time starts: the day then starts
+    // one hour later.
+    timezone
+        .from_local_datetime(&datetime)
+        .earliest()
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(datetime + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|d| d.with_timezone(&Utc))
+        .unwrap_or_else(|| datetime.and_utc())
+}
+
+/// Parse an IANA timezone name, such as `Europe/Rome`, ignoring case.
+pub fn parse_timezone(s: &str) -> Option<Tz> {
+    Tz::from_str_insensitive(s.trim()).ok()
+}
+
+fn next_value<T: Copy + PartialEq>(values: &[T], current: &T) -> T {
+    let position = values.iter().position(|v| v == current);
+    match position {
+        Some(p) => values[(p + 1) % values.len()],
+        // A value that is not among the choices goes back to the first choice.
+        None => values[0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setting_round_trip() {
+        let settings = [
+            Setting::PageSize(25),
+            Setting::DecimalSeparator(Some(',')),
+            Setting::AutoRegister(true),
+            Setting::Language(Language::Italian),
+        ];
+        for setting in settings {
+            let key = SettingKey::parse(setting.key().as_str()).expect("test");
+            let value = setting.value().expect("test");
+            assert_eq!(Some(setting), Setting::parse(key, &value));
+        }
+
+        assert_eq!(None, Setting::DecimalSeparator(None).value());
+        assert_eq!(None, Setting::parse(SettingKey::PageSize, "7"));
+        assert_eq!(None, Setting::parse(SettingKey::DecimalSeparator, ".,"));
+        assert_eq!(None, SettingKey::parse("unknown"));
+    }
+
+    #[test]
+    fn test_next_setting() {
+        let mut settings = ChatSettings::default();
+
+        assert_eq!(Setting::PageSize(25), settings.next(SettingKey::PageSize));
+        settings.set(Setting::PageSize(50));
+        assert_eq!(Setting::PageSize(5), settings.next(SettingKey::PageSize));
+
+        let mut separators = vec![];
+        for _ in 0..3 {
+            let next = settings.next(SettingKey::DecimalSeparator);
+            settings.set(next);
+            separators.push(settings.decimal_separator);
+        }
+        assert_eq!(vec![Some('.'), Some(','), None], separators);
+
+        assert_eq!(
+            Setting::AutoRegister(true),
+            settings.next(SettingKey::AutoRegister)
+        );
+        assert_eq!(
+            Setting::Language(Language::Italian),
+            settings.next(SettingKey::Language)
+        );
+    }
+
+    #[test]
+    fn test_decimal_separator() {
+        let mut settings = ChatSettings {
+            language: Language::Italian,
+            ..Default::default()
+        };
+        assert_eq!(',', settings.decimal_separator());
+
+        settings.set(Setting::DecimalSeparator(Some('.')));
+        assert_eq!('.', settings.decimal_separator());
+    }
+
+    #[test]
+    fn test_timezone_round_trip() {
+        let setting = Setting::Timezone(Some(Tz::Asia__Tokyo));
+        let value = setting.value().expect("test");
+        assert_eq!("Asia/Tokyo", value);
+        assert_eq!(Some(setting), Setting::parse(SettingKey::Timezone, &value));
+
+        assert_eq!(Some(Tz::Europe__Rome), parse_timezone(" europe/rome "));
+        assert_eq!(None, parse_timezone("Europe/Milan"));
+        assert_eq!(None, Setting::Timezone(None).value());
+    }
+
+    #[test]
+    fn test_local_date_around_midnight() {
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").expect("test");
+        let ts = |s: &str| {
+            DateTime::parse_from_rfc3339(s)
+                .expect("test")
+                .with_timezone(&Utc)
+        };
+        let settings = |timezone| ChatSettings {
+            timezone: Some(timezone),
+            ..Default::default()
+        };
+
+        // Late evening in UTC is already the next day in Tokyo (UTC+9).
+        let tokyo = settings(Tz::Asia__Tokyo);
+        assert_eq!(
+            date("2023-05-01"),
+            tokyo.local_date(ts("2023-05-01T14:59:59Z"))
+        );
+        assert_eq!(
+            date("2023-05-02"),
+            tokyo.local_date(ts("2023-05-01T15:00:00Z"))
+        );
+
+        // Rome is UTC+2 in summer and UTC+1 in winter.
+        let rome = settings(Tz::Europe__Rome);
+        assert_eq!(
+            date("2023-05-01"),
+            rome.local_date(ts("2023-05-01T21:59:59Z"))
+        );
+        assert_eq!(
+            date("2023-05-02"),
+            rome.local_date(ts("2023-05-01T22:00:00Z"))
+        );
+        assert_eq!(
+            date("2023-01-01"),
+            rome.local_date(ts("2022-12-31T23:00:00Z"))
+        );
+        assert_eq!(
+            date("2022-12-31"),
+            rome.local_date(ts("2022-12-31T22:59:59Z"))
+        );
+
+        // Early morning in UTC is still the previous day in New York.
+        let new_york = settings(Tz::America__New_York);
+        assert_eq!(
+            date("2023-04-30"),
+            new_york.local_date(ts("2023-05-01T03:59:59Z"))
+        );
+        assert_eq!(
+            date("2023-05-01"),
+            new_york.local_date(ts("2023-05-01T04:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_start_of_day() {
+        let date = NaiveDate::from_ymd_opt(2023, 5, 2).expect("test");
+        let tokyo = ChatSettings {
+            timezone: Some(Tz::Asia__Tokyo),
+            ..Default::default()
+        };
+
+        let start = tokyo.start_of_day(date);
+        assert_eq!(
+            DateTime::parse_from_rfc3339("2023-05-01T15:00:00Z").expect("test"),
+            start
+        );
+        assert_eq!(date, tokyo.local_date(start));
+        assert_eq!(
+            date.pred_opt(),
+            Some(tokyo.local_date(start - Duration::seconds(1)))
+        );
+
+        // In Santiago, daylight saving time started at midnight on 2022-09-11.
+        let santiago = ChatSettings {
+            timezone: Some(Tz::America__Santiago),
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2022, 9, 11).expect("test");
+        assert_eq!(date, santiago.local_date(santiago.start_of_day(date)));
+    }
+}
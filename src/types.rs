@@ -1,6 +1,6 @@
 //! The definition of data structures used in multiple modules.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 
 /// A certain quantity of money.
 ///
@@ -59,6 +59,31 @@ pub struct SavedParticipant {
     pub amount: Option<Amount>,
 }
 
+/// An expense read from an imported file, with its date and state.
+#[derive(Clone, Debug)]
+pub struct ImportedExpense {
+    pub expense: ParsedExpense,
+    pub message_ts: DateTime<Utc>,
+    pub is_active: bool,
+}
+
+/// Which balance `/balance` should show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BalanceOptions {
+    Current,
+    /// The balance at the end of the given day.
+    AtDate(NaiveDate),
+    /// The balance right before the expense with the given ID was saved.
+    BeforeExpense(i64),
+}
+
+/// A point in the history of a chat, used to rebuild a past balance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryPoint {
+    At(DateTime<Utc>),
+    BeforeExpense(i64),
+}
+
 /// A debtor, a creditor and the amount of money that the debtor owes to the creditor.
 #[derive(Clone, Debug)]
 pub struct MoneyExchange {
@@ -67,6 +92,43 @@ pub struct MoneyExchange {
     pub amount: Amount,
 }
 
+/// How much money a participant is owed (if positive) or owes (if negative)
+/// in the current balance.
+#[derive(Clone, Debug)]
+pub struct NetPosition {
+    pub participant: String,
+    pub amount: Amount,
+}
+
+/// A group whose members share their finances, so that they settle their debts as a
+/// single party.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Household {
+    pub name: String,
+    pub members: Vec<String>,
+}
+
+/// A group with its participants and, recursively, the groups that are its members.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GroupTree {
+    pub name: String,
+    pub participants: Vec<String>,
+    pub subgroups: Vec<GroupTree>,
+}
+
+/// A rule of the chat that the money exchanges of a balance must respect.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SettlementConstraint {
+    /// The first participant only sends money to the second one or, if there are more
+    /// constraints of this kind for the same participant, to one of the others.
+    OnlyPays(String, String),
+    /// The two participants never send money to each other, in either direction.
+    Forbidden(String, String),
+    /// The participant only sends or receives money to settle their own debts or
+    /// credits, and never on behalf of others.
+    NoRouting(String),
+}
+
 /// Whether a participant to an expense is a creditor or a debtor.
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum ParticipantMode {
@@ -98,6 +160,50 @@ impl MoneyExchange {
     }
 }
 
+impl NetPosition {
+    pub fn new(participant: &str, amount: Amount) -> NetPosition {
+        NetPosition {
+            participant: participant.to_string(),
+            amount,
+        }
+    }
+}
+
+impl SettlementConstraint {
+    /// The names used for the kinds of constraint in the database.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SettlementConstraint::OnlyPays(..) => "only_pays",
+            SettlementConstraint::Forbidden(..) => "forbidden",
+            SettlementConstraint::NoRouting(_) => "no_routing",
+        }
+    }
+
+    /// Build a constraint from the name of its kind and its participants.
+    pub fn from_kind(
+        kind: &str,
+        participant: String,
+        other: Option<String>,
+    ) -> Option<SettlementConstraint> {
+        match (kind, other) {
+            ("only_pays", Some(other)) => Some(SettlementConstraint::OnlyPays(participant, other)),
+            ("forbidden", Some(other)) => Some(SettlementConstraint::Forbidden(participant, other)),
+            ("no_routing", None) => Some(SettlementConstraint::NoRouting(participant)),
+            _ => None,
+        }
+    }
+
+    /// The participant that the constraint is about and, if any, the other one.
+    pub fn participants(&self) -> (&str, Option<&str>) {
+        match self {
+            SettlementConstraint::OnlyPays(p1, p2) | SettlementConstraint::Forbidden(p1, p2) => {
+                (p1, Some(p2))
+            }
+            SettlementConstraint::NoRouting(p) => (p, None),
+        }
+    }
+}
+
 impl SavedExpense {
     pub fn new(
         id: i64,
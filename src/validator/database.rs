@@ -7,6 +7,7 @@
 use std::collections::HashSet;
 use std::sync::Arc;
 
+use super::suggestion::closest_names;
 use crate::database::Database;
 use crate::error::InputError;
 use tokio::sync::Mutex;
@@ -24,9 +25,13 @@ pub async fn validate_participants_exist<D: Database, T: AsRef<str>>(
 
         for participant in participants {
             if !registered_participants.contains(participant.as_ref()) {
-                return Err(
-                    InputError::unregistered_participant(participant.as_ref().to_string()).into(),
-                );
+                let suggestions =
+                    suggest_participants(participant.as_ref(), chat_id, database).await?;
+                return Err(InputError::unregistered_participant(
+                    participant.as_ref().to_string(),
+                    suggestions,
+                )
+                .into());
             }
         }
     }
@@ -46,10 +51,23 @@ pub async fn validate_participant_exists<D: Database>(
     {
         Ok(())
     } else {
-        Err(InputError::unregistered_participant(participant.to_string()).into())
+        let suggestions = suggest_participants(participant, chat_id, database).await?;
+        Err(InputError::unregistered_participant(participant.to_string(), suggestions).into())
     }
 }
 
+/// Find the registered participants and aliases that are closest to a name that is not registered.
+async fn suggest_participants<D: Database>(
+    participant: &str,
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<Vec<String>> {
+    let database = database.lock().await;
+    let mut candidates = database.get_participants(chat_id)?;
+    candidates.extend(database.get_aliases(chat_id)?.into_keys());
+    Ok(closest_names(participant, &candidates))
+}
+
 /// Check that all aliases provided by the user are not already registered as participants or aliases.
 pub async fn validate_aliases_do_not_exist<D: Database, T: AsRef<str>>(
     participant: &str,
@@ -126,6 +144,88 @@ pub async fn validate_group_exists<D: Database>(
     if group_exists {
         Ok(())
     } else {
-        Err(InputError::unregistered_group(group_name.to_string()).into())
+        let groups = database.lock().await.get_groups(chat_id)?;
+        let suggestions = closest_names(group_name, &groups);
+        Err(InputError::unregistered_group(group_name.to_string(), suggestions).into())
     }
 }
+
+/// Verify that, if the group is a household, its members are participants that are not
+/// in another household, since a participant can belong to one household only.
+pub async fn validate_household_members<D: Database>(
+    group_name: &str,
+    members: &[String],
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<()> {
+    let households = database.lock().await.get_households(chat_id)?;
+    if !households.iter().any(|h| h.name == group_name) {
+        return Ok(());
+    }
+
+    if members.iter().any(|m| m.starts_with('#')) {
+        return Err(InputError::subgroup_in_household(group_name.to_string()).into());
+    }
+    for household in households.iter().filter(|h| h.name != group_name) {
+        if let Some(member) = members.iter().find(|m| household.members.contains(m)) {
+            return Err(InputError::participant_in_other_household(
+                member.clone(),
+                household.name.clone(),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Verify that two participants that are merged are not in different households, since
+/// a participant can belong to one household only.
+pub async fn validate_same_household<D: Database>(
+    keep: &str,
+    drop: &str,
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<()> {
+    let households = database.lock().await.get_households(chat_id)?;
+    let household_of = |participant: &str| {
+        households
+            .iter()
+            .find(|h| h.members.iter().any(|m| m == participant))
+    };
+    match (household_of(keep), household_of(drop)) {
+        (Some(h1), Some(h2)) if h1.name != h2.name => Err(
+            InputError::participant_in_other_household(drop.to_string(), h1.name.clone()).into(),
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Verify that adding the given subgroups to a group does not make it contain itself,
+/// directly or through other subgroups.
+pub async fn validate_no_group_cycles<D: Database>(
+    group_name: &str,
+    subgroups: &[String],
+    chat_id: i64,
+    database: &Arc<Mutex<D>>,
+) -> anyhow::Result<()> {
+    for subgroup in subgroups {
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![subgroup.clone()];
+        while let Some(group) = to_visit.pop() {
+            if group == group_name {
+                return Err(
+                    InputError::group_cycle(group_name.to_string(), subgroup.clone()).into(),
+                );
+            }
+            if visited.insert(group.clone()) {
+                let members = database.lock().await.get_group_members(chat_id, &group)?;
+                to_visit.extend(
+                    members
+                        .iter()
+                        .filter_map(|m| m.strip_prefix('#').map(str::to_string)),
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -8,8 +8,9 @@ use std::sync::Arc;
 
 use tokio::sync::Mutex;
 
+use super::suggestion::closest_names;
 use crate::database::Database;
-use crate::error::InputError;
+use crate::error::{ExpenseProblem, InputError};
 use crate::types::{ParsedExpense, ParsedParticipant};
 
 /// Check that groups do not have custom amount set and that they exist.
@@ -19,14 +20,17 @@ pub async fn validate_groups<D: Database>(
     database: &Arc<Mutex<D>>,
 ) -> anyhow::Result<()> {
     let groups = database.lock().await.get_groups(chat_id)?;
-    let groups = groups.into_iter().collect::<HashSet<_>>();
+    let registered_groups = groups.iter().collect::<HashSet<_>>();
 
     for participant in &expense.participants {
         if participant.is_group() {
             if participant.amount.is_some() {
                 return Err(InputError::group_with_custom_amount().into());
-            } else if !groups.contains(&participant.name) {
-                return Err(InputError::unregistered_group(participant.name.clone()).into());
+            } else if !registered_groups.contains(&participant.name) {
+                let suggestions = closest_names(&participant.name, &groups);
+                return Err(
+                    InputError::unregistered_group(participant.name.clone(), suggestions).into(),
+                );
             }
         }
     }
@@ -58,7 +62,7 @@ pub fn validate_expense(expense: &ParsedExpense) -> anyhow::Result<()> {
 fn at_least_one_participant(expense: &ParsedExpense) -> Result<(), InputError> {
     if expense.participants.is_empty() {
         Err(InputError::invalid_expense(
-            "there are neither debtors nor creditors in this expense!".to_string(),
+            ExpenseProblem::NoParticipants,
             format!("{:#?}", expense),
         ))
     } else {
@@ -70,7 +74,7 @@ fn at_least_one_creditor(expense: &ParsedExpense) -> Result<(), InputError> {
     let no_creditors = !expense.participants.iter().any(|p| p.is_creditor());
     if no_creditors {
         Err(InputError::invalid_expense(
-            "there are no creditors in this expense!".to_string(),
+            ExpenseProblem::NoCreditors,
             format!("{:#?}", expense),
         ))
     } else {
@@ -96,13 +100,12 @@ fn total_fixed_credit_in_range(expense: &ParsedExpense) -> Result<(), InputError
 
     if total_credit > amount {
         Err(InputError::invalid_expense(
-            "the money that people paid are more than the total expense amount!".to_string(),
+            ExpenseProblem::CreditAboveAmount,
             format!("{:#?}", expense),
         ))
     } else if total_credit < amount && only_fixed_creditors {
         Err(InputError::invalid_expense(
-            "all creditors paid a fixed amount and the total is less than the expense amount!"
-                .to_string(),
+            ExpenseProblem::FixedCreditBelowAmount,
             format!("{:#?}", expense),
         ))
     } else {
@@ -124,13 +127,12 @@ fn total_fixed_debt_in_range(expense: &ParsedExpense) -> Result<(), InputError>
 
     if total_debt > amount {
         Err(InputError::invalid_expense(
-            "the money owed by people are more than the total expense amount!".to_string(),
+            ExpenseProblem::DebtAboveAmount,
             format!("{:#?}", expense),
         ))
     } else if total_debt < amount && only_fixed_debtors {
         Err(InputError::invalid_expense(
-            "all debtors owe a fixed amount and the total is less than the expense amount!"
-                .to_string(),
+            ExpenseProblem::FixedDebtBelowAmount,
             format!("{:#?}", expense),
         ))
     } else {
@@ -156,12 +158,12 @@ fn no_duplicate_custom_amounts(expense: &ParsedExpense) -> Result<(), InputError
 
     if creditors_have_multiple_custom_amount {
         Err(InputError::invalid_expense(
-            "there are creditors appearing multiple times with custom amounts!".to_string(),
+            ExpenseProblem::DuplicateCreditorAmounts,
             format!("{:#?}", expense),
         ))
     } else if debtors_have_multiple_custom_amount {
         Err(InputError::invalid_expense(
-            "there are debtors appearing multiple times with custom amounts!".to_string(),
+            ExpenseProblem::DuplicateDebtorAmounts,
             format!("{:#?}", expense),
         ))
     } else {
@@ -5,11 +5,13 @@
 
 mod database;
 mod expense;
+mod suggestion;
 
 use crate::error::InputError;
 pub use database::{
     validate_aliases_do_not_exist, validate_aliases_exist, validate_group_exists,
-    validate_participant_exists, validate_participants_exist,
+    validate_household_members, validate_no_group_cycles, validate_participant_exists,
+    validate_participants_exist, validate_same_household,
 };
 pub use expense::{validate_expense, validate_groups};
 
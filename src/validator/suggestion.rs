@@ -0,0 +1,84 @@
+//! Suggestions of registered names for names that are not registered,
+//! which are often just typos.
+
+/// The maximum number of suggestions for a name.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Find the candidates that are closest to `name` by edit distance, closest first.
+///
+/// Only candidates that differ by a few characters are returned, since names
+/// that are too different are not likely to be what the user meant.
+pub fn closest_names<T: AsRef<str>>(name: &str, candidates: &[T]) -> Vec<String> {
+    let name = name.to_lowercase();
+    let max_distance = match name.chars().count() {
+        0..=3 => 1,
+        4..=7 => 2,
+        _ => 3,
+    };
+
+    let mut closest: Vec<_> = candidates
+        .iter()
+        .map(|c| c.as_ref())
+        .map(|c| (edit_distance(&name, c), c))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    closest.sort_unstable();
+    closest.dedup();
+
+    closest
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, c)| c.to_string())
+        .collect()
+}
+
+/// The Levenshtein distance between two strings, counting characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<_> = b.chars().collect();
+    let mut previous: Vec<_> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(0, edit_distance("marco", "marco"));
+        assert_eq!(1, edit_distance("marco", "marcco"));
+        assert_eq!(2, edit_distance("marco", "mark"));
+        assert_eq!(2, edit_distance("marco", "amrco"));
+        assert_eq!(5, edit_distance("", "marco"));
+        assert_eq!(1, edit_distance("niccolò", "niccolo"));
+    }
+
+    #[test]
+    fn test_closest_names() {
+        let candidates = ["marco", "maria", "mario", "luca", "lucia", "giovanni"];
+
+        assert_eq!(
+            vec!["marco".to_string(), "mario".to_string()],
+            closest_names("marcco", &candidates)
+        );
+        assert_eq!(
+            vec!["luca".to_string(), "lucia".to_string()],
+            closest_names("lcua", &candidates)
+        );
+        assert_eq!(
+            vec!["giovanni".to_string()],
+            closest_names("Giovani", &candidates)
+        );
+        assert!(closest_names("paolo", &candidates).is_empty());
+    }
+}